@@ -0,0 +1,44 @@
+/* C interface to dirty-tracker, available when built with the "ffi"
+ * feature. */
+
+#ifndef DIRTY_TRACKER_H
+#define DIRTY_TRACKER_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The tree is clean. */
+#define DIRTY_TRACKER_CLEAN 0
+/* The tree has changed. */
+#define DIRTY_TRACKER_DIRTY 1
+/* It is not known whether the tree has changed. */
+#define DIRTY_TRACKER_UNKNOWN 2
+
+typedef struct DirtyTracker DirtyTracker;
+
+/* Create a tracker for the tree below path. Returns NULL on failure. */
+DirtyTracker *dirty_tracker_new(const char *path);
+
+/* Returns one of DIRTY_TRACKER_CLEAN, DIRTY_TRACKER_DIRTY or
+ * DIRTY_TRACKER_UNKNOWN. */
+int dirty_tracker_state(DirtyTracker *tracker);
+
+/* Returns the dirty paths as a NULL-terminated array, or NULL if the state
+ * is unknown. Free the array with dirty_tracker_paths_free(). */
+char **dirty_tracker_paths(DirtyTracker *tracker);
+
+/* Free an array returned by dirty_tracker_paths(). */
+void dirty_tracker_paths_free(char **paths);
+
+/* Mark the tree as clean. */
+void dirty_tracker_mark_clean(DirtyTracker *tracker);
+
+/* Free a tracker. */
+void dirty_tracker_free(DirtyTracker *tracker);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DIRTY_TRACKER_H */
@@ -0,0 +1,16 @@
+const { DirtyTracker } = require('./dirty-tracker.node');
+
+/**
+ * Returns an async iterator over the changes to the tree, as objects with
+ * `path` and `kind` ("created", "modified", "removed", "renamed-from" or
+ * "renamed-to") properties.
+ */
+DirtyTracker.prototype.changes = async function* () {
+  for (;;) {
+    yield await this.nextChange();
+  }
+};
+
+DirtyTracker.prototype[Symbol.asyncIterator] = DirtyTracker.prototype.changes;
+
+module.exports = { DirtyTracker };
@@ -0,0 +1,159 @@
+//! Node.js bindings for dirty-tracker.
+//!
+//! `index.js` wraps these in an async iterator over the changes to the
+//! tree.
+
+use dirty_tracker::{ChangeKind, DirtyNotification, DirtyTrackerError, State};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long `nextChange()` holds on to the tracker while waiting for events,
+/// before giving other calls a chance.
+const POLL: Duration = Duration::from_millis(100);
+
+struct Shared {
+    tracker: dirty_tracker::DirtyTracker,
+    changes: Receiver<DirtyNotification>,
+}
+
+fn to_napi_error(e: impl std::fmt::Display) -> Error {
+    Error::from_reason(e.to_string())
+}
+
+/// A single change to a path.
+#[napi(object)]
+pub struct Change {
+    pub path: String,
+    /// One of "created", "modified", "removed", "renamed-from" or
+    /// "renamed-to".
+    pub kind: String,
+}
+
+impl From<DirtyNotification> for Change {
+    fn from(notification: DirtyNotification) -> Self {
+        Change {
+            path: notification.path.to_string_lossy().into_owned(),
+            kind: match notification.kind {
+                ChangeKind::Created => "created",
+                ChangeKind::Modified => "modified",
+                ChangeKind::Removed => "removed",
+                ChangeKind::RenamedFrom => "renamed-from",
+                ChangeKind::RenamedTo => "renamed-to",
+            }
+            .to_string(),
+        }
+    }
+}
+
+/// Tracks the files that change below a directory.
+#[napi]
+pub struct DirtyTracker {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[napi]
+impl DirtyTracker {
+    #[napi(constructor)]
+    pub fn new(path: String) -> Result<Self> {
+        // The tree snapshot is needed for rescan().
+        let mut tracker = dirty_tracker::DirtyTracker::builder(std::path::Path::new(&path))
+            .tree_snapshot(true)
+            .build()
+            .map_err(to_napi_error)?;
+        let changes = tracker.subscribe();
+        Ok(DirtyTracker {
+            shared: Arc::new(Mutex::new(Shared { tracker, changes })),
+        })
+    }
+
+    /// Returns "clean", "dirty" or "unknown".
+    #[napi]
+    pub fn state(&self) -> &'static str {
+        match self.shared.lock().unwrap().tracker.state() {
+            State::Clean => "clean",
+            State::Dirty => "dirty",
+            State::Unknown => "unknown",
+        }
+    }
+
+    /// Returns the dirty paths, sorted, or null if the state is unknown.
+    #[napi]
+    pub fn paths(&self) -> Option<Vec<String>> {
+        let mut shared = self.shared.lock().unwrap();
+        let mut paths = shared
+            .tracker
+            .paths()?
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        paths.sort();
+        Some(paths)
+    }
+
+    #[napi]
+    pub fn mark_clean(&self) {
+        self.shared.lock().unwrap().tracker.mark_clean();
+    }
+
+    /// Rebuild the set of dirty paths by scanning the tree, e.g. to recover
+    /// from the unknown state.
+    #[napi]
+    pub fn rescan(&self) -> Result<()> {
+        self.shared
+            .lock()
+            .unwrap()
+            .tracker
+            .rescan()
+            .map_err(to_napi_error)
+    }
+
+    /// Returns a promise for the next change.
+    ///
+    /// The promise is rejected if the state becomes unknown.
+    #[napi(ts_return_type = "Promise<Change>")]
+    pub fn next_change(&self) -> AsyncTask<NextChange> {
+        AsyncTask::new(NextChange {
+            shared: self.shared.clone(),
+        })
+    }
+}
+
+/// Waits for the next change on the libuv thread pool.
+pub struct NextChange {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Task for NextChange {
+    type Output = DirtyNotification;
+    type JsValue = Change;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        loop {
+            let mut shared = self.shared.lock().unwrap();
+            let Shared { tracker, changes } = &mut *shared;
+            let mut next = changes.try_recv().ok();
+            if next.is_none() {
+                match tracker.wait_until(
+                    |_| {
+                        next = changes.try_recv().ok();
+                        next.is_some()
+                    },
+                    POLL,
+                ) {
+                    Ok(_) | Err(DirtyTrackerError::Timeout(_)) => {}
+                    Err(e) => return Err(to_napi_error(e)),
+                }
+            }
+            if let Some(next) = next {
+                return Ok(next);
+            }
+        }
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.into())
+    }
+}
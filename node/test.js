@@ -0,0 +1,31 @@
+const assert = require('assert');
+const fs = require('fs');
+const os = require('os');
+const path = require('path');
+const { DirtyTracker } = require('.');
+
+(async () => {
+  const dir = fs.mkdtempSync(path.join(os.tmpdir(), 'dirty-tracker-'));
+  try {
+    const tracker = new DirtyTracker(dir);
+    assert.strictEqual(tracker.state(), 'clean');
+
+    const file = path.join(dir, 'file');
+    fs.writeFileSync(file, 'hello');
+    assert.strictEqual(tracker.state(), 'dirty');
+    assert.deepStrictEqual(tracker.paths(), [file]);
+
+    const changes = tracker.changes();
+    assert.deepStrictEqual((await changes.next()).value, { path: file, kind: 'created' });
+
+    tracker.markClean();
+    assert.strictEqual(tracker.state(), 'clean');
+    assert.throws(() => new DirtyTracker(path.join(dir, 'missing')));
+  } finally {
+    fs.rmSync(dir, { recursive: true });
+  }
+  console.log('ok');
+})().catch((e) => {
+  console.error(e);
+  process.exit(1);
+});
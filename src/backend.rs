@@ -0,0 +1,154 @@
+//! The source of file system events for a tracker.
+
+use crate::{FlushStrategy, NoFlush, SentinelFlush};
+use notify::{RecursiveMode, WatcherKind};
+use std::path::Path;
+
+/// The sending side of the channel a backend delivers its events on.
+pub type EventSender = std::sync::mpsc::Sender<notify::Result<notify::Event>>;
+
+/// A source of file system events, such as one of the `notify` watchers.
+///
+/// This is implemented for every `notify::Watcher`, so alternative notify
+/// backends can be used through `DirtyTrackerBuilder::build_with_backend()`.
+/// Other event sources can implement it directly; they are expected to
+/// deliver their events on the `EventSender` they were created with.
+pub trait WatcherBackend: Send {
+    /// Start watching `path`.
+    fn add_watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()>;
+
+    /// Stop watching `path`.
+    fn remove_watch(&mut self, path: &Path) -> notify::Result<()>;
+
+    /// Returns the kind of backend.
+    fn backend_kind(&self) -> WatcherKind;
+
+    /// Returns the flush strategy that suits this backend best, used unless
+    /// one is set on the builder.
+    ///
+    /// The default is `SentinelFlush`, which needs events to be delivered
+    /// in order.
+    fn default_flush(&self) -> Box<dyn FlushStrategy> {
+        Box::new(SentinelFlush::new())
+    }
+}
+
+impl<W: notify::Watcher + Send + 'static> WatcherBackend for W {
+    fn add_watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        notify::Watcher::watch(self, path, recursive_mode)
+    }
+
+    fn remove_watch(&mut self, path: &Path) -> notify::Result<()> {
+        notify::Watcher::unwatch(self, path)
+    }
+
+    fn backend_kind(&self) -> WatcherKind {
+        W::kind()
+    }
+
+    fn default_flush(&self) -> Box<dyn FlushStrategy> {
+        match W::kind() {
+            // The poll watcher only notices changes that outlive a poll
+            // interval, so it would never see a sentinel file. Injected
+            // events from the null watcher are delivered synchronously.
+            WatcherKind::PollWatcher | WatcherKind::NullWatcher => Box::new(NoFlush),
+            _ => Box::new(SentinelFlush::new()),
+        }
+    }
+}
+
+/// Returns the number of watches a backend of the given kind needs for the
+/// tree below `root`.
+pub(crate) fn estimate_watches(kind: WatcherKind, root: &Path) -> std::io::Result<usize> {
+    match kind {
+        // One watch for every directory.
+        WatcherKind::Inotify => {
+            let mut count = 1;
+            crate::snapshot::walk(root, &mut |_, metadata| {
+                if metadata.is_dir() {
+                    count += 1;
+                }
+            })?;
+            Ok(count)
+        }
+        // One file descriptor for every entry.
+        WatcherKind::Kqueue => {
+            let mut count = 1;
+            crate::snapshot::walk(root, &mut |_, _| count += 1)?;
+            Ok(count)
+        }
+        // The poll and null watchers don't use any watches.
+        WatcherKind::PollWatcher | WatcherKind::NullWatcher => Ok(0),
+        // Watches are per tree.
+        _ => Ok(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DirtyTracker, State};
+    use std::time::Duration;
+
+    #[test]
+    fn test_poll_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .poll_interval(Duration::from_millis(10))
+            .build_polling()
+            .unwrap();
+        assert_eq!(tracker.watcher_kind(), WatcherKind::PollWatcher);
+        assert_eq!(tracker.state(), State::Clean);
+
+        // The poll watcher only compares mtimes at a granularity of seconds,
+        // so add a file rather than modifying one.
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+        tracker
+            .wait_for_path(&file, Duration::from_secs(10))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_poll_compare_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .poll_interval(Duration::from_millis(10))
+            .poll_compare_contents(true)
+            .build_polling()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        std::fs::write(&file, b"world").unwrap();
+        tracker
+            .wait_for_path(&file, Duration::from_secs(10))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_estimate_watches() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::create_dir(dir.path().join("c")).unwrap();
+        std::fs::write(dir.path().join("a/file"), b"hello").unwrap();
+        assert_eq!(
+            estimate_watches(WatcherKind::Inotify, dir.path()).unwrap(),
+            4
+        );
+        assert_eq!(
+            estimate_watches(WatcherKind::Kqueue, dir.path()).unwrap(),
+            5
+        );
+        assert_eq!(
+            estimate_watches(WatcherKind::Fsevent, dir.path()).unwrap(),
+            1
+        );
+        assert_eq!(
+            estimate_watches(WatcherKind::PollWatcher, dir.path()).unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! A long-lived tracker that answers queries over a Unix socket, and
+//! optionally on D-Bus.
+//!
+//! This lets short-lived scripts share a single watcher, rather than each
+//! scanning the tree themselves. Clients send one command per line, see
+//! `USAGE` for the protocol.
+
+use super::{display, POLL};
+use dirty_tracker::{DirtyTracker, DirtyTrackerError, State};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Returns the name `state` is reported as.
+pub(crate) fn state_name(state: State) -> &'static str {
+    match state {
+        State::Clean => "clean",
+        State::Dirty => "dirty",
+        State::Unknown => "unknown",
+    }
+}
+
+/// Returns the response to a single `request`.
+fn respond(root: &Path, tracker: &mut DirtyTracker, request: &str) -> String {
+    match request {
+        "state" => format!("{}\n", state_name(tracker.state())),
+        "paths" => match tracker.try_paths() {
+            Ok(paths) => {
+                let mut paths = paths.iter().collect::<Vec<_>>();
+                paths.sort();
+                let mut response = String::new();
+                for path in paths {
+                    response.push_str(&format!("{}\n", display(root, path)));
+                }
+                response.push('\n');
+                response
+            }
+            Err(reason) => format!("error: state unknown ({})\n", reason),
+        },
+        "mark-clean" => {
+            tracker.mark_clean();
+            "ok\n".to_string()
+        }
+        other => format!("error: unknown command: {}\n", other),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn run(
+    dir: &Path,
+    socket: Option<&Path>,
+    dbus: bool,
+    interrupted: Arc<AtomicBool>,
+) -> Result<(), DirtyTrackerError> {
+    use std::os::unix::net::UnixListener;
+    use std::sync::Mutex;
+
+    let tracker = DirtyTracker::builder(dir).tree_snapshot(true).build()?;
+    let tracker = Arc::new(Mutex::new(tracker));
+    #[cfg(feature = "dbus")]
+    let mut service = if dbus {
+        Some(
+            super::dbus::Service::new(dir, tracker.clone())
+                .map_err(|e| DirtyTrackerError::Io(std::io::Error::other(e)))?,
+        )
+    } else {
+        None
+    };
+    #[cfg(not(feature = "dbus"))]
+    let _ = dbus;
+    let listener = match socket {
+        Some(socket) => Some(UnixListener::bind(socket).map_err(DirtyTrackerError::Io)?),
+        None => None,
+    };
+    let result = (|| {
+        if let Some(listener) = listener.as_ref() {
+            listener
+                .set_nonblocking(true)
+                .map_err(DirtyTrackerError::Io)?;
+        }
+        while !interrupted.load(Ordering::SeqCst) {
+            if let Some(listener) = listener.as_ref() {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let root = dir.to_path_buf();
+                        let tracker = tracker.clone();
+                        std::thread::spawn(move || {
+                            if let Err(e) = serve(&root, &tracker, stream) {
+                                eprintln!("dirty-tracker: client error: {}", e);
+                            }
+                        });
+                        continue;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(DirtyTrackerError::Io(e)),
+                }
+            }
+            // Keep processing events while idle, so they don't pile up, and
+            // recover from dropped events as watch does.
+            let mut guard = tracker.lock().unwrap();
+            if let (State::Unknown, Some(reason)) = guard.state_detail() {
+                eprintln!("State unknown ({}), rescanning", reason);
+                guard.rescan()?;
+            }
+            #[cfg(feature = "dbus")]
+            let state = guard.state();
+            drop(guard);
+            #[cfg(feature = "dbus")]
+            if let Some(service) = service.as_mut() {
+                service
+                    .update(state)
+                    .map_err(|e| DirtyTrackerError::Io(std::io::Error::other(e)))?;
+            }
+            std::thread::sleep(POLL);
+        }
+        Ok(())
+    })();
+    if let Some(socket) = socket {
+        let _ = std::fs::remove_file(socket);
+    }
+    result
+}
+
+#[cfg(not(unix))]
+pub(crate) fn run(
+    _dir: &Path,
+    _socket: Option<&Path>,
+    _dbus: bool,
+    _interrupted: Arc<AtomicBool>,
+) -> Result<(), DirtyTrackerError> {
+    Err(DirtyTrackerError::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the daemon needs Unix sockets",
+    )))
+}
+
+/// Answer the requests of a single client, until it disconnects.
+#[cfg(unix)]
+fn serve(
+    root: &Path,
+    tracker: &std::sync::Mutex<DirtyTracker>,
+    stream: std::os::unix::net::UnixStream,
+) -> std::io::Result<()> {
+    use std::io::{BufRead, Write};
+
+    // Accepted sockets inherit non-blocking mode from the listener on some
+    // platforms.
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    for line in std::io::BufReader::new(stream).lines() {
+        let response = respond(root, &mut tracker.lock().unwrap(), line?.trim());
+        writer.write_all(response.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_serve() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = Mutex::new(DirtyTracker::new(dir.path()).unwrap());
+        std::fs::write(dir.path().join("b"), b"b").unwrap();
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client
+            .write_all(b"state\npaths\nmark-clean\nstate\nfrobnicate\n")
+            .unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        serve(dir.path(), &tracker, server).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(
+            response,
+            "dirty\na\nb\n\nok\nclean\nerror: unknown command: frobnicate\n"
+        );
+    }
+}
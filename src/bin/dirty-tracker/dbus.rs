@@ -0,0 +1,94 @@
+//! Export of the daemon's tracker on the D-Bus session bus.
+//!
+//! The tracker is served as `org.dirtytracker`, at `/org/dirtytracker`, with
+//! the `org.dirtytracker.Tracker` interface:
+//!
+//! * `State`: a property that is one of `clean`, `dirty` or `unknown`, and
+//!   that emits `PropertiesChanged` when it changes
+//! * `Paths()`: returns the dirty paths, relative to the root
+//! * `MarkClean()`: marks the tree as clean
+
+use super::daemon::state_name;
+use super::display;
+use dirty_tracker::{DirtyTracker, State};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The well-known name the service is registered under.
+pub(crate) const NAME: &str = "org.dirtytracker";
+
+/// The path of the tracker object.
+pub(crate) const PATH: &str = "/org/dirtytracker";
+
+struct Tracker {
+    root: PathBuf,
+    tracker: Arc<Mutex<DirtyTracker>>,
+}
+
+#[zbus::interface(name = "org.dirtytracker.Tracker")]
+impl Tracker {
+    #[zbus(property)]
+    fn state(&self) -> String {
+        state_name(self.tracker.lock().unwrap().state()).to_string()
+    }
+
+    fn paths(&self) -> zbus::fdo::Result<Vec<String>> {
+        let mut tracker = self.tracker.lock().unwrap();
+        let paths = tracker
+            .try_paths()
+            .map_err(|reason| zbus::fdo::Error::Failed(format!("state unknown ({})", reason)))?;
+        let mut paths = paths
+            .iter()
+            .map(|path| display(&self.root, path).to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn mark_clean(&self) {
+        self.tracker.lock().unwrap().mark_clean();
+    }
+}
+
+/// A connection to the session bus that serves a tracker.
+pub(crate) struct Service {
+    connection: zbus::blocking::Connection,
+    last: Option<State>,
+}
+
+impl Service {
+    pub(crate) fn new(root: &Path, tracker: Arc<Mutex<DirtyTracker>>) -> zbus::Result<Self> {
+        let connection = zbus::blocking::connection::Builder::session()?
+            .name(NAME)?
+            .serve_at(
+                PATH,
+                Tracker {
+                    root: root.to_path_buf(),
+                    tracker,
+                },
+            )?
+            .build()?;
+        Ok(Service {
+            connection,
+            last: None,
+        })
+    }
+
+    /// Signal a change of the `State` property, if `state` differs from
+    /// the one last seen.
+    ///
+    /// The property is read back from the tracker, so this must not be
+    /// called while the tracker is locked.
+    pub(crate) fn update(&mut self, state: State) -> zbus::Result<()> {
+        if self.last == Some(state) {
+            return Ok(());
+        }
+        self.last = Some(state);
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, Tracker>(PATH)?;
+        zbus::block_on(iface.get().state_changed(iface.signal_emitter()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,445 @@
+//! Command-line interface to the dirty tracker.
+
+use dirty_tracker::{ChangeKind, DirtyTracker, DirtyTrackerError};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+
+const USAGE: &str = "\
+Usage: dirty-tracker watch [--format <format>] <dir>
+       dirty-tracker exec [--format <format>] [-o <file>] <dir> -- <command>...
+       dirty-tracker daemon [--socket <path>] [--dbus] <dir>
+
+Commands:
+  watch <dir>    Print paths below <dir> as they become dirty, and a summary
+                 on interrupt
+  exec <dir>     Run <command>, and print the paths below <dir> that changed
+                 while it ran; exits with the command's exit code
+  daemon <dir>   Keep watching <dir>, and answer queries on a Unix socket
+                 or D-Bus; see below
+
+Options:
+  -o, --output <file>    Write the changed paths to <file> rather than
+                         standard output
+  --format <format>      Either text (the default), which lists dirty
+                         paths, or ndjson, which prints a JSON object with
+                         the path, kind and timestamp of every change
+  --socket <path>        The Unix socket the daemon listens on
+  --dbus                 Export the daemon's tracker on the D-Bus session
+                         bus, as org.dirtytracker (needs the dbus feature)
+
+The daemon reads one command per line, and answers each:
+  state          One of clean, dirty or unknown
+  paths          The dirty paths, one per line, followed by an empty line
+  mark-clean     Marks the tree as clean, and answers ok
+Failures are reported as a single line starting with \"error:\".";
+
+/// How often to check whether we've been interrupted.
+const POLL: Duration = Duration::from_millis(200);
+
+/// How changes are reported.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// One dirty path per line.
+    #[default]
+    Text,
+    /// One JSON object per change.
+    Ndjson,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "ndjson" => Ok(Format::Ndjson),
+            other => Err(format!("unknown format: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Watch {
+        dir: PathBuf,
+        format: Format,
+    },
+    Exec {
+        dir: PathBuf,
+        output: Option<PathBuf>,
+        format: Format,
+        command: Vec<String>,
+    },
+    Daemon {
+        dir: PathBuf,
+        socket: Option<PathBuf>,
+        dbus: bool,
+    },
+    Help,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let name = match args.next() {
+        Some(name) => name,
+        None => return Err("missing command".to_string()),
+    };
+    match name.as_str() {
+        "-h" | "--help" | "help" => return Ok(Command::Help),
+        "watch" | "exec" | "daemon" => {}
+        other => return Err(format!("unknown command: {}", other)),
+    }
+    let exec = name == "exec";
+    let daemon = name == "daemon";
+    let mut dir: Option<PathBuf> = None;
+    let mut output = None;
+    let mut socket = None;
+    let mut dbus = false;
+    let mut format = Format::default();
+    let mut command = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" if exec => {
+                output = Some(args.next().ok_or("missing output file")?.into());
+            }
+            "--socket" if daemon => {
+                socket = Some(args.next().ok_or("missing socket path")?.into());
+            }
+            "--dbus" if daemon => {
+                if !cfg!(feature = "dbus") {
+                    return Err("built without D-Bus support".to_string());
+                }
+                dbus = true;
+            }
+            "--format" if !daemon => {
+                format = args.next().ok_or("missing format")?.parse()?;
+            }
+            "--" if exec && dir.is_some() => {
+                command.extend(args.by_ref());
+            }
+            _ if !daemon && arg.starts_with("--format=") => {
+                format = arg["--format=".len()..].parse()?;
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option: {}", arg));
+            }
+            _ if dir.is_none() => dir = Some(arg.into()),
+            _ if exec => {
+                command.push(arg);
+                command.extend(args.by_ref());
+            }
+            _ => return Err(format!("unexpected argument: {}", arg)),
+        }
+    }
+    let dir = dir.ok_or("missing directory")?;
+    if daemon {
+        if socket.is_none() && !dbus {
+            return Err("missing --socket".to_string());
+        }
+        return Ok(Command::Daemon { dir, socket, dbus });
+    }
+    if !exec {
+        return Ok(Command::Watch { dir, format });
+    }
+    if command.is_empty() {
+        return Err("missing command".to_string());
+    }
+    Ok(Command::Exec {
+        dir,
+        output,
+        format,
+        command,
+    })
+}
+
+/// Returns `path` relative to `root`, for display.
+fn display<'a>(root: &Path, path: &'a Path) -> std::path::Display<'a> {
+    path.strip_prefix(root).unwrap_or(path).display()
+}
+
+/// Returns `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Returns a single line of NDJSON describing a change to `path`.
+///
+/// The timestamp is the time the change was processed, in seconds since
+/// the Unix epoch.
+fn json_line(root: &Path, path: &Path, kind: ChangeKind, time: SystemTime) -> String {
+    let kind = match kind {
+        ChangeKind::Created => "created",
+        ChangeKind::Modified => "modified",
+        ChangeKind::Removed => "removed",
+        ChangeKind::RenamedFrom => "renamed-from",
+        ChangeKind::RenamedTo => "renamed-to",
+    };
+    let timestamp = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    format!(
+        "{{\"path\":{},\"kind\":\"{}\",\"timestamp\":{:.3}}}",
+        json_string(&display(root, path).to_string()),
+        kind,
+        timestamp
+    )
+}
+
+/// Returns a flag that is set once the process is interrupted.
+fn interrupted() -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+            .expect("unable to install interrupt handler");
+    }
+    interrupted
+}
+
+fn watch(dir: &Path, format: Format) -> Result<(), DirtyTrackerError> {
+    let interrupted = interrupted();
+    // The tree snapshot is needed to recover by rescanning.
+    let mut tracker = DirtyTracker::builder(dir).tree_snapshot(true).build()?;
+    if format == Format::Ndjson {
+        let root = dir.to_path_buf();
+        tracker.on_change(move |path, kind| {
+            println!("{}", json_line(&root, path, kind, SystemTime::now()))
+        });
+    }
+    let mut shown = HashSet::new();
+    while !interrupted.load(Ordering::SeqCst) {
+        match tracker.wait_until(|_| interrupted.load(Ordering::SeqCst), POLL) {
+            Ok(_) | Err(DirtyTrackerError::Timeout(_)) => {}
+            Err(DirtyTrackerError::Unknown(reason)) => {
+                eprintln!("State unknown ({}), rescanning", reason);
+                tracker.rescan()?;
+            }
+            Err(e) => return Err(e),
+        }
+        if format != Format::Text {
+            continue;
+        }
+        let mut new = tracker
+            .try_paths()?
+            .iter()
+            .filter(|path| !shown.contains(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+        new.sort();
+        for path in new {
+            println!("{}", display(dir, &path));
+            shown.insert(path);
+        }
+    }
+
+    let mut paths = tracker.try_paths()?.iter().collect::<Vec<_>>();
+    paths.sort();
+    eprintln!("{} dirty path(s):", paths.len());
+    for path in paths {
+        eprintln!("  {}", display(dir, path));
+    }
+    Ok(())
+}
+
+/// Returns the exit code to report for a command that exited with
+/// `status`.
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    // Follow the shell convention for commands killed by a signal.
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    1
+}
+
+fn exec(
+    dir: &Path,
+    output: Option<&Path>,
+    format: Format,
+    command: &[String],
+) -> Result<i32, DirtyTrackerError> {
+    let mut child = std::process::Command::new(&command[0]);
+    child.args(&command[1..]);
+    let (status, listing) = match format {
+        Format::Text => {
+            let (status, paths) = dirty_tracker::track_command(dir, &mut child)?;
+            let mut paths = paths.iter().collect::<Vec<_>>();
+            paths.sort();
+            let mut listing = String::new();
+            for path in paths {
+                listing.push_str(&format!("{}\n", display(dir, path)));
+            }
+            (status, listing)
+        }
+        Format::Ndjson => {
+            let mut tracker = DirtyTracker::builder(dir).without_baseline().build()?;
+            let listing = Arc::new(Mutex::new(String::new()));
+            {
+                let root = dir.to_path_buf();
+                let listing = listing.clone();
+                tracker.on_change(move |path, kind| {
+                    let line = json_line(&root, path, kind, SystemTime::now());
+                    let mut listing = listing.lock().unwrap();
+                    listing.push_str(&line);
+                    listing.push('\n');
+                });
+            }
+            let status = child.status().map_err(DirtyTrackerError::Io)?;
+            tracker.try_paths()?;
+            let listing = listing.lock().unwrap().clone();
+            (status, listing)
+        }
+    };
+    match output {
+        Some(output) => std::fs::write(output, listing).map_err(DirtyTrackerError::Io)?,
+        None => print!("{}", listing),
+    }
+    Ok(exit_code(status))
+}
+
+fn main() {
+    let command = match parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("dirty-tracker: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let result = match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(0)
+        }
+        Command::Watch { dir, format } => watch(&dir, format).map(|()| 0),
+        Command::Exec {
+            dir,
+            output,
+            format,
+            command,
+        } => exec(&dir, output.as_deref(), format, &command),
+        Command::Daemon { dir, socket, dbus } => {
+            daemon::run(&dir, socket.as_deref(), dbus, interrupted()).map(|()| 0)
+        }
+    };
+    match result {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("dirty-tracker: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse(&["watch", "/tmp"]),
+            Ok(Command::Watch {
+                dir: "/tmp".into(),
+                format: Format::Text,
+            })
+        );
+        assert_eq!(
+            parse(&["watch", "--format", "ndjson", "/tmp"]),
+            Ok(Command::Watch {
+                dir: "/tmp".into(),
+                format: Format::Ndjson,
+            })
+        );
+        assert!(parse(&["watch", "--format=yaml", "/tmp"]).is_err());
+        assert!(parse(&["watch", "-o", "out", "/tmp"]).is_err());
+        assert_eq!(parse(&["--help"]), Ok(Command::Help));
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["watch"]).is_err());
+        assert!(parse(&["watch", "a", "b"]).is_err());
+        assert!(parse(&["frobnicate"]).is_err());
+
+        assert_eq!(
+            parse(&["exec", "/tmp", "-o", "out", "--", "touch", "-c", "f"]),
+            Ok(Command::Exec {
+                dir: "/tmp".into(),
+                output: Some("out".into()),
+                format: Format::Text,
+                command: vec!["touch".into(), "-c".into(), "f".into()],
+            })
+        );
+        assert_eq!(
+            parse(&["exec", "--format=ndjson", "/tmp", "true"]),
+            Ok(Command::Exec {
+                dir: "/tmp".into(),
+                output: None,
+                format: Format::Ndjson,
+                command: vec!["true".into()],
+            })
+        );
+        assert!(parse(&["exec", "/tmp"]).is_err());
+        assert!(parse(&["exec", "/tmp", "--"]).is_err());
+
+        assert_eq!(
+            parse(&["daemon", "--socket", "sock", "/tmp"]),
+            Ok(Command::Daemon {
+                dir: "/tmp".into(),
+                socket: Some("sock".into()),
+                dbus: false,
+            })
+        );
+        assert!(parse(&["daemon", "/tmp"]).is_err());
+        assert!(parse(&["daemon", "--format", "ndjson", "/tmp"]).is_err());
+        #[cfg(feature = "dbus")]
+        assert_eq!(
+            parse(&["daemon", "--dbus", "/tmp"]),
+            Ok(Command::Daemon {
+                dir: "/tmp".into(),
+                socket: None,
+                dbus: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_json_line() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1500);
+        assert_eq!(
+            json_line(
+                Path::new("/root"),
+                Path::new("/root/a \"b\"\n"),
+                ChangeKind::Created,
+                time
+            ),
+            r#"{"path":"a \"b\"\n","kind":"created","timestamp":1.500}"#
+        );
+        assert_eq!(json_string("\u{1}\\"), r#""\u0001\\""#);
+    }
+}
@@ -0,0 +1,77 @@
+//! Coalescing of repeated change notifications.
+//!
+//! A file that is being written to can produce a stream of modifications
+//! in quick succession. Within a window, only the first notification of a
+//! kind of change for a path is sent to callbacks and subscribers; the
+//! changes are still recorded in the dirty set.
+
+use crate::ChangeKind;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+    window: Duration,
+    /// The changes notified within the window, and when.
+    recent: HashMap<PathBuf, (ChangeKind, Instant)>,
+    pruned: Option<Instant>,
+}
+
+impl Coalescer {
+    pub(crate) fn new(window: Duration) -> Self {
+        Coalescer {
+            window,
+            recent: HashMap::new(),
+            pruned: None,
+        }
+    }
+
+    /// Returns whether a notification should be sent about the change of
+    /// `kind` to `path` at `now`, rather than being merged into an earlier
+    /// one.
+    pub(crate) fn admit(&mut self, path: &Path, kind: ChangeKind, now: Instant) -> bool {
+        let window = self.window;
+        let within = |at: Instant| now.saturating_duration_since(at) < window;
+        if !self.pruned.is_some_and(within) {
+            self.recent.retain(|_, (_, at)| within(*at));
+            self.pruned = Some(now);
+        }
+        match self.recent.get(path) {
+            Some((last, at)) if *last == kind && within(*at) => false,
+            _ => {
+                self.recent.insert(path.to_path_buf(), (kind, now));
+                true
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.recent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admit() {
+        let mut coalescer = Coalescer::new(Duration::from_millis(50));
+        let start = Instant::now();
+        let a = Path::new("a");
+        let b = Path::new("b");
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(coalescer.admit(a, ChangeKind::Modified, at(0)));
+        assert!(!coalescer.admit(a, ChangeKind::Modified, at(10)));
+        assert!(coalescer.admit(b, ChangeKind::Modified, at(10)));
+        assert!(coalescer.admit(a, ChangeKind::Removed, at(20)));
+        assert!(coalescer.admit(a, ChangeKind::Created, at(30)));
+        assert!(!coalescer.admit(a, ChangeKind::Created, at(40)));
+        // The window starts at the first notification.
+        assert!(coalescer.admit(a, ChangeKind::Created, at(80)));
+        assert!(coalescer.admit(b, ChangeKind::Modified, at(100)));
+        coalescer.clear();
+        assert!(coalescer.admit(b, ChangeKind::Modified, at(110)));
+    }
+}
@@ -0,0 +1,175 @@
+//! Debouncing of the events from the watcher.
+//!
+//! Events are held back for a while after they arrive, so that events that
+//! turn out to be redundant can be left out before the tracker sees them:
+//! repeats of an event, and the stream of events a file gets while it is
+//! being written to. The halves of renames are also paired up, for
+//! backends that don't do so themselves.
+//!
+//! This is only compiled with the `debounce` feature. It was meant to use
+//! `notify-debouncer-full`, but that isn't available to this crate, so the
+//! debouncing is done here instead.
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+fn rename(mode: RenameMode) -> EventKind {
+    EventKind::Modify(ModifyKind::Name(mode))
+}
+
+/// The events that arrived within one debounce period.
+#[derive(Debug, Default)]
+pub(crate) struct Batch {
+    started: Option<Instant>,
+    events: Vec<Event>,
+    /// The index of the most recent event for each path.
+    latest: HashMap<PathBuf, usize>,
+    /// The trackers of the renames the backend paired up itself.
+    paired: HashSet<usize>,
+}
+
+impl Batch {
+    /// Returns when the batch should be released, if it holds any events.
+    pub(crate) fn deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.started.map(|started| started + timeout)
+    }
+
+    pub(crate) fn push(&mut self, event: Event) {
+        self.started.get_or_insert_with(Instant::now);
+        // A repeat of the most recent event for the same paths makes no
+        // difference.
+        let last = event
+            .paths
+            .iter()
+            .filter_map(|path| self.latest.get(path))
+            .max()
+            .map(|i| &self.events[*i]);
+        if last == Some(&event) {
+            return;
+        }
+        if event.kind == rename(RenameMode::Both) {
+            self.paired.extend(event.tracker());
+        }
+        for path in event.paths.iter() {
+            self.latest.insert(path.clone(), self.events.len());
+        }
+        self.events.push(event);
+    }
+
+    /// Returns the events in the batch, and starts a new one.
+    pub(crate) fn take(&mut self) -> Vec<Event> {
+        self.started = None;
+        self.latest.clear();
+        let paired = std::mem::take(&mut self.paired);
+        let events = std::mem::take(&mut self.events);
+        let mut ret = Vec::with_capacity(events.len());
+        // The index in ret of the most recent start of a rename, by tracker.
+        let mut starts = HashMap::new();
+        for event in events {
+            let tracker = event.tracker();
+            let both = match tracker {
+                Some(tracker) if event.kind == rename(RenameMode::From) => {
+                    starts.insert(tracker, ret.len());
+                    None
+                }
+                Some(tracker)
+                    if event.kind == rename(RenameMode::To) && !paired.contains(&tracker) =>
+                {
+                    starts.get(&tracker).and_then(|i| stitch(&ret[*i], &event))
+                }
+                _ => None,
+            };
+            ret.push(event);
+            ret.extend(both);
+        }
+        ret
+    }
+}
+
+/// Returns the event for both sides of the rename that starts with `from`
+/// and ends with `to`.
+fn stitch(from: &Event, to: &Event) -> Option<Event> {
+    let tracker = to.tracker()?;
+    match (&from.paths[..], &to.paths[..]) {
+        ([from], [to]) => Some(
+            Event::new(rename(RenameMode::Both))
+                .add_path(from.clone())
+                .add_path(to.clone())
+                .set_tracker(tracker),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(path.into())
+    }
+
+    #[test]
+    fn test_batch() {
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        let create = EventKind::Create(CreateKind::File);
+        let remove = EventKind::Remove(RemoveKind::File);
+        let mut batch = Batch::default();
+        assert_eq!(batch.deadline(Duration::from_secs(1)), None);
+        batch.push(event(create, "a"));
+        batch.push(event(modify, "a"));
+        batch.push(event(modify, "b"));
+        batch.push(event(modify, "a"));
+        batch.push(event(remove, "a"));
+        batch.push(event(modify, "a"));
+        assert!(batch.deadline(Duration::from_secs(1)).is_some());
+        assert_eq!(
+            batch.take(),
+            vec![
+                event(create, "a"),
+                event(modify, "a"),
+                event(modify, "b"),
+                event(remove, "a"),
+                event(modify, "a"),
+            ]
+        );
+        assert_eq!(batch.deadline(Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn test_burst() {
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        let mut batch = Batch::default();
+        for _ in 0..2 {
+            for i in 0..50_000 {
+                batch.push(event(modify, &i.to_string()));
+            }
+        }
+        // The second round repeats the first.
+        assert_eq!(batch.take().len(), 50_000);
+    }
+
+    #[test]
+    fn test_stitch() {
+        let from = event(EventKind::Modify(ModifyKind::Name(RenameMode::From)), "a").set_tracker(1);
+        let to = event(EventKind::Modify(ModifyKind::Name(RenameMode::To)), "b").set_tracker(1);
+        let both = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path("a".into())
+            .add_path("b".into())
+            .set_tracker(1);
+        let mut batch = Batch::default();
+        batch.push(from.clone());
+        batch.push(to.clone());
+        assert_eq!(batch.take(), vec![from.clone(), to.clone(), both.clone()]);
+
+        // Backends that pair up renames themselves.
+        batch.push(from.clone());
+        batch.push(to.clone());
+        batch.push(both.clone());
+        assert_eq!(batch.take(), vec![from, to, both]);
+    }
+}
@@ -0,0 +1,70 @@
+//! Unified diffs between the original and current contents of files.
+
+use std::path::Path;
+
+/// Returns true if `data` looks like binary rather than text.
+fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|b| *b == 0) || std::str::from_utf8(data).is_err()
+}
+
+/// Produce a unified diff between the `old` and `new` contents of `relpath`.
+///
+/// A missing side means the file did not exist (or was not a regular file).
+/// Returns an empty string if there are no differences.
+pub(crate) fn unified_diff(relpath: &Path, old: Option<&[u8]>, new: Option<&[u8]>) -> String {
+    if old == new {
+        return String::new();
+    }
+    let name = |prefix: &str, contents: Option<&[u8]>| match contents {
+        Some(_) => format!("{}/{}", prefix, relpath.display()),
+        None => "/dev/null".to_string(),
+    };
+    let old_name = name("a", old);
+    let new_name = name("b", new);
+    let old = old.unwrap_or_default();
+    let new = new.unwrap_or_default();
+    if is_binary(old) || is_binary(new) {
+        return format!("Binary files {} and {} differ\n", old_name, new_name);
+    }
+    // Both sides were checked to be valid UTF-8 above.
+    let old = std::str::from_utf8(old).unwrap();
+    let new = std::str::from_utf8(new).unwrap();
+    similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&old_name, &new_name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            unified_diff(Path::new("file"), Some(b"a\nb\nc\n"), Some(b"a\nB\nc\n")),
+            "--- a/file\n+++ b/file\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
+
+    #[test]
+    fn test_added() {
+        assert_eq!(
+            unified_diff(Path::new("file"), None, Some(b"a\n")),
+            "--- /dev/null\n+++ b/file\n@@ -0,0 +1 @@\n+a\n"
+        );
+    }
+
+    #[test]
+    fn test_binary() {
+        assert_eq!(
+            unified_diff(Path::new("file"), Some(b"a\0"), Some(b"b\0")),
+            "Binary files a/file and b/file differ\n"
+        );
+    }
+
+    #[test]
+    fn test_unchanged() {
+        assert_eq!(unified_diff(Path::new("file"), Some(b"a"), Some(b"a")), "");
+    }
+}
@@ -0,0 +1,177 @@
+//! A C interface to the tracker, for embedding it in programs written in
+//! other languages.
+//!
+//! This is only available with the `ffi` feature. The declarations are
+//! available as a C header in `include/dirty_tracker.h`. The crate is only
+//! built as a Rust library by default; build a shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//!
+//! Paths are passed as NUL-terminated strings; on Unix these are the raw
+//! bytes of the path, elsewhere they are UTF-8.
+
+use crate::{DirtyTracker, State};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+/// The tree is clean.
+pub const DIRTY_TRACKER_CLEAN: c_int = 0;
+/// The tree has changed.
+pub const DIRTY_TRACKER_DIRTY: c_int = 1;
+/// It is not known whether the tree has changed.
+pub const DIRTY_TRACKER_UNKNOWN: c_int = 2;
+
+#[cfg(unix)]
+fn path_from_c(path: &CStr) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(path.to_bytes()).into()
+}
+
+#[cfg(not(unix))]
+fn path_from_c(path: &CStr) -> PathBuf {
+    path.to_string_lossy().into_owned().into()
+}
+
+#[cfg(unix)]
+fn path_to_c(path: &Path) -> CString {
+    use std::os::unix::ffi::OsStrExt;
+    // Paths can't contain NUL bytes on Unix.
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+#[cfg(not(unix))]
+fn path_to_c(path: &Path) -> CString {
+    CString::new(path.to_string_lossy().into_owned()).unwrap()
+}
+
+/// Create a tracker for the tree below `path`.
+///
+/// Returns NULL if the tracker could not be created.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn dirty_tracker_new(path: *const c_char) -> *mut DirtyTracker {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = path_from_c(CStr::from_ptr(path));
+    match DirtyTracker::new(&path) {
+        Ok(tracker) => Box::into_raw(Box::new(tracker)),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Returns the state of the tree: one of `DIRTY_TRACKER_CLEAN`,
+/// `DIRTY_TRACKER_DIRTY` or `DIRTY_TRACKER_UNKNOWN`.
+///
+/// # Safety
+/// `tracker` must have been returned by `dirty_tracker_new()`, and not yet
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn dirty_tracker_state(tracker: *mut DirtyTracker) -> c_int {
+    match (*tracker).state() {
+        State::Clean => DIRTY_TRACKER_CLEAN,
+        State::Dirty => DIRTY_TRACKER_DIRTY,
+        State::Unknown => DIRTY_TRACKER_UNKNOWN,
+    }
+}
+
+/// Returns the dirty paths, as a NULL-terminated array of strings.
+///
+/// Returns NULL if the state is unknown. The array must be freed with
+/// `dirty_tracker_paths_free()`.
+///
+/// # Safety
+/// `tracker` must have been returned by `dirty_tracker_new()`, and not yet
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn dirty_tracker_paths(tracker: *mut DirtyTracker) -> *mut *mut c_char {
+    let paths = match (*tracker).sorted_paths() {
+        Some(paths) => paths,
+        None => return ptr::null_mut(),
+    };
+    let array = paths
+        .into_iter()
+        .map(|path| path_to_c(path).into_raw())
+        .chain(std::iter::once(ptr::null_mut()))
+        .collect::<Box<[_]>>();
+    Box::into_raw(array) as *mut *mut c_char
+}
+
+/// Free an array returned by `dirty_tracker_paths()`.
+///
+/// # Safety
+/// `paths` must be NULL, or have been returned by `dirty_tracker_paths()`
+/// and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn dirty_tracker_paths_free(paths: *mut *mut c_char) {
+    if paths.is_null() {
+        return;
+    }
+    let mut len = 0;
+    while !(*paths.add(len)).is_null() {
+        drop(CString::from_raw(*paths.add(len)));
+        len += 1;
+    }
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(paths, len + 1)));
+}
+
+/// Mark the tree as clean.
+///
+/// # Safety
+/// `tracker` must have been returned by `dirty_tracker_new()`, and not yet
+/// been freed.
+#[no_mangle]
+pub unsafe extern "C" fn dirty_tracker_mark_clean(tracker: *mut DirtyTracker) {
+    (*tracker).mark_clean();
+}
+
+/// Free a tracker.
+///
+/// # Safety
+/// `tracker` must be NULL, or have been returned by `dirty_tracker_new()`
+/// and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn dirty_tracker_free(tracker: *mut DirtyTracker) {
+    if !tracker.is_null() {
+        drop(Box::from_raw(tracker));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = path_to_c(dir.path());
+        unsafe {
+            assert!(dirty_tracker_new(ptr::null()).is_null());
+            let missing = path_to_c(&dir.path().join("missing"));
+            assert!(dirty_tracker_new(missing.as_ptr()).is_null());
+
+            let tracker = dirty_tracker_new(root.as_ptr());
+            assert!(!tracker.is_null());
+            assert_eq!(dirty_tracker_state(tracker), DIRTY_TRACKER_CLEAN);
+
+            std::fs::write(dir.path().join("b"), b"b").unwrap();
+            std::fs::write(dir.path().join("a"), b"a").unwrap();
+            assert_eq!(dirty_tracker_state(tracker), DIRTY_TRACKER_DIRTY);
+            let paths = dirty_tracker_paths(tracker);
+            assert_eq!(path_from_c(CStr::from_ptr(*paths)), dir.path().join("a"));
+            assert_eq!(
+                path_from_c(CStr::from_ptr(*paths.add(1))),
+                dir.path().join("b")
+            );
+            assert!((*paths.add(2)).is_null());
+            dirty_tracker_paths_free(paths);
+
+            dirty_tracker_mark_clean(tracker);
+            assert_eq!(dirty_tracker_state(tracker), DIRTY_TRACKER_CLEAN);
+            dirty_tracker_free(tracker);
+        }
+    }
+}
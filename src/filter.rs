@@ -0,0 +1,140 @@
+//! Filtering of the paths that are recorded as dirty.
+
+use crate::glob::Glob;
+use crate::ChangeKind;
+use notify::event::{EventKind, MetadataKind, ModifyKind};
+use notify::RecursiveMode;
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Decides which paths are left out of the dirty set.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    /// Whether to leave out the temporary files editors leave behind.
+    pub(crate) editor_files: bool,
+    /// The kinds of changes that are recorded, or None for all of them.
+    pub(crate) kinds: Option<HashSet<ChangeKind>>,
+    /// Whether to leave out changes that only affect metadata.
+    pub(crate) ignore_metadata: bool,
+    /// The pattern paths have to match to be recorded, if any.
+    pub(crate) glob: Option<Glob>,
+    /// The only paths that are recorded, if set.
+    pub(crate) files: Option<HashSet<PathBuf>>,
+}
+
+impl Filter {
+    /// Returns true if changes to `path` should not be recorded.
+    pub(crate) fn excludes(&self, path: &Path) -> bool {
+        (self.editor_files && is_editor_file(path))
+            || self.glob.as_ref().is_some_and(|glob| !glob.matches(path))
+            || self
+                .files
+                .as_ref()
+                .is_some_and(|files| !files.contains(path))
+    }
+
+    /// Returns the directories to watch instead of the root, if only
+    /// specific files are recorded.
+    pub(crate) fn file_dirs(&self) -> Option<BTreeSet<&Path>> {
+        let files = self.files.as_ref()?;
+        Some(
+            files
+                .iter()
+                .map(|file| match file.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns how the root has to be watched for the paths that can be
+    /// recorded.
+    pub(crate) fn recursive_mode(&self) -> RecursiveMode {
+        self.glob
+            .as_ref()
+            .map_or(RecursiveMode::Recursive, Glob::recursive_mode)
+    }
+
+    /// Returns true if events of `kind` should not be recorded at all.
+    pub(crate) fn excludes_event(&self, kind: &EventKind) -> bool {
+        // The polling watcher reports content changes as changes to the
+        // modification time, unless it compares contents.
+        self.ignore_metadata
+            && matches!(
+                kind,
+                EventKind::Modify(ModifyKind::Metadata(metadata)) if *metadata != MetadataKind::WriteTime
+            )
+    }
+
+    /// Returns true if changes of `kind` should be recorded.
+    pub(crate) fn records(&self, kind: ChangeKind) -> bool {
+        self.kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&kind))
+    }
+}
+
+/// Returns true if `path` looks like a file an editor created for its own
+/// purposes, rather than one the user is editing.
+///
+/// This covers backup files (`file~`), Vim swap files (`.file.swp`) and the
+/// `4913` files Vim creates to check whether it can write to a directory,
+/// and Emacs lock (`.#file`) and auto-save (`#file#`) files.
+pub(crate) fn is_editor_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    if name == "4913" || name.ends_with('~') || name.starts_with(".#") {
+        return true;
+    }
+    if name.len() > 2 && name.starts_with('#') && name.ends_with('#') {
+        return true;
+    }
+    name.len() > 5
+        && name.starts_with('.')
+        && [".swp", ".swo", ".swx"]
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excludes_event() {
+        let filter = Filter {
+            ignore_metadata: true,
+            ..Default::default()
+        };
+        let metadata = |kind| EventKind::Modify(ModifyKind::Metadata(kind));
+        assert!(filter.excludes_event(&metadata(MetadataKind::Any)));
+        assert!(filter.excludes_event(&metadata(MetadataKind::Permissions)));
+        assert!(!filter.excludes_event(&metadata(MetadataKind::WriteTime)));
+        assert!(!filter.excludes_event(&EventKind::Modify(ModifyKind::Any)));
+        assert!(!Filter::default().excludes_event(&metadata(MetadataKind::Any)));
+    }
+
+    #[test]
+    fn test_is_editor_file() {
+        for name in [
+            "file~",
+            ".file.swp",
+            ".file.swo",
+            "4913",
+            ".#file",
+            "#file#",
+        ] {
+            assert!(
+                is_editor_file(&Path::new("/tree/dir").join(name)),
+                "{}",
+                name
+            );
+        }
+        for name in ["file", "file.swp", ".swp", "14913", "#", "file#"] {
+            assert!(!is_editor_file(&Path::new("/tree").join(name)), "{}", name);
+        }
+    }
+}
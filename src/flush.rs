@@ -0,0 +1,480 @@
+//! Strategies for making sure all pending events have been delivered.
+
+use crate::{DirtyTracker, DirtyTrackerError, UnknownReason};
+use notify::{Event, EventKind, RecursiveMode};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+/// A way of making sure that the events for all changes made so far have
+/// been delivered, before the tracker answers a query.
+///
+/// Different file systems and use cases need different trade-offs between
+/// latency and accuracy. The built-in strategies are `SentinelFlush` (the
+/// default), `SettleFlush` and `NoFlush`; use
+/// `DirtyTrackerBuilder::flush_strategy()` to pick one.
+pub trait FlushStrategy: Send {
+    /// Process pending events until all changes made before the call have
+    /// been seen.
+    ///
+    /// If `timeout` is set, this should return `DirtyTrackerError::Timeout`
+    /// rather than block for longer than that waiting for an event.
+    fn flush(
+        &mut self,
+        events: &mut PendingEvents<'_>,
+        timeout: Option<Duration>,
+    ) -> Result<(), DirtyTrackerError>;
+}
+
+/// Access to the events that are pending for a tracker, as passed to
+/// `FlushStrategy::flush()`.
+pub struct PendingEvents<'a> {
+    tracker: &'a mut DirtyTracker,
+    /// The number of rescans requested before the flush started.
+    rescans_requested: u64,
+    /// How often the root had been watched anew, as of the last call to
+    /// `root_rewatched()`.
+    root_rewatched: u64,
+}
+
+impl<'a> PendingEvents<'a> {
+    pub(crate) fn new(tracker: &'a mut DirtyTracker) -> Self {
+        let rescans_requested = tracker.stats.rescans_requested;
+        let root_rewatched = tracker.root_rewatched;
+        PendingEvents {
+            tracker,
+            rescans_requested,
+            root_rewatched,
+        }
+    }
+
+    /// Returns the root of the watched tree.
+    ///
+    /// While the tracker waits for the root to be created (see
+    /// `DirtyTrackerBuilder::allow_missing_root()`), this is its parent.
+    pub fn root(&self) -> &Path {
+        match self.tracker.parent_watch.as_ref() {
+            Some(parent) if self.tracker.root_missing => parent,
+            _ => &self.tracker.path,
+        }
+    }
+
+    /// Wait for the next event, for at most `timeout` if it is set.
+    ///
+    /// Watcher errors are recorded by the tracker rather than returned.
+    /// Returns None if no event arrived in time; events are not processed
+    /// until they are passed to `process()`.
+    pub fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<Event>, DirtyTrackerError> {
+        loop {
+            let result = match timeout {
+                Some(timeout) => match self.tracker.rx.recv_timeout(timeout) {
+                    Ok(result) => result,
+                    Err(RecvTimeoutError::Timeout) => return Ok(None),
+                    Err(RecvTimeoutError::Disconnected) => return Err(self.tracker.disconnected()),
+                },
+                None => self
+                    .tracker
+                    .rx
+                    .recv()
+                    .map_err(|_| self.tracker.disconnected())?,
+            };
+            match result {
+                Ok(event) => return Ok(Some(event)),
+                Err(e) => self.tracker.process_watcher_error(e),
+            }
+        }
+    }
+
+    /// Returns the next event if one has already been delivered.
+    pub fn try_recv(&mut self) -> Result<Option<Event>, DirtyTrackerError> {
+        loop {
+            match self.tracker.rx.try_recv() {
+                Ok(Ok(event)) => return Ok(Some(event)),
+                Ok(Err(e)) => self.tracker.process_watcher_error(e),
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Disconnected) => return Err(self.tracker.disconnected()),
+            }
+        }
+    }
+
+    /// Record the changes described by `event`.
+    pub fn process(&mut self, event: Event) {
+        self.tracker.process_pending_event(event);
+    }
+
+    /// Returns true if events were lost since the flush started, e.g.
+    /// because the watcher or the event queue overflowed.
+    ///
+    /// The events a strategy is waiting for may be among them, so it
+    /// should stop waiting.
+    pub fn overflowed(&self) -> bool {
+        self.tracker.stats.rescans_requested != self.rescans_requested
+    }
+
+    /// Returns true if the root started being watched anew since the flush
+    /// started, or since this was last called.
+    ///
+    /// This happens when it is created or replaced (see
+    /// `DirtyTrackerBuilder::rearm_root()`). Changes made in it before
+    /// then, such as to a sentinel file, were not seen by the watcher.
+    pub fn root_rewatched(&mut self) -> bool {
+        let rewatched = self.tracker.root_rewatched != self.root_rewatched;
+        self.root_rewatched = self.tracker.root_rewatched;
+        rewatched
+    }
+
+    /// Ignore the events for `paths` that are still to arrive, e.g. for
+    /// sentinel files the flush gave up waiting for.
+    pub(crate) fn ignore_late(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.tracker.late_sentinels.extend(paths);
+    }
+
+    /// Record that the events for all changes made so far have arrived, so
+    /// there are no late ones left to ignore.
+    pub(crate) fn caught_up(&mut self) {
+        self.tracker.late_sentinels.clear();
+    }
+
+    /// Process events until none have arrived for `idle`.
+    ///
+    /// Returns `DirtyTrackerError::Timeout` if that doesn't happen within
+    /// `max`.
+    pub fn settle(
+        &mut self,
+        idle: Duration,
+        max: Option<Duration>,
+    ) -> Result<(), DirtyTrackerError> {
+        self.tracker.settle(idle, max)
+    }
+
+    /// Watch `dir` (but not its subdirectories) as well as the tree, without
+    /// recording changes in it.
+    ///
+    /// This is intended for strategies that generate their own events
+    /// outside the tree.
+    pub fn watch_extra(&mut self, dir: &Path) -> Result<(), DirtyTrackerError> {
+        self.tracker
+            .watcher
+            .add_watch(dir, RecursiveMode::NonRecursive)
+            .map_err(DirtyTrackerError::Watcher)?;
+        // Events for the directory need to be recognized by the strategy,
+        // whatever form the watcher reports them in.
+        let _ = self.tracker.rx.normalizer.add_root(dir);
+        self.tracker.rx.exempt(dir);
+        self.tracker.ignored_dirs.push(dir.to_path_buf());
+        Ok(())
+    }
+}
+
+/// Create and remove a sentinel file, and wait for the corresponding
+/// events.
+///
+/// This relies on the watcher delivering events in order, and is the
+/// default. By default the sentinel file is created in the root of the
+/// tree; if that is not writable, a private directory elsewhere is used
+/// instead.
+#[derive(Debug, Default)]
+pub struct SentinelFlush {
+    dir: Option<PathBuf>,
+    prefix: Option<String>,
+    /// Whether `dir` still needs to be watched.
+    unwatched: bool,
+    /// Private directory for sentinel files, used when the tree itself can
+    /// not be written to.
+    scratch: Option<tempfile::TempDir>,
+}
+
+impl SentinelFlush {
+    /// Create a strategy that creates sentinel files in the root of the tree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create sentinel files in `dir`, rather than in the root of the tree.
+    ///
+    /// See `DirtyTrackerBuilder::sentinel_dir()`.
+    pub fn dir(mut self, dir: &Path) -> Self {
+        self.dir = Some(dir.to_path_buf());
+        self.unwatched = true;
+        self
+    }
+
+    /// Use `prefix` for the names of sentinel files.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Create a new sentinel file.
+    ///
+    /// If the tree can not be written to (e.g. because it is read-only or the
+    /// file system is full) and no sentinel directory was configured, this
+    /// falls back to a private directory elsewhere, which is watched from
+    /// then on.
+    fn create(
+        &mut self,
+        events: &mut PendingEvents<'_>,
+    ) -> Result<tempfile::NamedTempFile, DirtyTrackerError> {
+        let mut builder = tempfile::Builder::new();
+        if let Some(prefix) = self.prefix.as_ref() {
+            builder.prefix(prefix);
+        }
+        if let Some(scratch) = self.scratch.as_ref() {
+            return builder
+                .tempfile_in(scratch.path())
+                .map_err(DirtyTrackerError::Sentinel);
+        }
+        if let Some(dir) = self.dir.as_ref() {
+            if self.unwatched {
+                if !dir.starts_with(events.root()) {
+                    events.watch_extra(dir)?;
+                }
+                self.unwatched = false;
+            }
+            return builder
+                .tempfile_in(dir)
+                .map_err(DirtyTrackerError::Sentinel);
+        }
+        let err = match builder.tempfile_in(events.root()) {
+            Ok(f) => return Ok(f),
+            Err(e) => e,
+        };
+        if !matches!(
+            err.kind(),
+            std::io::ErrorKind::PermissionDenied
+                | std::io::ErrorKind::ReadOnlyFilesystem
+                | std::io::ErrorKind::StorageFull
+        ) {
+            return Err(DirtyTrackerError::Sentinel(err));
+        }
+        log_debug!(
+            "Unable to create sentinel in {} ({}), using a private directory",
+            events.root().display(),
+            err
+        );
+        let scratch = tempfile::Builder::new()
+            .prefix("dirty-tracker-")
+            .tempdir()
+            .map_err(DirtyTrackerError::Sentinel)?;
+        events.watch_extra(scratch.path())?;
+        let scratch = self.scratch.insert(scratch);
+        builder
+            .tempfile_in(scratch.path())
+            .map_err(DirtyTrackerError::Sentinel)
+    }
+
+    /// Create and remove a sentinel file, returning its path.
+    fn touch(&mut self, events: &mut PendingEvents<'_>) -> Result<PathBuf, DirtyTrackerError> {
+        let mut dummy = self.create(events)?;
+        use std::io::Write;
+        dummy
+            .write_all(b"dummy")
+            .map_err(DirtyTrackerError::Sentinel)?;
+        let dummy_path = dummy.path().to_path_buf();
+        dummy.close().map_err(DirtyTrackerError::Sentinel)?;
+        Ok(dummy_path)
+    }
+}
+
+impl FlushStrategy for SentinelFlush {
+    fn flush(
+        &mut self,
+        events: &mut PendingEvents<'_>,
+        timeout: Option<Duration>,
+    ) -> Result<(), DirtyTrackerError> {
+        // Make a sentinel change to ensure that we process all pending events.
+
+        // We do this by creating a dummy file and then deleting it
+        // immediately.
+        //
+        // This is a bit of a hack, but it's the simplest way to ensure
+        // that we process all pending events.
+        //
+        // We can't just wait for a timeout, because we might miss events - and it would be
+        // difficult to determine the correct timeout value. Performance is one of the main
+        // reasons for using this library, so we don't want to wait for a long time.
+        let mut dummy_paths = vec![self.touch(events)?];
+
+        // Events for the sentinel itself are not of interest to anybody.
+        // Events without paths, such as requests for a rescan, are not for
+        // the sentinel.
+        let is_sentinel_event = |event: &Event, dummy_paths: &[PathBuf]| {
+            !event.paths.is_empty() && event.paths.iter().all(|p| dummy_paths.contains(p))
+        };
+        let is_sentinel_delete_event = |event: &Event, dummy_path: &Path| {
+            matches!(
+                event.kind,
+                EventKind::Remove(_) if event.paths.iter().any(|p| p == dummy_path)
+            )
+        };
+
+        // Process all pending events.
+        loop {
+            let event = match events.recv(timeout)? {
+                Some(event) => event,
+                None => {
+                    // The sentinel's events may still arrive later, when
+                    // they would be taken for changes to the tree.
+                    events.ignore_late(dummy_paths);
+                    return Err(DirtyTrackerError::Timeout(timeout.unwrap_or_default()));
+                }
+            };
+            let done = is_sentinel_delete_event(&event, dummy_paths.last().unwrap());
+            if !is_sentinel_event(&event, &dummy_paths) {
+                events.process(event);
+            }
+            if done {
+                // Events arrive in order, so those for earlier sentinels
+                // arrived before, if at all.
+                events.caught_up();
+                return Ok(());
+            }
+            // The root was replaced, possibly after the sentinel was
+            // created in the new tree, but before that was watched.
+            if events.root_rewatched() {
+                dummy_paths.push(self.touch(events)?);
+            }
+            // The sentinel's events may have been lost.
+            if events.overflowed() {
+                events.ignore_late(dummy_paths);
+                return Err(DirtyTrackerError::Unknown(UnknownReason::Overflow));
+            }
+        }
+    }
+}
+
+/// Process events until none have arrived for a while.
+///
+/// This never modifies the watched tree, but is only as reliable as the
+/// chosen window: events that take longer than the window to be delivered
+/// are picked up by the next query instead. Every query also takes at least
+/// the window.
+#[derive(Debug, Clone, Copy)]
+pub struct SettleFlush {
+    window: Duration,
+}
+
+impl SettleFlush {
+    /// Create a strategy that waits until no events have arrived for
+    /// `window`.
+    pub fn new(window: Duration) -> Self {
+        SettleFlush { window }
+    }
+}
+
+impl FlushStrategy for SettleFlush {
+    fn flush(
+        &mut self,
+        events: &mut PendingEvents<'_>,
+        timeout: Option<Duration>,
+    ) -> Result<(), DirtyTrackerError> {
+        events.settle(self.window, timeout)
+    }
+}
+
+/// Only process events that have already been delivered.
+///
+/// Queries never block, but changes made just before them may not be
+/// reflected yet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoFlush;
+
+impl FlushStrategy for NoFlush {
+    fn flush(
+        &mut self,
+        events: &mut PendingEvents<'_>,
+        _timeout: Option<Duration>,
+    ) -> Result<(), DirtyTrackerError> {
+        while let Some(event) = events.try_recv()? {
+            events.process(event);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    #[test]
+    fn test_no_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .flush_strategy(NoFlush)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while tracker.state() != State::Dirty {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file]));
+    }
+
+    #[test]
+    fn test_custom_strategy() {
+        struct Counting(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+        impl FlushStrategy for Counting {
+            fn flush(
+                &mut self,
+                events: &mut PendingEvents<'_>,
+                timeout: Option<Duration>,
+            ) -> Result<(), DirtyTrackerError> {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                SentinelFlush::new().flush(events, timeout)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .flush_strategy(Counting(count.clone()))
+            .build()
+            .unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file]));
+        assert_eq!(count.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_rescan_during_flush() {
+        type Slot = std::sync::Arc<std::sync::Mutex<Option<crate::EventSender>>>;
+
+        /// Delivers a request for a rescan while flushing.
+        struct Overflowing(Slot);
+
+        impl FlushStrategy for Overflowing {
+            fn flush(
+                &mut self,
+                events: &mut PendingEvents<'_>,
+                timeout: Option<Duration>,
+            ) -> Result<(), DirtyTrackerError> {
+                if let Some(tx) = self.0.lock().unwrap().take() {
+                    let event = Event::new(EventKind::Other).set_flag(notify::event::Flag::Rescan);
+                    tx.send(Ok(event)).unwrap();
+                }
+                SentinelFlush::new().flush(events, timeout)
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let slot = Slot::default();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .flush_strategy(Overflowing(slot.clone()))
+            .build_with_backend(|tx| {
+                *slot.lock().unwrap() = Some(tx.clone());
+                <notify::RecommendedWatcher as notify::Watcher>::new(tx, notify::Config::default())
+            })
+            .unwrap();
+        assert_eq!(tracker.state(), State::Unknown);
+        assert_eq!(tracker.stats().rescans_requested, 1);
+    }
+}
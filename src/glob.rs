@@ -0,0 +1,235 @@
+//! Glob patterns for the paths to track.
+//!
+//! Patterns use `/` to separate components, and support `*` and `?` within
+//! components, character classes such as `[a-z]` and `[!.]`, and `**` for
+//! any number of components. Unlike shells, wildcards also match names
+//! starting with a dot.
+
+use notify::RecursiveMode;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    One,
+    /// `*`
+    Any,
+    /// `[...]`
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Component {
+    /// `**`
+    AnyDepth,
+    Name(Vec<Token>),
+}
+
+/// A glob pattern, split into the directory it applies to and a pattern
+/// for the paths below it.
+#[derive(Debug, Clone)]
+pub(crate) struct Glob {
+    base: PathBuf,
+    components: Vec<Component>,
+}
+
+fn has_wildcards(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+fn parse_name(component: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = component.chars().peekable();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '?' => Token::One,
+            '*' => {
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                Token::Any
+            }
+            '[' => match parse_class(chars.clone().collect::<Vec<_>>().as_slice()) {
+                Some((token, len)) => {
+                    chars.nth(len - 1);
+                    token
+                }
+                // An unclosed bracket is just a bracket.
+                None => Token::Literal('['),
+            },
+            c => Token::Literal(c),
+        });
+    }
+    tokens
+}
+
+/// Parses a character class, given what follows its `[`; returns it along
+/// with the number of characters it took up.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    let mut i = negated as usize;
+    let mut ranges = Vec::new();
+    // A `]` right at the start is part of the class.
+    while i < chars.len() && (chars[i] != ']' || i == negated as usize) {
+        let start = chars[i];
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|c| *c != ']') {
+            ranges.push((start, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((start, start));
+            i += 1;
+        }
+    }
+    if i == chars.len() {
+        return None;
+    }
+    Some((Token::Class { negated, ranges }, i + 1))
+}
+
+fn match_name(tokens: &[Token], name: &[char]) -> bool {
+    match tokens.split_first() {
+        None => name.is_empty(),
+        Some((Token::Any, rest)) => (0..=name.len()).any(|i| match_name(rest, &name[i..])),
+        Some((token, rest)) => match name.split_first() {
+            None => false,
+            Some((c, name)) => {
+                let matches = match token {
+                    Token::Literal(l) => l == c,
+                    Token::One => true,
+                    Token::Class { negated, ranges } => {
+                        ranges.iter().any(|(lo, hi)| lo <= c && c <= hi) != *negated
+                    }
+                    Token::Any => unreachable!(),
+                };
+                matches && match_name(rest, name)
+            }
+        },
+    }
+}
+
+fn match_components(components: &[Component], names: &[Vec<char>]) -> bool {
+    match components.split_first() {
+        None => names.is_empty(),
+        Some((Component::AnyDepth, rest)) => {
+            (0..=names.len()).any(|i| match_components(rest, &names[i..]))
+        }
+        Some((Component::Name(tokens), rest)) => match names.split_first() {
+            None => false,
+            Some((name, names)) => match_name(tokens, name) && match_components(rest, names),
+        },
+    }
+}
+
+impl Glob {
+    pub(crate) fn new(pattern: &str) -> Self {
+        let mut base = PathBuf::new();
+        if pattern.starts_with('/') {
+            base.push("/");
+        }
+        let mut parts = pattern.split('/').filter(|part| !part.is_empty());
+        let mut components = Vec::new();
+        for part in parts.by_ref() {
+            if has_wildcards(part) {
+                components.push(Self::parse_component(part));
+                break;
+            }
+            base.push(part);
+        }
+        components.extend(parts.map(Self::parse_component));
+        if base.as_os_str().is_empty() {
+            base.push(".");
+        }
+        Glob { base, components }
+    }
+
+    fn parse_component(part: &str) -> Component {
+        if part == "**" {
+            Component::AnyDepth
+        } else {
+            Component::Name(parse_name(part))
+        }
+    }
+
+    /// Returns the directory below which the pattern applies: its leading
+    /// components without any wildcards.
+    pub(crate) fn base(&self) -> &Path {
+        &self.base
+    }
+
+    /// Returns how the base has to be watched for changes to paths that
+    /// match the pattern.
+    pub(crate) fn recursive_mode(&self) -> RecursiveMode {
+        match self.components.as_slice() {
+            [] | [Component::Name(_)] => RecursiveMode::NonRecursive,
+            _ => RecursiveMode::Recursive,
+        }
+    }
+
+    /// Returns true if `path` matches the pattern.
+    pub(crate) fn matches(&self, path: &Path) -> bool {
+        let relpath = match path.strip_prefix(&self.base) {
+            Ok(relpath) => relpath,
+            Err(_) => return false,
+        };
+        let names = relpath
+            .iter()
+            .map(|name| name.to_str().map(|name| name.chars().collect::<Vec<_>>()))
+            .collect::<Option<Vec<_>>>();
+        names.is_some_and(|names| match_components(&self.components, &names))
+    }
+
+    /// Apply the pattern below `to`, e.g. because the base was moved there.
+    pub(crate) fn relocate(&mut self, to: &Path) {
+        self.base = to.to_path_buf();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base() {
+        assert_eq!(Glob::new("src/**/*.rs").base(), Path::new("src"));
+        assert_eq!(Glob::new("/a/b/*.rs").base(), Path::new("/a/b"));
+        assert_eq!(Glob::new("*.rs").base(), Path::new("."));
+        assert_eq!(Glob::new("a/b").base(), Path::new("a/b"));
+        assert_eq!(
+            Glob::new("src/*.rs").recursive_mode(),
+            RecursiveMode::NonRecursive
+        );
+        assert_eq!(
+            Glob::new("src/*/*.rs").recursive_mode(),
+            RecursiveMode::Recursive
+        );
+        assert_eq!(
+            Glob::new("src/**").recursive_mode(),
+            RecursiveMode::Recursive
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let glob = Glob::new("/src/**/*.rs");
+        assert!(glob.matches(Path::new("/src/lib.rs")));
+        assert!(glob.matches(Path::new("/src/a/b/.hidden.rs")));
+        assert!(!glob.matches(Path::new("/src/lib.rs.orig")));
+        assert!(!glob.matches(Path::new("/src/a")));
+        assert!(!glob.matches(Path::new("/other/lib.rs")));
+
+        let glob = Glob::new("/t/file?.[a-c]");
+        assert!(glob.matches(Path::new("/t/file1.b")));
+        assert!(!glob.matches(Path::new("/t/file1.d")));
+        assert!(!glob.matches(Path::new("/t/file.b")));
+        assert!(!glob.matches(Path::new("/t/sub/file1.b")));
+        assert!(Glob::new("/t/[!.]*").matches(Path::new("/t/file")));
+        assert!(!Glob::new("/t/[!.]*").matches(Path::new("/t/.file")));
+        assert!(Glob::new("/t/[]]").matches(Path::new("/t/]")));
+        assert!(Glob::new("/t/a[b*").matches(Path::new("/t/a[bcd")));
+        assert!(Glob::new("/t/**/x").matches(Path::new("/t/x")));
+    }
+}
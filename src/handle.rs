@@ -0,0 +1,151 @@
+//! Cloneable handles to a tracker.
+
+use crate::{DirtyTracker, DirtyTrackerError, State};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A handle to a tracker that can be cloned, and shared between threads.
+///
+/// All clones refer to the same tracker, and so see the same dirty set;
+/// marking the tree clean through one marks it clean for all of them.
+/// Queries lock the tracker for their duration, so they return owned values
+/// rather than references into it. Use `lock()` for anything else.
+///
+/// # Example
+/// ```rust
+/// use dirty_tracker::{DirtyTracker, SharedTracker, State};
+///
+/// let td = tempfile::tempdir().unwrap();
+/// let tracker = SharedTracker::from(DirtyTracker::new(td.path()).unwrap());
+/// let other = tracker.clone();
+/// std::thread::spawn(move || std::fs::write(other.lock().path().join("file"), b"hello"))
+///     .join()
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(tracker.state(), State::Dirty);
+/// ```
+#[derive(Clone)]
+pub struct SharedTracker(Arc<Mutex<DirtyTracker>>);
+
+impl From<DirtyTracker> for SharedTracker {
+    fn from(tracker: DirtyTracker) -> Self {
+        let eager_drain = tracker.eager_drain;
+        let shared = SharedTracker(Arc::new(Mutex::new(tracker)));
+        if eager_drain {
+            shared.drain_eagerly();
+        }
+        shared
+    }
+}
+
+impl SharedTracker {
+    /// Record the events in the dirty set as soon as they are queued, see
+    /// `DirtyTrackerBuilder::eager_drain()`.
+    fn drain_eagerly(&self) {
+        let tracker = Arc::downgrade(&self.0);
+        self.lock().rx.on_push(Box::new(move || {
+            let tracker = match tracker.upgrade() {
+                Some(tracker) => tracker,
+                None => return,
+            };
+            // Whoever holds the lock processes the events anyway, and may
+            // well be waiting for the queue's thread.
+            let guard = tracker.try_lock();
+            if let Ok(mut tracker) = guard {
+                let _ = tracker.sweep();
+            }
+        }));
+    }
+
+    /// Lock the tracker, for as long as the guard is held.
+    pub fn lock(&self) -> MutexGuard<'_, DirtyTracker> {
+        // A panic in a callback shouldn't make the tracker unusable.
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// See `DirtyTracker::state()`.
+    pub fn state(&self) -> State {
+        self.lock().state()
+    }
+
+    /// See `DirtyTracker::paths()`.
+    pub fn paths(&self) -> Option<HashSet<PathBuf>> {
+        self.lock().paths().cloned()
+    }
+
+    /// See `DirtyTracker::is_path_dirty()`.
+    pub fn is_path_dirty(&self, path: &Path) -> Option<bool> {
+        self.lock().is_path_dirty(path)
+    }
+
+    /// See `DirtyTracker::mark_clean()`.
+    pub fn mark_clean(&self) {
+        self.lock().mark_clean()
+    }
+
+    /// Take the paths of the dirty files, and mark the tree as clean.
+    ///
+    /// Unlike calling `paths()` followed by `mark_clean()`, no other handle
+    /// can get in between. See `DirtyTracker::drain_paths()`.
+    pub fn drain_paths(&self) -> Option<Vec<PathBuf>> {
+        Some(self.lock().drain_paths()?.collect())
+    }
+
+    /// See `DirtyTracker::rescan()`.
+    pub fn rescan(&self) -> Result<(), DirtyTrackerError> {
+        self.lock().rescan()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_tracker() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracker = SharedTracker::from(DirtyTracker::new(dir.path()).unwrap());
+        let file = dir.path().join("file");
+        let other = tracker.clone();
+        std::thread::spawn({
+            let file = file.clone();
+            move || {
+                std::fs::write(&file, b"hello").unwrap();
+                assert_eq!(other.is_path_dirty(&file), Some(true));
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!(tracker.paths(), Some(maplit::hashset![file.clone()]));
+        assert_eq!(tracker.drain_paths(), Some(vec![file]));
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_eager_drain() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sender = None;
+        let tracker = DirtyTracker::builder(dir.path())
+            .eager_drain(true)
+            .build_with_backend(|tx| {
+                sender = Some(tx.clone());
+                <notify::NullWatcher as notify::Watcher>::new(tx, notify::Config::default())
+            })
+            .unwrap();
+        let tracker = SharedTracker::from(tracker);
+        let file = dir.path().join("file");
+        let event = notify::Event::new(notify::EventKind::Create(notify::event::CreateKind::File))
+            .add_path(file.clone());
+        let sender = sender.unwrap();
+        sender.send(Ok(event)).unwrap();
+        // The event is recorded without querying the tracker.
+        let start = std::time::Instant::now();
+        while tracker.lock().stats().dirty == 0 {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(tracker.lock().pending_events(), 0);
+        assert_eq!(tracker.paths(), Some(maplit::hashset![file]));
+    }
+}
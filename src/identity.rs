@@ -0,0 +1,184 @@
+//! Identification of files independently of their paths.
+//!
+//! This is used to recognize files that were moved around in the tree, e.g.
+//! by editors that save to a temporary file and then rename it over the
+//! original.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Identifies a file independently of its path, for as long as it exists.
+pub(crate) type FileId = (u64, u64);
+
+/// Returns the identity of the file at `path`, if the platform provides one.
+#[cfg(unix)]
+pub(crate) fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_id(_path: &Path) -> Option<FileId> {
+    None
+}
+
+/// The file ids of the entries in a tree.
+#[derive(Debug, Default)]
+pub(crate) struct FileIds {
+    /// The ids of the entries when the tree was last clean.
+    clean: HashMap<PathBuf, FileId>,
+    /// The ids of entries that changed since.
+    seen: HashMap<PathBuf, FileId>,
+    /// Maps the paths of files that were moved since the tree was clean
+    /// onto their paths back then.
+    moved: HashMap<PathBuf, PathBuf>,
+}
+
+impl FileIds {
+    /// Record the ids of every entry below `root`.
+    pub(crate) fn capture(root: &Path) -> std::io::Result<Self> {
+        let mut clean = HashMap::new();
+        crate::snapshot::walk(root, &mut |path, _| {
+            if let Some(id) = file_id(&path) {
+                clean.insert(path, id);
+            }
+        })?;
+        Ok(FileIds {
+            clean,
+            ..Default::default()
+        })
+    }
+
+    /// Record the id of `path`, which just changed.
+    pub(crate) fn seen(&mut self, path: &Path) {
+        if let Some(id) = file_id(path) {
+            self.seen.insert(path.to_path_buf(), id);
+        }
+    }
+
+    /// Record that `from` was renamed to `to`.
+    ///
+    /// By the time the events are processed, `from` may well be gone, e.g.
+    /// for temporary files that are renamed right after being written.
+    pub(crate) fn renamed(&mut self, from: &Path, to: &Path) {
+        let id = self
+            .seen
+            .get(from)
+            .or_else(|| self.clean.get(from))
+            .copied()
+            .or_else(|| file_id(to));
+        if let Some(id) = id {
+            self.seen.insert(from.to_path_buf(), id);
+            self.seen.insert(to.to_path_buf(), id);
+        }
+    }
+
+    /// Returns the path the file at `path` had when the tree was last clean,
+    /// if it was moved since.
+    pub(crate) fn moved_from(&self, path: &Path) -> Option<&Path> {
+        self.moved.get(path).map(PathBuf::as_path)
+    }
+
+    /// Match up the dirty paths that no longer exist with the files that
+    /// now live elsewhere.
+    ///
+    /// Returns the paths that were only ever temporary names of files that
+    /// are still in the tree, and so shouldn't be reported as dirty.
+    pub(crate) fn reconcile(&mut self, dirty: &HashSet<PathBuf>) -> Vec<PathBuf> {
+        let (gone, present): (Vec<_>, Vec<_>) = dirty
+            .iter()
+            .partition(|path| std::fs::symlink_metadata(path).is_err());
+        let gone = gone
+            .into_iter()
+            .filter_map(|path| Some((path, *self.seen.get(path).or(self.clean.get(path))?)))
+            .collect::<Vec<_>>();
+        if gone.is_empty() {
+            return Vec::new();
+        }
+        let current = present
+            .into_iter()
+            .filter_map(|path| Some((file_id(path)?, path)))
+            .collect::<HashMap<_, _>>();
+        let mut temporary = Vec::new();
+        for (path, id) in gone {
+            let new = match current.get(&id) {
+                Some(new) => *new,
+                None => continue,
+            };
+            self.seen.remove(path);
+            let origin = self
+                .moved
+                .remove(path)
+                .or_else(|| self.clean.contains_key(path).then(|| path.clone()));
+            match origin {
+                Some(origin) if &origin != new => {
+                    self.moved.insert(new.clone(), origin);
+                }
+                // Moved back, or a new file replaced whatever was at `new`.
+                _ => {
+                    self.moved.remove(new);
+                }
+            }
+            if !self.clean.contains_key(path) {
+                temporary.push(path.clone());
+            }
+        }
+        temporary.sort();
+        temporary
+    }
+
+    /// Update the ids after the tree was moved from `from` to `to`.
+    pub(crate) fn relocate(&mut self, from: &Path, to: &Path) {
+        let rebase = |path: &Path| crate::root::rebase(path, from, to);
+        self.clean = self.clean.drain().map(|(k, v)| (rebase(&k), v)).collect();
+        self.seen = self.seen.drain().map(|(k, v)| (rebase(&k), v)).collect();
+        self.moved = self
+            .moved
+            .drain()
+            .map(|(k, v)| (rebase(&k), rebase(&v)))
+            .collect();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconcile() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let c = dir.path().join("c");
+        let tmp = dir.path().join("tmp");
+        std::fs::write(&a, b"a").unwrap();
+        let mut ids = FileIds::capture(dir.path()).unwrap();
+
+        // A rename, followed by another one.
+        std::fs::rename(&a, &b).unwrap();
+        ids.seen(&b);
+        assert!(ids
+            .reconcile(&maplit::hashset![a.clone(), b.clone()])
+            .is_empty());
+        assert_eq!(ids.moved_from(&b), Some(a.as_path()));
+        std::fs::rename(&b, &c).unwrap();
+        ids.seen(&c);
+        assert_eq!(
+            ids.reconcile(&maplit::hashset![a.clone(), b.clone(), c.clone()]),
+            vec![b.clone()]
+        );
+        assert_eq!(ids.moved_from(&c), Some(a.as_path()));
+
+        // A save through a temporary file.
+        std::fs::write(&tmp, b"new").unwrap();
+        ids.seen(&tmp);
+        std::fs::rename(&tmp, &c).unwrap();
+        ids.seen(&c);
+        assert_eq!(
+            ids.reconcile(&maplit::hashset![a.clone(), c.clone(), tmp.clone()]),
+            vec![tmp.clone()]
+        );
+        assert_eq!(ids.moved_from(&c), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Accounting for the inotify watch limit on Linux.
+//!
+//! inotify needs a watch for every directory in the tree, and the number of
+//! watches per user is limited by `fs.inotify.max_user_watches`. Running
+//! out halfway through a tree means events for the rest of it are silently
+//! missed, so the tracker checks up front whether the tree fits.
+
+const MAX_USER_WATCHES: &str = "/proc/sys/fs/inotify/max_user_watches";
+
+/// Returns the maximum number of inotify watches per user.
+pub(crate) fn max_user_watches() -> Option<usize> {
+    std::fs::read_to_string(MAX_USER_WATCHES)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Returns the number of inotify watches currently held by processes of
+/// the current user, as far as they can be inspected.
+pub(crate) fn used_watches() -> Option<usize> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = std::fs::metadata("/proc/self").ok()?.uid();
+    let mut used = 0;
+    for entry in std::fs::read_dir("/proc").ok()? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+        match entry.metadata() {
+            Ok(metadata) if metadata.uid() == uid => {}
+            _ => continue,
+        }
+        // Processes may exit, and those of other users can not be read; both
+        // are skipped.
+        let fdinfo = match std::fs::read_dir(entry.path().join("fdinfo")) {
+            Ok(fdinfo) => fdinfo,
+            Err(_) => continue,
+        };
+        for fd in fdinfo.flatten() {
+            if let Ok(info) = std::fs::read_to_string(fd.path()) {
+                used += info
+                    .lines()
+                    .filter(|line| line.starts_with("inotify wd:"))
+                    .count();
+            }
+        }
+    }
+    Some(used)
+}
+
+/// Returns the number of watches that are still available, if known.
+pub(crate) fn available_watches() -> Option<usize> {
+    let max = max_user_watches()?;
+    Some(max.saturating_sub(used_watches().unwrap_or(0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_available_watches() {
+        if max_user_watches().is_none() {
+            return;
+        }
+        assert!(available_watches().unwrap() <= max_user_watches().unwrap());
+    }
+}
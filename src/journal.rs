@@ -0,0 +1,231 @@
+//! Bookkeeping for checkpoints.
+//!
+//! Time is divided into generations, which end whenever a checkpoint is
+//! taken. For every path, the journal records the generations it changed
+//! in, and how, so that the changes between checkpoints can be told apart.
+//! That is only needed for the changes after the oldest checkpoint that is
+//! still in use, so checkpoints can be released to forget about earlier
+//! ones.
+
+use crate::ChangeKind;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// A logical point in time, as returned by `DirtyTracker::checkpoint()`.
+///
+/// Later checkpoints compare greater than earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Generation(pub(crate) u64);
+
+/// Returns the kind of change that `next` following `previous` amounts to,
+/// or None if they cancel out.
+fn combine(previous: Option<ChangeKind>, next: ChangeKind) -> Option<ChangeKind> {
+    match (previous, next) {
+        // It didn't exist before.
+        (Some(ChangeKind::Created), ChangeKind::Modified | ChangeKind::RenamedTo) => {
+            Some(ChangeKind::Created)
+        }
+        // Nor does it now.
+        (Some(ChangeKind::Created), ChangeKind::Removed | ChangeKind::RenamedFrom) => None,
+        _ => Some(next),
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Journal {
+    /// The generation changes are currently recorded in.
+    generation: u64,
+    /// The generations each path changed in, oldest first, along with the
+    /// kind of change it amounted to in each.
+    changes: HashMap<PathBuf, Vec<(u64, ChangeKind)>>,
+    /// The number of times each checkpoint that hasn't been released was
+    /// handed out.
+    live: BTreeMap<u64, usize>,
+}
+
+impl Journal {
+    pub(crate) fn new() -> Self {
+        Journal {
+            generation: 1,
+            changes: HashMap::new(),
+            live: BTreeMap::new(),
+        }
+    }
+
+    /// End the current generation, and return the checkpoint between it
+    /// and the next.
+    pub(crate) fn checkpoint(&mut self) -> Generation {
+        let checkpoint = Generation(self.generation);
+        *self.live.entry(checkpoint.0).or_insert(0) += 1;
+        self.generation += 1;
+        checkpoint
+    }
+
+    /// Record that `checkpoint` is no longer in use.
+    pub(crate) fn release(&mut self, checkpoint: Generation) {
+        if let Some(count) = self.live.get_mut(&checkpoint.0) {
+            *count -= 1;
+            if *count == 0 {
+                self.live.remove(&checkpoint.0);
+            }
+        }
+        match self.live.keys().next() {
+            Some(oldest) => self.forget_until(Generation(*oldest)),
+            None => self.changes.clear(),
+        }
+    }
+
+    /// Record a change of `kind` to `path`.
+    pub(crate) fn stamp(&mut self, path: &Path, kind: ChangeKind) {
+        // Nobody is interested in when it changed.
+        if self.live.is_empty() {
+            return;
+        }
+        let generation = self.generation;
+        let changes = match self.changes.get_mut(path) {
+            Some(changes) => changes,
+            None => self.changes.entry(path.to_path_buf()).or_default(),
+        };
+        match changes.last_mut() {
+            Some((last, previous)) if *last == generation => match combine(Some(*previous), kind) {
+                Some(kind) => *previous = kind,
+                None => {
+                    changes.pop();
+                    if changes.is_empty() {
+                        self.changes.remove(path);
+                    }
+                }
+            },
+            _ => changes.push((generation, kind)),
+        }
+    }
+
+    /// Returns true if `path` changed after `checkpoint`.
+    pub(crate) fn changed_since(&self, path: &Path, checkpoint: Generation) -> bool {
+        self.changes
+            .get(path)
+            .and_then(|changes| changes.last())
+            .is_some_and(|(generation, _)| *generation > checkpoint.0)
+    }
+
+    /// Returns the paths that changed after `from` but before `to`, and the
+    /// kind of change that amounted to.
+    pub(crate) fn between(
+        &self,
+        from: Generation,
+        to: Generation,
+    ) -> impl Iterator<Item = (&Path, ChangeKind)> + '_ {
+        self.changes.iter().filter_map(move |(path, changes)| {
+            changes
+                .iter()
+                .filter(|(generation, _)| *generation > from.0 && *generation <= to.0)
+                .fold(None, |previous, (_, kind)| combine(previous, *kind))
+                .map(|kind| (path.as_path(), kind))
+        })
+    }
+
+    /// Forget the changes made before `checkpoint`.
+    fn forget_until(&mut self, checkpoint: Generation) {
+        self.changes.retain(|_, changes| {
+            changes.retain(|(generation, _)| *generation > checkpoint.0);
+            !changes.is_empty()
+        });
+    }
+
+    /// Update the paths after the tree was moved from `from` to `to`.
+    pub(crate) fn relocate(&mut self, from: &Path, to: &Path) {
+        self.changes = self
+            .changes
+            .drain()
+            .map(|(path, changes)| (crate::root::rebase(&path, from, to), changes))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal() {
+        let a = Path::new("a");
+        let b = Path::new("b");
+        let mut journal = Journal::new();
+        let first = journal.checkpoint();
+        journal.stamp(a, ChangeKind::Modified);
+        let second = journal.checkpoint();
+        journal.stamp(b, ChangeKind::Created);
+        journal.stamp(b, ChangeKind::Modified);
+        let third = journal.checkpoint();
+        assert!(first < second);
+        assert!(journal.changed_since(a, first));
+        assert!(!journal.changed_since(a, second));
+        assert!(journal.changed_since(b, second));
+        assert!(!journal.changed_since(b, third));
+        journal.stamp(a, ChangeKind::Removed);
+        assert!(journal.changed_since(a, third));
+        // A path that is created and removed again didn't change.
+        journal.stamp(b, ChangeKind::Removed);
+        let c = Path::new("c");
+        journal.stamp(c, ChangeKind::Created);
+        journal.stamp(c, ChangeKind::Removed);
+        assert!(!journal.changed_since(c, third));
+        let fourth = journal.checkpoint();
+
+        let between = |journal: &Journal, from, to| {
+            journal
+                .between(from, to)
+                .map(|(path, kind)| (path.to_path_buf(), kind))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert_eq!(
+            between(&journal, first, third),
+            maplit::btreemap! {
+                a.to_path_buf() => ChangeKind::Modified,
+                b.to_path_buf() => ChangeKind::Created,
+            }
+        );
+        assert_eq!(
+            between(&journal, second, third),
+            maplit::btreemap! {b.to_path_buf() => ChangeKind::Created}
+        );
+        assert!(between(&journal, third, second).is_empty());
+        assert_eq!(
+            between(&journal, third, fourth),
+            maplit::btreemap! {
+                a.to_path_buf() => ChangeKind::Removed,
+                b.to_path_buf() => ChangeKind::Removed,
+            }
+        );
+        assert_eq!(
+            between(&journal, first, fourth),
+            maplit::btreemap! {a.to_path_buf() => ChangeKind::Removed}
+        );
+
+        journal.relocate(Path::new(""), Path::new("/new"));
+        assert!(journal.changed_since(Path::new("/new/a"), third));
+    }
+
+    #[test]
+    fn test_release() {
+        let a = Path::new("a");
+        let b = Path::new("b");
+        let mut journal = Journal::new();
+        journal.stamp(a, ChangeKind::Modified);
+        assert!(journal.changes.is_empty());
+        let first = journal.checkpoint();
+        journal.stamp(a, ChangeKind::Modified);
+        let second = journal.checkpoint();
+        let again = journal.checkpoint();
+        journal.stamp(b, ChangeKind::Modified);
+        journal.release(first);
+        assert!(!journal.changed_since(a, first));
+        assert!(journal.changed_since(b, second));
+        journal.release(second);
+        assert!(journal.changed_since(b, again));
+        journal.release(again);
+        assert!(journal.changes.is_empty());
+        journal.stamp(a, ChangeKind::Modified);
+        assert!(journal.changes.is_empty());
+    }
+}
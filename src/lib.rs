@@ -24,10 +24,80 @@
 //! assert_eq!(tracker.paths(), Some(&maplit::hashset![td.path().join("file")]));
 //! ```
 
+pub use backend::{EventSender, WatcherBackend};
+pub use flush::{FlushStrategy, NoFlush, PendingEvents, SentinelFlush, SettleFlush};
+pub use handle::SharedTracker;
+pub use journal::Generation;
+pub use normalize::UnicodeForm;
+use notify::event::{AccessKind, ModifyKind, RenameMode};
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::collections::HashSet;
+pub use pool::WatcherPool;
+pub use report::Report;
+pub use set::DirtyTrackerSet;
+use shadow::{Shadow, ShadowOptions};
+use snapshot::ContentSnapshot;
+pub use snapshot::TreeSnapshot;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+#[cfg(feature = "tokio")]
+pub use track::track_changes_async;
+pub use track::{track_changes, track_command};
+
+/// Log a warning, if the `log` feature is enabled.
+#[cfg(feature = "log")]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+/// Log a debug message, if the `log` feature is enabled.
+#[cfg(feature = "log")]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+mod backend;
+mod coalesce;
+#[cfg(feature = "debounce")]
+mod debounce;
+mod diff;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+mod flush;
+mod glob;
+mod handle;
+mod identity;
+#[cfg(target_os = "linux")]
+mod inotify;
+mod journal;
+#[cfg(feature = "test-util")]
+pub mod mock;
+mod normalize;
+mod pool;
+mod queue;
+mod relative;
+mod report;
+mod rollup;
+mod root;
+mod set;
+mod shadow;
+mod snapshot;
+mod telemetry;
+mod track;
+mod view;
+mod watchdog;
 
 /// The tracker object.
 ///
@@ -41,472 +111,6462 @@ use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError};
 /// - Clean: No files have been modified.
 /// - Dirty: Some files have been modified.
 /// - Unknown: The tracker is in an unknown state. This can happen if the
-///  tracker has missed some events, or if the underlying file system is
-///  behaving in an unexpected way.
+///   tracker has missed some events, or if the underlying file system is
+///   behaving in an unexpected way.
 pub struct DirtyTracker {
     path: PathBuf,
-    rx: Receiver<notify::Result<Event>>,
+    rx: normalize::EventReceiver,
+    /// The dirty paths. These are full paths, unlike `created`, since
+    /// `paths()` hands them out by reference.
     paths: HashSet<PathBuf>,
-    created: HashSet<PathBuf>,
-    need_rescan: bool,
-    #[allow(dead_code)]
-    watcher: RecommendedWatcher,
+    /// The dirty paths that were not part of the tree when it was clean.
+    created: relative::RelativeSet,
+    /// The dirty paths that were part of the tree when it was clean, but
+    /// were removed since.
+    removed: relative::RelativeSet,
+    unknown: Option<UnknownReason>,
+    baseline: Option<Box<dyn Baseline>>,
+    contents: Option<ContentSnapshot>,
+    shadow: Option<Shadow>,
+    shadow_options: Option<ShadowOptions>,
+    subscribers: Vec<Sender<DirtyNotification>>,
+    /// Receives a copy of every event from the watcher.
+    tee: Option<Sender<Event>>,
+    /// The most recent events from the watcher, and how many to keep.
+    recent: Option<(VecDeque<Event>, usize)>,
+    coalesce: Option<coalesce::Coalescer>,
+    /// When the dirty paths last changed, once a checkpoint was taken.
+    journal: Option<journal::Journal>,
+    callbacks: Vec<ChangeCallback>,
+    threshold: Option<usize>,
+    threshold_exceeded: bool,
+    threshold_callbacks: Vec<ThresholdCallback>,
+    transition_callbacks: Vec<TransitionCallback>,
+    /// The state as of the last transition.
+    published: State,
+    /// The sequence number of the last change.
+    sequence: u64,
+    rollup: Option<rollup::Rollup>,
+    limit: Option<(usize, LimitPolicy)>,
+    /// The depth below which changes are recorded for their ancestors.
+    approximate: Option<usize>,
+    consistency: Consistency,
+    /// Events dropped because the event queue was full.
+    dropped: queue::Dropped,
+    /// The watcher, if it is shared with views.
+    shared: Option<std::sync::Arc<view::Shared>>,
+    /// The directory `relpaths()` is relative to.
+    relpath_base: PathBuf,
+    /// If paths are compared case-insensitively, maps case-folded paths
+    /// onto the form they were recorded in.
+    case_folded: Option<HashMap<PathBuf, PathBuf>>,
+    unicode_form: Option<UnicodeForm>,
+    /// None while a flush is in progress.
+    flush: Option<Box<dyn FlushStrategy>>,
+    /// Directories that are watched for our own purposes, and whose events
+    /// should not be recorded.
+    ignored_dirs: Vec<PathBuf>,
+    /// Sentinel files whose events were still outstanding when a flush
+    /// gave up waiting for them, and should not be recorded once they
+    /// arrive.
+    late_sentinels: HashSet<PathBuf>,
+    /// None while a check is in progress, or if disabled.
+    watchdog: Option<watchdog::Watchdog>,
+    /// The file id of the root, used to find it if it is moved.
+    root_id: Option<identity::FileId>,
+    /// The parent of the root, if it is watched as well to notice the root
+    /// being created.
+    parent_watch: Option<PathBuf>,
+    /// Whether the root has yet to be created.
+    root_missing: bool,
+    /// Whether the root is being replaced, rather than created for the
+    /// first time.
+    root_replaced: bool,
+    /// The number of times the root has started being watched after it was
+    /// created or replaced.
+    root_rewatched: u64,
+    /// Whether a `SharedTracker` should process events as they arrive.
+    eager_drain: bool,
+    rearm_root: bool,
+    follow_root: bool,
+    symlink_policy: SymlinkPolicy,
+    filter: filter::Filter,
+    directories: DirectoryPolicy,
+    /// The dirty directories, unless they are reported along with files.
+    dirs: HashSet<PathBuf>,
+    /// The files opened since the tree was clean, if access is tracked.
+    accessed: Option<HashSet<PathBuf>>,
+    file_ids: Option<identity::FileIds>,
+    /// Maps the paths of entries renamed since the tree was clean onto
+    /// their original paths.
+    renames: HashMap<PathBuf, PathBuf>,
+    /// Paths created since the tree was clean that the watcher reported as
+    /// renamed, without telling which side of the rename they were on.
+    rename_candidates: HashSet<PathBuf>,
+    stats: Stats,
+    metrics: telemetry::Metrics,
+    #[cfg(feature = "tokio")]
+    state_tx: Option<tokio::sync::watch::Sender<State>>,
+    watcher: Box<dyn WatcherBackend>,
+}
+
+/// A record of the clean state of a tree.
+///
+/// This is used by `DirtyTracker::rescan()` to rebuild the dirty set after
+/// events have been missed. By default the tracker records the size and
+/// modification time of every file, but projects with an authoritative
+/// baseline (e.g. a git index or a previously written manifest) can provide
+/// their own implementation.
+pub trait Baseline: Send {
+    /// Returns the paths below `root` that differ from the baseline.
+    ///
+    /// This should include paths that have been added since the baseline was
+    /// recorded, as well as paths that have been removed.
+    fn dirty_paths(&self, root: &Path) -> std::io::Result<HashSet<PathBuf>>;
+
+    /// Returns true if `path` is part of the baseline.
+    ///
+    /// Dirty paths that are not part of the baseline are treated as newly
+    /// created, so removing them again makes them clean.
+    fn contains(&self, path: &Path) -> bool;
+
+    /// Returns the paths of the entries below `dir` that are part of the
+    /// baseline.
+    ///
+    /// This is used to mark the contents of a directory dirty when it is
+    /// removed or moved away, since watchers generally only report the
+    /// directory itself. The default implementation returns nothing, in
+    /// which case only the directory is marked.
+    fn entries_below(&self, _dir: &Path) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Returns the type of `path` in the baseline, if known.
+    ///
+    /// This is used to tell what removed paths used to be, e.g. for
+    /// `DirtyTracker::entries()`. The default implementation returns None.
+    fn file_type(&self, _path: &Path) -> Option<FileType> {
+        None
+    }
+
+    /// Record the current contents of `root` as the new baseline.
+    ///
+    /// This is called by `DirtyTracker::mark_clean()`. The default
+    /// implementation does nothing, which is appropriate for baselines that
+    /// are maintained elsewhere.
+    fn reset(&mut self, _root: &Path) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Update the baseline after the tree was moved from `from` to `to`.
+    ///
+    /// This is called when the tracker follows a moved root, see
+    /// `DirtyTrackerBuilder::follow_root()`. The default implementation does
+    /// nothing, which is appropriate for baselines that don't record paths.
+    fn relocate(&mut self, _from: &Path, _to: &Path) {}
+}
+
+/// Counters describing the activity of a tracker.
+///
+/// See `DirtyTracker::stats()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of events received from the watcher.
+    pub events: u64,
+    /// The number of paths reported as created.
+    pub created: u64,
+    /// The number of paths reported as modified.
+    pub modified: u64,
+    /// The number of paths reported as removed.
+    pub removed: u64,
+    /// The number of renames reported by the watcher.
+    pub renamed: u64,
+    /// The number of errors reported by the watcher.
+    pub watcher_errors: u64,
+    /// The number of times the watcher dropped events and requested a
+    /// rescan.
+    pub rescans_requested: u64,
+    /// The number of times `DirtyTracker::rescan()` rebuilt the dirty set.
+    pub rescans: u64,
+    /// The number of events that were dropped because the event queue was
+    /// full; see `DirtyTrackerBuilder::event_queue_capacity()`.
+    pub events_dropped: u64,
+    /// How long the most recent flush took, e.g. the round trip of the
+    /// sentinel file.
+    pub last_flush: Option<std::time::Duration>,
+    /// The longest a flush has taken.
+    pub max_flush: Option<std::time::Duration>,
+    /// The current number of dirty paths.
+    pub dirty: usize,
+    /// The largest number of dirty paths seen at once.
+    pub peak_dirty: usize,
+}
+
+/// The kind of change that was made to a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    /// The path was created.
+    Created,
+    /// The contents or metadata of the path were modified.
+    Modified,
+    /// The path was removed.
+    Removed,
+    /// The path was renamed to another path.
+    ///
+    /// This is followed by `RenamedTo` for the new path, if that is in the
+    /// tree; `DirtyTracker::renames()` pairs up the two.
+    RenamedFrom,
+    /// Another path was renamed to this path.
+    RenamedTo,
+}
+
+type ChangeCallback = Box<dyn FnMut(&Path, ChangeKind) + Send>;
+type ThresholdCallback = Box<dyn FnMut(usize) + Send>;
+type TransitionCallback = Box<dyn FnMut(&Transition) + Send>;
+
+/// A notification about a single change, as sent to subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyNotification {
+    /// The path that changed.
+    pub path: PathBuf,
+    /// What happened to the path.
+    pub kind: ChangeKind,
+    /// The sequence number of the change; see `DirtyTracker::sequence()`.
+    pub sequence: u64,
+}
+
+/// A change of the state of a tracker, as passed to `on_transition()`
+/// callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    /// The state before.
+    pub from: State,
+    /// The state after.
+    pub to: State,
+    /// The change that caused the transition, if it was caused by one,
+    /// rather than by e.g. `mark_clean()` or a watcher error.
+    pub path: Option<PathBuf>,
+}
+
+/// The type of an entry in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link.
+    Symlink,
+    /// Anything else, such as a named pipe or a device node.
+    Other,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl From<std::fs::FileType> for FileType {
+    fn from(file_type: std::fs::FileType) -> Self {
+        if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_file() {
+            FileType::File
+        } else {
+            FileType::Other
+        }
+    }
+}
+
+/// A dirty path, as returned by `DirtyTracker::entries()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtyEntry {
+    /// The dirty path.
+    pub path: PathBuf,
+    /// What the path is, or was before it was removed, if known.
+    pub file_type: Option<FileType>,
+    /// Whether the path still exists.
+    pub exists: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum State {
     Clean,
     Dirty,
     Unknown,
 }
 
+/// The reason a tracker is in the `State::Unknown` state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UnknownReason {
+    /// The watcher dropped events and requested a rescan.
+    Overflow,
+    /// The watcher reported an error.
+    WatcherError(String),
+    /// The watcher ran out of watches, e.g. because of the inotify limit.
+    TooManyFiles {
+        /// The limit on the number of watches, if known.
+        limit: Option<usize>,
+    },
+    /// The watcher's event channel was disconnected.
+    Disconnected,
+    /// Timed out waiting for pending events to be delivered.
+    Timeout(std::time::Duration),
+    /// The sentinel file could not be created or removed.
+    Sentinel(std::io::ErrorKind),
+    /// The watcher did not report a probe change within the given
+    /// duration, and has probably stopped working.
+    Unresponsive(std::time::Duration),
+    /// The watched directory itself was removed.
+    RootRemoved,
+    /// The watched directory was moved elsewhere, and is not being followed;
+    /// see `DirtyTrackerBuilder::follow_root()`.
+    RootMoved,
+    /// More paths changed than the tracker was allowed to record; see
+    /// `DirtyTrackerBuilder::max_dirty_paths()`.
+    TooManyChanges {
+        /// The maximum number of dirty paths.
+        limit: usize,
+    },
+}
+
+impl UnknownReason {
+    fn from_notify(error: &notify::Error) -> Self {
+        match error.kind {
+            notify::ErrorKind::MaxFilesWatch => UnknownReason::TooManyFiles {
+                limit: watch_limit(),
+            },
+            _ => UnknownReason::WatcherError(error.to_string()),
+        }
+    }
+
+    fn from_error(error: &DirtyTrackerError) -> Self {
+        match error {
+            DirtyTrackerError::Init(e) | DirtyTrackerError::Watcher(e) => {
+                UnknownReason::from_notify(e)
+            }
+            DirtyTrackerError::Sentinel(e) => UnknownReason::Sentinel(e.kind()),
+            DirtyTrackerError::Timeout(timeout) => UnknownReason::Timeout(*timeout),
+            DirtyTrackerError::Disconnected => UnknownReason::Disconnected,
+            DirtyTrackerError::Unknown(reason) => reason.clone(),
+            DirtyTrackerError::WatchLimit { limit } => {
+                UnknownReason::TooManyFiles { limit: *limit }
+            }
+            other => UnknownReason::WatcherError(other.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for UnknownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UnknownReason::Overflow => write!(f, "Events were dropped; a rescan is needed"),
+            UnknownReason::WatcherError(e) => write!(f, "Watcher error: {}", e),
+            UnknownReason::TooManyFiles { limit } => fmt_watch_limit(f, *limit),
+            UnknownReason::Disconnected => write!(f, "Disconnected"),
+            UnknownReason::Timeout(timeout) => write!(f, "Timeout: {:?}", timeout),
+            UnknownReason::Sentinel(kind) => write!(f, "Sentinel file error: {}", kind),
+            UnknownReason::Unresponsive(timeout) => {
+                write!(f, "Watcher did not respond within {:?}", timeout)
+            }
+            UnknownReason::RootRemoved => write!(f, "The watched directory was removed"),
+            UnknownReason::RootMoved => write!(f, "The watched directory was moved"),
+            UnknownReason::TooManyChanges { limit } => {
+                write!(f, "More than {} paths changed", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnknownReason {}
+
+/// Returns the limit on the number of watches, if there is a known one.
+fn watch_limit() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    return inotify::max_user_watches();
+    #[cfg(not(target_os = "linux"))]
+    return None;
+}
+
+fn fmt_watch_limit(f: &mut std::fmt::Formatter, limit: Option<usize>) -> std::fmt::Result {
+    match limit {
+        Some(limit) => write!(
+            f,
+            "The limit of {} watches was reached; consider raising fs.inotify.max_user_watches",
+            limit
+        ),
+        None => write!(f, "Too many files to watch"),
+    }
+}
+
+/// Errors that can occur while setting up or querying a tracker.
 #[derive(Debug)]
-pub enum ProcessError {
+#[non_exhaustive]
+pub enum DirtyTrackerError {
+    /// The watcher could not be created or could not watch the requested path.
+    Init(notify::Error),
+    /// The watcher reported an error while delivering events.
+    Watcher(notify::Error),
+    /// Creating or removing the sentinel file failed.
+    Sentinel(std::io::Error),
+    /// Timed out waiting for pending events to be delivered.
     Timeout(std::time::Duration),
+    /// The watcher's event channel was disconnected.
     Disconnected,
+    /// Scanning the watched tree failed.
+    Io(std::io::Error),
+    /// The operation needs a baseline, but the tracker was built without one.
+    NoBaseline,
+    /// The tracker is in the `State::Unknown` state.
+    Unknown(UnknownReason),
+    /// A view was requested of a tracker that wasn't built with
+    /// `DirtyTrackerBuilder::allow_views()`.
+    ViewsNotAllowed,
+    /// The watcher ran out of watches while setting up, e.g. because of
+    /// the inotify limit.
+    WatchLimit {
+        /// The limit on the number of watches, if known.
+        limit: Option<usize>,
+    },
+    /// The only watcher available is a low-performance one, and the
+    /// builder was told to refuse those.
+    LowPerformanceWatcher(notify::WatcherKind),
+    /// An option was set on the builder to a value it doesn't accept.
+    InvalidOption(&'static str),
+    /// Watching the tree needs more inotify watches than are available.
+    InsufficientWatches {
+        /// The number of watches needed for the tree.
+        needed: usize,
+        /// The number of watches still available to the current user.
+        available: usize,
+    },
 }
 
-impl std::fmt::Display for ProcessError {
+impl std::fmt::Display for DirtyTrackerError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            ProcessError::Timeout(timeout) => write!(f, "Timeout: {:?}", timeout),
-            ProcessError::Disconnected => write!(f, "Disconnected"),
+            DirtyTrackerError::Init(e) => write!(f, "Unable to set up watcher: {}", e),
+            DirtyTrackerError::Watcher(e) => write!(f, "Watcher error: {}", e),
+            DirtyTrackerError::Sentinel(e) => write!(f, "Sentinel file error: {}", e),
+            DirtyTrackerError::Timeout(timeout) => write!(f, "Timeout: {:?}", timeout),
+            DirtyTrackerError::Disconnected => write!(f, "Disconnected"),
+            DirtyTrackerError::Io(e) => write!(f, "I/O error: {}", e),
+            DirtyTrackerError::NoBaseline => write!(f, "No baseline available"),
+            DirtyTrackerError::ViewsNotAllowed => write!(f, "The tracker does not allow views"),
+            DirtyTrackerError::Unknown(reason) => write!(f, "State unknown: {}", reason),
+            DirtyTrackerError::WatchLimit { limit } => fmt_watch_limit(f, *limit),
+            DirtyTrackerError::LowPerformanceWatcher(kind) => {
+                write!(f, "Refusing to use low-performance watcher: {:?}", kind)
+            }
+            DirtyTrackerError::InvalidOption(reason) => write!(f, "Invalid option: {}", reason),
+            DirtyTrackerError::InsufficientWatches { needed, available } => write!(
+                f,
+                "Watching the tree needs {} inotify watches, but only {} are available",
+                needed, available
+            ),
         }
     }
 }
 
-impl std::error::Error for ProcessError {}
+impl std::error::Error for DirtyTrackerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DirtyTrackerError::Init(e) | DirtyTrackerError::Watcher(e) => Some(e),
+            DirtyTrackerError::Sentinel(e) | DirtyTrackerError::Io(e) => Some(e),
+            DirtyTrackerError::Timeout(_)
+            | DirtyTrackerError::Disconnected
+            | DirtyTrackerError::NoBaseline
+            | DirtyTrackerError::ViewsNotAllowed
+            | DirtyTrackerError::WatchLimit { .. }
+            | DirtyTrackerError::LowPerformanceWatcher(_)
+            | DirtyTrackerError::InvalidOption(_)
+            | DirtyTrackerError::InsufficientWatches { .. } => None,
+            DirtyTrackerError::Unknown(reason) => Some(reason),
+        }
+    }
+}
 
-impl DirtyTracker {
-    /// Create a new tracker object.
-    ///
-    /// # Arguments
-    /// * `path` - The path to the directory to watch.
-    ///
-    /// # Returns
-    /// A new `DirtyTracker` object.
-    pub fn new(path: &Path) -> notify::Result<Self> {
-        // Create a channel to receive the events.
-        let (tx, rx) = channel();
+impl From<notify::Error> for DirtyTrackerError {
+    fn from(e: notify::Error) -> Self {
+        DirtyTrackerError::Watcher(e)
+    }
+}
 
-        let config = notify::Config::default();
+impl From<UnknownReason> for DirtyTrackerError {
+    fn from(reason: UnknownReason) -> Self {
+        DirtyTrackerError::Unknown(reason)
+    }
+}
 
-        // Create a watcher object.
-        let mut watcher: RecommendedWatcher = notify::RecommendedWatcher::new(tx, config)?;
+/// The error type previously returned by event processing.
+#[deprecated(since = "0.4.0", note = "Use DirtyTrackerError instead")]
+pub type ProcessError = DirtyTrackerError;
 
-        // TODO: Refuse to work with watchers that are low-performance.
+/// Where and how sentinel files are created.
+#[derive(Debug, Clone, Default)]
+struct SentinelOptions {
+    /// The directory to create sentinel files in; defaults to the root of
+    /// the watched tree.
+    dir: Option<PathBuf>,
+    /// The prefix for sentinel file names.
+    prefix: Option<String>,
+}
 
-        // Add a path to be watched. All files and directories at that path and below will be monitored for changes.
-        watcher.watch(path, RecursiveMode::Recursive)?;
+/// What to do if the recommended watcher for the platform is a
+/// low-performance one, i.e. one that polls the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PollingPolicy {
+    /// Use it anyway. `DirtyTracker::is_polling()` tells whether this
+    /// happened.
+    #[default]
+    Allow,
+    /// Fail with `DirtyTrackerError::LowPerformanceWatcher`.
+    Refuse,
+}
 
-        Ok(DirtyTracker {
-            path: path.to_path_buf(),
-            rx,
-            paths: HashSet::new(),
-            created: HashSet::new(),
-            need_rescan: false,
-            watcher,
-        })
+impl PollingPolicy {
+    fn check(self, kind: notify::WatcherKind) -> Result<(), DirtyTrackerError> {
+        match (self, kind) {
+            (PollingPolicy::Refuse, notify::WatcherKind::PollWatcher) => {
+                Err(DirtyTrackerError::LowPerformanceWatcher(kind))
+            }
+            _ => Ok(()),
+        }
     }
+}
 
-    /// Mark all files as clean.
+/// What to do once the dirty set reaches its maximum size, see
+/// `DirtyTrackerBuilder::max_dirty_paths()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitPolicy {
+    /// Give up, and enter the `State::Unknown` state with
+    /// `UnknownReason::TooManyChanges`.
+    Unknown,
+    /// Roll up the directories with the most dirty entries, as with
+    /// `DirtyTrackerBuilder::rollup_threshold()`, until the dirty set fits.
+    RollUp,
+}
+
+/// How up to date queries such as `DirtyTracker::state()` are, see
+/// `DirtyTrackerBuilder::consistency()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Consistency {
+    /// Every query first waits for the events of all changes made before
+    /// it, using the flush strategy.
+    #[default]
+    Strong,
+    /// Queries only process the events that have already arrived, and
+    /// never block. Changes show up as soon as the watcher reports them;
+    /// use `DirtyTracker::sync()` to wait for them explicitly.
+    Eventual,
+}
+
+/// How directories are reported, see `DirtyTrackerBuilder::directories()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirectoryPolicy {
+    /// Report directories along with files.
+    #[default]
+    Include,
+    /// Report directories separately, through `DirtyTracker::dirty_dirs()`.
+    Separate,
+    /// Don't report directories at all.
+    Omit,
+}
+
+/// What to do with changes outside the tree, such as those to the targets
+/// of symbolic links that point outside it.
+///
+/// Depending on the platform, watchers may report these with the path of
+/// the target rather than that of the link in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Record changes with the paths the watcher reports them under.
+    #[default]
+    AsReported,
+    /// Watch the targets of symbolic links that point outside the tree, and
+    /// report changes to them as changes to the links.
     ///
-    /// Note that this can race with file modifications, so it's only safe
-    /// if you're sure that no modifications are happening.
-    pub fn mark_clean(&mut self) {
-        let _ = self.process_pending(None);
-        self.need_rescan = false;
-        self.paths.clear();
-        self.created.clear();
-    }
+    /// This covers links that exist when the tracker is created, and those
+    /// that are created later on. `DirtyTracker::rescan()` only compares the
+    /// links themselves.
+    Follow,
+    /// Ignore changes to paths outside the tree.
+    Ignore,
+}
 
-    /// Returns true if there are dirty files.
-    #[deprecated(since = "0.2.0", note = "Use state() instead")]
-    pub fn is_dirty(&mut self) -> bool {
-        self.state() == State::Dirty
-    }
+enum BaselineOption {
+    TreeSnapshot,
+    Custom(Box<dyn Baseline>),
+    Disabled,
+}
 
-    /// Returns the state of the tracker.
-    pub fn state(&mut self) -> State {
-        if self.process_pending(None).is_err() {
-            return State::Unknown;
+/// Builder for `DirtyTracker` objects.
+///
+/// # Example
+/// ```rust
+/// use dirty_tracker::{DirtyTracker, State};
+///
+/// let td = tempfile::tempdir().unwrap();
+///
+/// let mut tracker = DirtyTracker::builder(td.path())
+///     .tree_snapshot(true)
+///     .build()
+///     .unwrap();
+/// assert_eq!(tracker.state(), State::Clean);
+/// ```
+pub struct DirtyTrackerBuilder {
+    path: PathBuf,
+    baseline: BaselineOption,
+    hash_contents: bool,
+    shadow_options: Option<ShadowOptions>,
+    threshold: Option<usize>,
+    rollup_threshold: Option<usize>,
+    limit: Option<(usize, LimitPolicy)>,
+    approximate: Option<usize>,
+    consistency: Consistency,
+    queue_capacity: Option<usize>,
+    eager_drain: bool,
+    allow_views: bool,
+    #[cfg(feature = "debounce")]
+    debounce: Option<std::time::Duration>,
+    coalesce_window: Option<std::time::Duration>,
+    tee: Option<Sender<Event>>,
+    recent_events: Option<usize>,
+    relpath_base: Option<PathBuf>,
+    canonicalize: bool,
+    case_insensitive: bool,
+    unicode_form: Option<UnicodeForm>,
+    flush: Option<Box<dyn FlushStrategy>>,
+    sentinel: SentinelOptions,
+    check_watches: bool,
+    polling: PollingPolicy,
+    poll_interval: Option<std::time::Duration>,
+    poll_compare_contents: bool,
+    watchdog: Option<(std::time::Duration, std::time::Duration)>,
+    follow_root: bool,
+    allow_missing_root: bool,
+    rearm_root: bool,
+    symlink_policy: SymlinkPolicy,
+    track_file_ids: bool,
+    track_access: bool,
+    directories: DirectoryPolicy,
+    filter: filter::Filter,
+}
+
+impl DirtyTrackerBuilder {
+    /// Create a new builder for a tracker watching `path`.
+    pub fn new(path: &Path) -> Self {
+        DirtyTrackerBuilder {
+            path: path.to_path_buf(),
+            baseline: BaselineOption::Disabled,
+            hash_contents: false,
+            shadow_options: None,
+            threshold: None,
+            rollup_threshold: None,
+            limit: None,
+            approximate: None,
+            consistency: Consistency::default(),
+            queue_capacity: None,
+            eager_drain: false,
+            allow_views: false,
+            #[cfg(feature = "debounce")]
+            debounce: None,
+            coalesce_window: None,
+            tee: None,
+            recent_events: None,
+            relpath_base: None,
+            canonicalize: true,
+            case_insensitive: false,
+            unicode_form: None,
+            flush: None,
+            sentinel: SentinelOptions::default(),
+            check_watches: false,
+            polling: PollingPolicy::default(),
+            poll_interval: None,
+            poll_compare_contents: false,
+            watchdog: None,
+            follow_root: false,
+            allow_missing_root: false,
+            rearm_root: false,
+            symlink_policy: SymlinkPolicy::default(),
+            track_file_ids: false,
+            track_access: false,
+            directories: DirectoryPolicy::default(),
+            filter: filter::Filter::default(),
         }
-        if self.need_rescan {
-            State::Unknown
-        } else if self.paths.is_empty() {
-            State::Clean
-        } else {
-            State::Dirty
+    }
+
+    /// Create a new builder for a tracker of the paths that match `pattern`,
+    /// e.g. `src/**/*.rs`.
+    ///
+    /// Only the directory named by the leading components of the pattern
+    /// without wildcards is watched, and only recursively if needed. Paths
+    /// that don't match are left out of the dirty set. Patterns support
+    /// `*`, `?`, character classes such as `[a-z]` and `[!a-z]`, and `**`
+    /// for any number of directories.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::DirtyTrackerBuilder;
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let pattern = format!("{}/**/*.rs", td.path().display());
+    /// let mut tracker = DirtyTrackerBuilder::from_glob(&pattern).build().unwrap();
+    /// std::fs::write(td.path().join("lib.rs"), b"").unwrap();
+    /// std::fs::write(td.path().join("README"), b"").unwrap();
+    /// assert_eq!(tracker.paths().unwrap().len(), 1);
+    /// ```
+    pub fn from_glob(pattern: &str) -> Self {
+        let mut glob = glob::Glob::new(pattern);
+        // Relative patterns are relative to the current directory; the
+        // paths they are matched against are absolute. If the current
+        // directory can't be determined, building the tracker fails anyway.
+        if let Ok(base) = root::absolute(glob.base()) {
+            glob.relocate(&base);
         }
+        let mut builder = Self::new(glob.base());
+        builder.filter.glob = Some(glob);
+        builder
     }
 
-    /// Returns the paths of the dirty files.
+    /// Create a new builder for a tracker of just the files in `files`.
     ///
-    /// If the tracker is in an unknown state, this will return None.
-    pub fn paths(&mut self) -> Option<&HashSet<PathBuf>> {
-        if self.process_pending(None).is_err() {
-            return None;
+    /// Rather than a whole tree, only the directories that contain the files
+    /// are watched, without their subdirectories, and changes to other
+    /// entries in them are left out. The files don't have to exist yet. The
+    /// tracker's path is the deepest directory that contains all of them.
+    /// The sizes and modification times of the files are recorded as the
+    /// baseline for `DirtyTracker::rescan()`.
+    ///
+    /// Relative paths are taken to be relative to the current directory.
+    /// Building the tracker fails with `DirtyTrackerError::InvalidOption`
+    /// if `files` is empty.
+    pub fn for_files(files: &[PathBuf]) -> Self {
+        let filter = filter::Filter {
+            // If the current directory can't be determined, building the
+            // tracker fails anyway.
+            files: Some(
+                files
+                    .iter()
+                    .map(|file| root::absolute(file).unwrap_or_else(|_| file.clone()))
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let mut dirs = filter.file_dirs().unwrap().into_iter();
+        let first = dirs.next().map(Path::to_path_buf);
+        let mut root = first.clone().unwrap_or_else(|| PathBuf::from("."));
+        for dir in dirs {
+            while !dir.starts_with(&root) && root.pop() {}
         }
-        if self.need_rescan {
-            None
+        let mut builder = Self::new(&root);
+        builder.filter = filter;
+        // Recording the files themselves is cheap.
+        builder.baseline = BaselineOption::TreeSnapshot;
+        // The root itself may not be watched.
+        builder.sentinel.dir = first;
+        builder
+    }
+
+    /// Record the size and modification time of every entry in the tree,
+    /// as the baseline for `DirtyTracker::rescan()`.
+    ///
+    /// This scans the whole tree at construction and in `mark_clean()`, so
+    /// it is disabled by default. Entries that can't be read, e.g. because
+    /// of their permissions, are left out.
+    ///
+    /// The snapshot is also what tells the tracker which entries were below
+    /// a directory that is removed or moved away, if the watcher only
+    /// reports the directory itself. Without it, only the directory and the
+    /// entries created below it since the tree was clean are reported.
+    pub fn tree_snapshot(mut self, enabled: bool) -> Self {
+        self.baseline = if enabled {
+            BaselineOption::TreeSnapshot
         } else {
-            Some(&self.paths)
-        }
+            BaselineOption::Disabled
+        };
+        self
     }
 
-    /// Returns the relative paths of the dirty files.
+    /// Use a custom baseline for `DirtyTracker::rescan()`, rather than
+    /// recording the size and modification time of every file.
+    pub fn baseline(mut self, baseline: impl Baseline + 'static) -> Self {
+        self.baseline = BaselineOption::Custom(Box::new(baseline));
+        self
+    }
+
+    /// Don't record a baseline at all.
     ///
-    /// If the tracker is in an unknown state, this will return None.
-    pub fn relpaths(&mut self) -> Option<HashSet<&Path>> {
-        let path = self.path.clone();
-        self.paths().as_mut().map(|paths| {
-            paths
-                .iter()
-                .map(|p| p.strip_prefix(&path).unwrap())
-                .collect()
-        })
+    /// This is the default, unless `tree_snapshot()` or `baseline()` is
+    /// used. It avoids scanning the tree at construction and in
+    /// `mark_clean()`, but means that `DirtyTracker::rescan()` is not
+    /// available.
+    pub fn without_baseline(mut self) -> Self {
+        self.baseline = BaselineOption::Disabled;
+        self
     }
 
-    fn process_pending_event(&mut self, event: Event) {
-        if event.need_rescan() {
-            self.need_rescan = true;
-        }
-        match event {
-            Event {
-                kind: EventKind::Create(_),
-                paths,
-                ..
-            } => {
-                for path in paths {
-                    self.created.insert(path.clone());
-                    self.paths.insert(path);
-                }
-            }
-            Event {
-                kind: EventKind::Modify(_),
-                paths,
-                ..
-            } => {
-                for path in paths {
-                    self.paths.insert(path);
-                }
-            }
-            Event {
-                kind: EventKind::Remove(_),
-                paths,
-                ..
-            } => {
-                for path in paths {
-                    if self.created.contains(&path) {
-                        self.paths.remove(&path);
-                        self.created.remove(&path);
-                    } else {
-                        self.paths.insert(path.clone());
-                    }
-                }
-            }
-            _ => {}
-        }
+    /// Record a hash of the contents of every file, for use by
+    /// `DirtyTracker::verify()`.
+    ///
+    /// This reads the entire tree at construction and in `mark_clean()`, so
+    /// it is disabled by default.
+    pub fn hash_contents(mut self, hash_contents: bool) -> Self {
+        self.hash_contents = hash_contents;
+        self
     }
 
-    fn process_pending(
-        &mut self,
-        timeout: Option<std::time::Duration>,
-    ) -> Result<(), ProcessError> {
-        // Make a sentinel change to ensure that we process all pending events.
-
-        // We do this by creating a dummy file and then deleting it
-        // immediately.
-        //
-        // This is a bit of a hack, but it's the simplest way to ensure
-        // that we process all pending events.
-        //
-        // We can't just wait for a timeout, because we might miss events - and it would be
-        // difficult to determine the correct timeout value. Performance is one of the main
-        // reasons for using this library, so we don't want to wait for a long time.
-        let mut dummy = tempfile::NamedTempFile::new_in(&self.path).unwrap();
-        use std::io::Write;
-        dummy.write_all(b"dummy").unwrap();
-        let dummy_path = dummy.path().to_path_buf();
-        std::mem::drop(dummy);
-
-        let is_sentinel_delete_event = |event: &notify::Event| {
-            matches!(
-                event.kind,
-                EventKind::Remove(_) if event.paths.iter().any(|p| p == &dummy_path)
-            )
+    /// Keep a copy of the original contents of the tree, so that changes can
+    /// be undone with `DirtyTracker::revert()`.
+    ///
+    /// Events are only delivered after a file has been modified, so the
+    /// original contents have to be preserved up front: the entire tree is
+    /// copied into a shadow directory at construction. `mark_clean()` then
+    /// only updates the copies of the paths that were dirty, unless the
+    /// state was unknown. This is disabled by default.
+    pub fn snapshot_contents(mut self, snapshot_contents: bool) -> Self {
+        self.shadow_options = if snapshot_contents {
+            Some(self.shadow_options.unwrap_or_default())
+        } else {
+            None
         };
+        self
+    }
 
-        // Process all pending events.
-        loop {
-            if let Some(timeout) = timeout {
-                match self.rx.recv_timeout(timeout) {
-                    Ok(Ok(event)) => {
-                        if is_sentinel_delete_event(&event) {
-                            self.process_pending_event(event);
-                            break;
-                        } else {
-                            self.process_pending_event(event)
-                        }
-                    }
-                    Ok(Err(e)) => {
-                        panic!("Error receiving event: {:?}", e);
-                    }
-                    Err(RecvTimeoutError::Timeout) => {
-                        return Err(ProcessError::Timeout(timeout));
-                    }
-                    Err(RecvTimeoutError::Disconnected) => {
-                        return Err(ProcessError::Disconnected);
-                    }
-                }
-            } else {
-                match self.rx.recv() {
-                    Ok(Ok(event)) => {
-                        if is_sentinel_delete_event(&event) {
-                            self.process_pending_event(event);
-                            break;
-                        } else {
-                            self.process_pending_event(event)
-                        }
-                    }
-                    Ok(Err(e)) => {
-                        panic!("Error receiving event: {:?}", e);
-                    }
-                    Err(RecvError) => {
-                        return Err(ProcessError::Disconnected);
-                    }
-                }
-            }
-        }
+    /// Store the shadow copies made for `snapshot_contents()` below `dir`,
+    /// rather than in the system temporary directory.
+    ///
+    /// The snapshot is stored in its own subdirectory, which is removed
+    /// again when the tracker is dropped, or when the whole tree has to be
+    /// copied again in `DirtyTracker::mark_clean()`. `dir` should not be
+    /// inside the watched tree.
+    ///
+    /// This implies `snapshot_contents(true)`.
+    pub fn backup_dir(mut self, dir: &Path) -> Self {
+        let mut options = self.shadow_options.unwrap_or_default();
+        options.dir = Some(dir.to_path_buf());
+        self.shadow_options = Some(options);
+        self
+    }
 
-        Ok(())
+    /// Flag the tracker once the number of dirty paths reaches `threshold`.
+    ///
+    /// This allows applications to notice that fine-grained tracking is no
+    /// longer worthwhile, and fall back to an operation on the whole tree.
+    /// See `DirtyTracker::threshold_exceeded()` and
+    /// `DirtyTracker::on_threshold_exceeded()`.
+    pub fn dirty_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = Some(threshold);
+        self
+    }
+
+    /// Record a directory instead of its contents once more than
+    /// `threshold` entries in it are dirty.
+    ///
+    /// This bounds the size of the dirty set when large numbers of files
+    /// change at once, e.g. when dependencies are installed. Changes below a
+    /// rolled-up directory are absorbed by it until the tree is marked
+    /// clean; see `DirtyTracker::rolled_up_dirs()`. Rolled-up directories
+    /// are reported in `DirtyTracker::paths()` regardless of
+    /// `directories()`.
+    pub fn rollup_threshold(mut self, threshold: usize) -> Self {
+        self.rollup_threshold = Some(threshold);
+        self
+    }
+
+    /// Never record more than `limit` dirty paths.
+    ///
+    /// This keeps the memory use of long-running trackers bounded. Once the
+    /// limit would be exceeded, the tracker either gives up until the tree
+    /// is marked clean or rescanned, or starts rolling up directories; see
+    /// `LimitPolicy`.
+    pub fn max_dirty_paths(mut self, limit: usize, policy: LimitPolicy) -> Self {
+        self.limit = Some((limit, policy));
+        self
+    }
+
+    /// Only track which directories up to `depth` levels below the root
+    /// contain changes, rather than which paths changed.
+    ///
+    /// This is meant for trees that are too large to keep a list of the
+    /// changed paths for. Changes deeper down than `depth` are recorded as
+    /// modifications of their ancestor at `depth`, so memory use is bounded
+    /// by the number of directories at that depth. The results are
+    /// approximate: `DirtyTracker::paths()` reports those directories rather
+    /// than what changed in them, and a directory stays dirty even if the
+    /// changes in it are undone. `DirtyTracker::is_subtree_dirty()` never
+    /// misses a change, but may report subtrees below `depth` as dirty when
+    /// something else in their ancestor changed.
+    pub fn approximate(mut self, depth: usize) -> Self {
+        self.approximate = Some(depth);
+        self
+    }
+
+    /// Make `DirtyTracker::relpaths()` return paths relative to `base`
+    /// rather than to the watched directory.
+    ///
+    /// This is useful if the watched directory is part of a larger tree,
+    /// such as a workspace, whose layout the paths should match. Dirty
+    /// paths that are not below `base` are left out.
+    pub fn relpath_base(mut self, base: &Path) -> Self {
+        self.relpath_base = Some(base.to_path_buf());
+        self
+    }
+
+    /// Whether to map event paths below the canonical form of the watched
+    /// directory back onto the watched directory.
+    ///
+    /// Watchers may report paths with symbolic links in the watched path
+    /// resolved; e.g. on macOS, changes below `/tmp` are reported below
+    /// `/private/tmp`. By default the tracker rewrites such paths, so that
+    /// all paths it reports start with the path it was created with.
+    pub fn canonicalize_paths(mut self, canonicalize: bool) -> Self {
+        self.canonicalize = canonicalize;
+        self
+    }
+
+    /// Compare paths case-insensitively, as case-insensitive file systems
+    /// (the default on Windows and macOS) do.
+    ///
+    /// Watchers may then report changes to a path with a different casing
+    /// each time. With this option, changes are recorded under the casing
+    /// they were first seen with, and queries such as
+    /// `DirtyTracker::is_path_dirty()` and `DirtyTracker::wait_for_path()`
+    /// accept any casing.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Convert paths below the watched directory to the Unicode
+    /// normalization form `form`.
+    ///
+    /// macOS reports file names in decomposed form (NFD), while most
+    /// applications use composed strings (NFC), so that paths that look
+    /// identical don't compare equal. With this option, recorded paths are
+    /// normalized, as are the paths passed to queries such as
+    /// `DirtyTracker::is_path_dirty()`.
+    pub fn unicode_normalization(mut self, form: UnicodeForm) -> Self {
+        self.unicode_form = Some(form);
+        self
+    }
+
+    /// Keep track of the identity (inode) of files, to recognize files that
+    /// were moved.
+    ///
+    /// With this option, `DirtyTracker::moved_from()` tells where a moved
+    /// file used to be, and the temporary names of files that were renamed
+    /// elsewhere in the tree (such as those editors save to before renaming
+    /// them over the original) are not reported as dirty. This records the
+    /// file id of every entry in the tree, and checks the file ids of the
+    /// dirty paths on every query while any of them don't exist.
+    ///
+    /// File ids are only available on Unix; elsewhere this has no effect.
+    pub fn track_file_ids(mut self, track: bool) -> Self {
+        self.track_file_ids = track;
+        self
+    }
+
+    /// Whether to leave out the temporary files editors create while a file
+    /// is being edited.
+    ///
+    /// This covers backup files (`file~`), Vim swap files (`.file.swp`) and
+    /// write probes (`4913`), and Emacs lock (`.#file`) and auto-save
+    /// (`#file#`) files. These rarely survive the editing session, but
+    /// would otherwise be reported while it lasts.
+    pub fn ignore_editor_files(mut self, ignore: bool) -> Self {
+        self.filter.editor_files = ignore;
+        self
+    }
+
+    /// Only record changes of the given kinds.
+    ///
+    /// By default changes of every kind make a path dirty. This can be used
+    /// to only track structural changes, for example, by leaving out
+    /// `ChangeKind::Modified`. Paths that are created and then removed again
+    /// are still left out, as long as creations are recorded or removals
+    /// aren't. `DirtyTracker::rescan()` reports paths that aren't in the
+    /// baseline as created, those that no longer exist as removed, and any
+    /// others as modified.
+    pub fn change_kinds(mut self, kinds: &[ChangeKind]) -> Self {
+        self.filter.kinds = Some(kinds.iter().copied().collect());
+        self
+    }
+
+    /// Whether to leave out changes that only affect metadata, such as
+    /// permissions, ownership or access times.
+    ///
+    /// Changes to the modification time are still recorded, as that is how
+    /// the polling watcher reports content changes unless
+    /// `poll_compare_contents()` is set. Note that `DirtyTracker::rescan()`
+    /// doesn't look at permissions or ownership to begin with.
+    pub fn ignore_metadata_changes(mut self, ignore: bool) -> Self {
+        self.filter.ignore_metadata = ignore;
+        self
+    }
+
+    /// Whether to record which files are opened, see
+    /// `DirtyTracker::accessed()`.
+    ///
+    /// Not all watchers report file accesses; of the native ones, only
+    /// inotify on Linux does. Files opened for writing are recorded too, as
+    /// are those read by the tracker itself, e.g. for `hash_contents()`.
+    pub fn track_access(mut self, track: bool) -> Self {
+        self.track_access = track;
+        self
+    }
+
+    /// How to report directories that are created, removed or modified.
+    ///
+    /// By default directories are part of `DirtyTracker::paths()` just like
+    /// files. Consumers that only care about files can leave them out, or
+    /// get them separately from `DirtyTracker::dirty_dirs()`. Either way,
+    /// only the files count towards the state of the tree.
+    pub fn directories(mut self, policy: DirectoryPolicy) -> Self {
+        self.directories = policy;
+        self
+    }
+
+    /// What to do with changes outside the tree, such as those to the
+    /// targets of symbolic links; see `SymlinkPolicy`.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlink_policy = policy;
+        self
+    }
+
+    /// How up to date queries such as `DirtyTracker::state()` should be.
+    ///
+    /// By default every query flushes pending events, which with the
+    /// default `SentinelFlush` strategy means writing a sentinel file and
+    /// waiting for its events, even if nothing changed. With
+    /// `Consistency::Eventual`, queries only process the events that have
+    /// already arrived, which is much cheaper for applications that poll
+    /// frequently, and returns right away for UI threads and hot loops that
+    /// can't wait. `DirtyTracker::sync()` still flushes.
+    pub fn consistency(mut self, consistency: Consistency) -> Self {
+        self.consistency = consistency;
+        self
+    }
+
+    /// Queue at most `capacity` events that have yet to be processed.
+    ///
+    /// By default, events queue up without limit until the tracker is
+    /// queried, which can take a lot of memory if the tree changes a lot in
+    /// the meantime. With a limit, events that don't fit are dropped and the
+    /// tracker enters the `State::Unknown` state with
+    /// `UnknownReason::Overflow`, as if the watcher had dropped them; see
+    /// `Stats::events_dropped`. This uses a thread to move events from the
+    /// backend into the queue.
+    ///
+    /// Events for sentinel directories are never dropped. Building the
+    /// tracker fails with `DirtyTrackerError::InvalidOption` if `capacity`
+    /// is 0.
+    pub fn event_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Take events off the watcher as soon as they are delivered, rather
+    /// than only when the tracker is queried.
+    ///
+    /// A thread moves events into the tracker's queue as they arrive, and
+    /// leaves out events that repeat the one most recently queued, such as
+    /// the stream of modifications of a file that is being written to.
+    ///
+    /// Once the tracker is turned into a `SharedTracker`, the thread also
+    /// records the queued events in the dirty set right away, unless the
+    /// tracker is in use at the time, so that the backlog doesn't grow
+    /// while the application is busy elsewhere. Callbacks such as
+    /// `on_change()` are then called from that thread. A plain
+    /// `DirtyTracker` can only record events when it is queried, so the
+    /// events wait in the queue until then.
+    pub fn eager_drain(mut self, eager: bool) -> Self {
+        self.eager_drain = eager;
+        self
+    }
+
+    /// Make it possible to create views of the tracker, with
+    /// `build_view()`.
+    ///
+    /// Views share the watcher of the tracker, so that several parts of a
+    /// program can track the same tree, each with their own dirty set,
+    /// without using up more watches. This uses a thread to pass the events
+    /// on to the views.
+    pub fn allow_views(mut self, allow: bool) -> Self {
+        self.allow_views = allow;
+        self
+    }
+
+    /// Hold back events from the watcher for `timeout` after they arrive,
+    /// and leave out the ones that turn out to be redundant.
+    ///
+    /// Repeats of the most recent event for the same paths are merged, as
+    /// happens for files that are written to in several steps, and the
+    /// halves of renames are paired up for backends that don't do so
+    /// themselves. Combine this with `ignore_editor_files()` to cut down on
+    /// the noise from editors.
+    ///
+    /// Queries wait for the held back events, so they take up to `timeout`
+    /// longer. Only available with the `debounce` feature.
+    #[cfg(feature = "debounce")]
+    pub fn debounce(mut self, timeout: std::time::Duration) -> Self {
+        self.debounce = Some(timeout);
+        self
+    }
+
+    /// Merge repeated notifications about a path within `window`.
+    ///
+    /// Only the first change of each kind to a path within the window is
+    /// passed to callbacks and subscribers, which cuts down on the traffic
+    /// for files that are written to in many small steps; later changes
+    /// are still recorded in the dirty set. Unlike `debounce()`, this
+    /// doesn't delay anything.
+    pub fn coalesce_window(mut self, window: std::time::Duration) -> Self {
+        self.coalesce_window = Some(window);
+        self
+    }
+
+    /// Send a copy of every event from the watcher to `tx`.
+    ///
+    /// This allows processing the raw events as well, without setting up
+    /// a second watcher for the same tree. Events are sent as the tracker
+    /// processes them, i.e. when it is queried, and exclude the events the
+    /// tracker causes itself, such as those for sentinel files. Once the
+    /// receiver is dropped, no more events are sent.
+    pub fn tee(mut self, tx: Sender<Event>) -> Self {
+        self.tee = Some(tx);
+        self
+    }
+
+    /// Keep the last `capacity` events from the watcher, for
+    /// `DirtyTracker::recent_events()`.
+    ///
+    /// Like `tee()`, this excludes the events the tracker causes itself.
+    pub fn keep_recent_events(mut self, capacity: usize) -> Self {
+        self.recent_events = Some(capacity);
+        self
+    }
+
+    /// Wait for events to settle rather than using a sentinel file.
+    ///
+    /// By default, the tracker makes sure all pending events have been
+    /// delivered by creating and removing a sentinel file in the watched
+    /// tree and waiting for the corresponding events. With this option it
+    /// instead processes events until none have arrived for `window`, so
+    /// queries never modify the watched tree.
+    ///
+    /// This is only as reliable as the chosen window: events that take
+    /// longer than `window` to be delivered are picked up by the next query
+    /// instead. Every query also takes at least `window`.
+    ///
+    /// This is shorthand for `flush_strategy(SettleFlush::new(window))`.
+    pub fn settle_window(self, window: std::time::Duration) -> Self {
+        self.flush_strategy(SettleFlush::new(window))
+    }
+
+    /// Use `strategy` to make sure all pending events have been delivered
+    /// before answering queries.
+    ///
+    /// This overrides `sentinel_dir()` and `sentinel_prefix()`, which only
+    /// apply to the default `SentinelFlush` strategy.
+    pub fn flush_strategy(mut self, strategy: impl FlushStrategy + 'static) -> Self {
+        self.flush = Some(Box::new(strategy));
+        self
+    }
+
+    /// Create sentinel files in `dir`, rather than in the root of the
+    /// watched tree.
+    ///
+    /// This can be a directory inside the tree (e.g. a dot-directory that
+    /// other tools ignore) or a directory elsewhere. In the latter case it
+    /// is watched as well, and events for it are not reported as dirty.
+    /// Note that this relies on the watcher delivering events for both
+    /// locations in order, which is the case for inotify.
+    pub fn sentinel_dir(mut self, dir: &Path) -> Self {
+        self.sentinel.dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Use `prefix` for the names of sentinel files.
+    pub fn sentinel_prefix(mut self, prefix: &str) -> Self {
+        self.sentinel.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Whether to check that there are enough inotify watches available
+    /// for the tree before watching it.
+    ///
+    /// inotify needs a watch for every directory, and running out halfway
+    /// through the tree means changes in the rest of it are missed. With
+    /// this check, `build()` instead fails with
+    /// `DirtyTrackerError::InsufficientWatches`. The check only applies to
+    /// the inotify backend. It is meant as a diagnostic, so it is disabled
+    /// by default: it walks the whole tree to count its directories, and
+    /// reads `/proc/*/fdinfo` to find the watches that are in use.
+    pub fn check_watches(mut self, check: bool) -> Self {
+        self.check_watches = check;
+        self
+    }
+
+    /// What to do if the recommended watcher for the platform polls the
+    /// tree, rather than being notified of changes by the operating system.
+    ///
+    /// This only applies to `build()`; backends passed to
+    /// `build_with_backend()` are always used.
+    pub fn polling_policy(mut self, policy: PollingPolicy) -> Self {
+        self.polling = policy;
+        self
+    }
+
+    /// How often to poll the tree, if the watcher polls.
+    ///
+    /// This applies to `build_polling()`, and to `build()` on platforms
+    /// where the recommended watcher polls. The default is notify's, which
+    /// is 30 seconds.
+    pub fn poll_interval(mut self, interval: std::time::Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// Whether a polling watcher should compare file contents, rather than
+    /// just modification times.
+    ///
+    /// Modification times are only compared at a granularity of seconds, so
+    /// this is needed to notice changes made in quick succession. It is
+    /// considerably more expensive, though.
+    pub fn poll_compare_contents(mut self, compare_contents: bool) -> Self {
+        self.poll_compare_contents = compare_contents;
+        self
+    }
+
+    /// Check, at most once every `interval`, that the watcher is still
+    /// working.
+    ///
+    /// A watcher that silently stops delivering events would otherwise make
+    /// the tracker report the tree as clean forever. When a check is due,
+    /// the next query creates and removes a file in a private directory, and
+    /// waits up to `timeout` for the watcher to report it. If it doesn't,
+    /// the tracker enters the `State::Unknown` state with
+    /// `UnknownReason::Unresponsive`.
+    ///
+    /// This has no effect for polling watchers.
+    pub fn watchdog(mut self, interval: std::time::Duration, timeout: std::time::Duration) -> Self {
+        self.watchdog = Some((interval, timeout));
+        self
+    }
+
+    /// Whether to keep tracking the watched directory if it is renamed.
+    ///
+    /// By default, the tracker enters the `State::Unknown` state with
+    /// `UnknownReason::RootMoved` when the directory is moved. With this
+    /// option, it instead finds the directory at its new location (by its
+    /// file id, among its former siblings), watches it there and reports
+    /// paths below the new location from then on; see
+    /// `DirtyTracker::path()`.
+    ///
+    /// File ids are only available on Unix, so elsewhere moves are always
+    /// reported as `UnknownReason::RootMoved`.
+    pub fn follow_root(mut self, follow: bool) -> Self {
+        self.follow_root = follow;
+        self
+    }
+
+    /// Whether to accept a directory that doesn't exist yet.
+    ///
+    /// By default, `build()` fails if the directory doesn't exist. With this
+    /// option, the tracker instead watches its parent (which must exist)
+    /// until the directory is created, and from then on watches the
+    /// directory itself. Until then the tree is clean; once it is created,
+    /// everything in it is reported as created.
+    pub fn allow_missing_root(mut self, allow: bool) -> Self {
+        self.allow_missing_root = allow;
+        self
+    }
+
+    /// Whether to start watching the directory again if it is replaced.
+    ///
+    /// Some tools replace a directory wholesale, by removing it or moving it
+    /// aside and then creating a new one in its place. By default this puts
+    /// the tracker in the `State::Unknown` state, with
+    /// `UnknownReason::RootRemoved` or `UnknownReason::RootMoved`. With this
+    /// option, the tracker instead waits for the directory to reappear and
+    /// watches the new one. Since anything in the tree may have changed, the
+    /// root itself and everything in the new tree are then reported as
+    /// modified.
+    ///
+    /// If `follow_root()` is set as well, a moved directory is followed
+    /// instead.
+    pub fn rearm_root(mut self, rearm: bool) -> Self {
+        self.rearm_root = rearm;
+        self
+    }
+
+    fn watcher_config(&self) -> notify::Config {
+        let mut config =
+            notify::Config::default().with_compare_contents(self.poll_compare_contents);
+        if let Some(interval) = self.poll_interval {
+            config = config.with_poll_interval(interval);
+        }
+        config
+    }
+
+    /// Returns the number of watches the recommended watcher for the
+    /// platform would need for the tree.
+    ///
+    /// See `DirtyTracker::estimated_watch_count()`.
+    pub fn estimated_watch_count(&self) -> std::io::Result<usize> {
+        backend::estimate_watches(<RecommendedWatcher as Watcher>::kind(), &self.path)
+    }
+
+    /// Create the tracker.
+    ///
+    /// # Returns
+    /// A new `DirtyTracker` object, or `DirtyTrackerError::Init` if the
+    /// watcher could not be set up.
+    pub fn build(self) -> Result<DirtyTracker, DirtyTrackerError> {
+        let config = self.watcher_config();
+
+        let kind = <RecommendedWatcher as Watcher>::kind();
+        self.polling.check(kind)?;
+        if kind == notify::WatcherKind::PollWatcher {
+            log_warn!("No native watcher available, polling instead");
+        }
+
+        self.build_with_backend(|tx| RecommendedWatcher::new(tx, config))
+    }
+
+    /// Create the tracker, polling the tree rather than using the
+    /// recommended watcher for the platform.
+    ///
+    /// See `poll_interval()` and `poll_compare_contents()`.
+    pub fn build_polling(self) -> Result<DirtyTracker, DirtyTrackerError> {
+        let config = self.watcher_config();
+        self.build_with_backend(|tx| notify::PollWatcher::new(tx, config))
+    }
+
+    /// Create the tracker, using the backend returned by `create` rather
+    /// than the recommended watcher for the platform.
+    ///
+    /// `create` is passed the sender that the backend should deliver its
+    /// events on.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::{DirtyTracker, State};
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    ///
+    /// let mut tracker = DirtyTracker::builder(td.path())
+    ///     .build_with_backend(|tx| notify::PollWatcher::new(tx, notify::Config::default()))
+    ///     .unwrap();
+    /// assert_eq!(tracker.state(), State::Clean);
+    /// ```
+    pub fn build_with_backend<B, F>(self, create: F) -> Result<DirtyTracker, DirtyTrackerError>
+    where
+        B: WatcherBackend + 'static,
+        F: FnOnce(EventSender) -> notify::Result<B>,
+    {
+        let views = self.allow_views.then(view::Views::default);
+        let (tx, rx, dropped) = self.channel(views.clone())?;
+
+        // Create a watcher object.
+        let watcher = create(tx).map_err(DirtyTrackerError::Init)?;
+
+        let (watcher, shared): (Box<dyn WatcherBackend>, _) = match views {
+            Some(views) => {
+                let shared = view::Shared::new(Box::new(watcher), views);
+                (
+                    Box::new(view::SharedWatcher::new(shared.clone())),
+                    Some(shared),
+                )
+            }
+            None => (Box::new(watcher), None),
+        };
+        let mut tracker = self.finish(watcher, rx, true)?;
+        tracker.dropped = dropped;
+        tracker.shared = shared;
+        Ok(tracker)
+    }
+
+    /// Create the tracker as a view of `tracker`, which receives the events
+    /// from its watcher rather than setting up another one.
+    ///
+    /// The view is a tracker in its own right, with its own dirty set and
+    /// options, which starts out clean. It keeps the watcher alive for as
+    /// long as it exists, even if `tracker` is dropped, and views can be
+    /// created of it in turn.
+    ///
+    /// Returns `DirtyTrackerError::ViewsNotAllowed` unless `tracker` was
+    /// built with `allow_views()`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::{DirtyTracker, State};
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let mut tracker = DirtyTracker::builder(td.path())
+    ///     .allow_views(true)
+    ///     .build()
+    ///     .unwrap();
+    /// let mut view = DirtyTracker::builder(td.path()).build_view(&tracker).unwrap();
+    /// std::fs::write(td.path().join("file"), b"hello").unwrap();
+    /// assert_eq!(view.state(), State::Dirty);
+    /// view.mark_clean();
+    /// assert_eq!(tracker.state(), State::Dirty);
+    /// ```
+    pub fn build_view(self, tracker: &DirtyTracker) -> Result<DirtyTracker, DirtyTrackerError> {
+        let shared = tracker
+            .shared
+            .clone()
+            .ok_or(DirtyTrackerError::ViewsNotAllowed)?;
+        self.build_shared(shared)
+    }
+
+    /// Create the tracker with the watcher of `pool`, rather than one of its
+    /// own.
+    ///
+    /// If the pool has no watcher yet, the recommended watcher for the
+    /// platform is created with the options of this builder. Views can be
+    /// created of trackers in a pool, as if they were built with
+    /// `allow_views()`.
+    pub fn build_in(self, pool: &WatcherPool) -> Result<DirtyTracker, DirtyTrackerError> {
+        let config = self.watcher_config();
+        self.polling
+            .check(<RecommendedWatcher as Watcher>::kind())?;
+        let shared = pool.shared(|tx| RecommendedWatcher::new(tx, config))?;
+        self.build_shared(shared)
+    }
+
+    fn build_shared(
+        self,
+        shared: std::sync::Arc<view::Shared>,
+    ) -> Result<DirtyTracker, DirtyTrackerError> {
+        let (tx, rx, dropped) = self.channel(None)?;
+        shared.views.lock().unwrap().push(tx);
+        let watcher = view::SharedWatcher::new(shared.clone());
+        let mut tracker = self.finish(Box::new(watcher), rx, true)?;
+        tracker.dropped = dropped;
+        tracker.shared = Some(shared);
+        Ok(tracker)
+    }
+
+    /// Create the channel to receive the events on, passing copies on to
+    /// `views` if set.
+    fn channel(
+        &self,
+        views: Option<view::Views>,
+    ) -> Result<(EventSender, queue::Receiver, queue::Dropped), DirtyTrackerError> {
+        if self.queue_capacity == Some(0) {
+            return Err(DirtyTrackerError::InvalidOption(
+                "the event queue capacity must be at least 1",
+            ));
+        }
+        let queue = queue::Options {
+            capacity: self.queue_capacity,
+            merge: self.eager_drain,
+            #[cfg(feature = "debounce")]
+            debounce: self.debounce,
+            views,
+        };
+        if queue.is_needed() {
+            queue::spawn(queue).map_err(DirtyTrackerError::Io)
+        } else {
+            let (tx, rx) = channel();
+            Ok((tx, rx.into(), queue::Dropped::default()))
+        }
+    }
+
+    /// Create the tracker with a `mock::MockWatcher` rather than a real
+    /// watcher, and return it along with a handle for injecting events.
+    ///
+    /// The tree is not scanned for a baseline unless a custom one is set
+    /// with `baseline()`, so the root does not need to exist.
+    /// Unless a different strategy is set, queries use `NoFlush`, which is
+    /// sufficient since injected events are delivered synchronously.
+    #[cfg(feature = "test-util")]
+    pub fn build_mock(self) -> Result<(DirtyTracker, mock::MockEvents), DirtyTrackerError> {
+        let (tx, rx) = channel();
+        let watcher = mock::MockWatcher::new(tx, notify::Config::default())
+            .map_err(DirtyTrackerError::Init)?;
+        let events = watcher.events();
+        Ok((self.finish(Box::new(watcher), rx.into(), false)?, events))
+    }
+
+    fn finish(
+        self,
+        mut watcher: Box<dyn WatcherBackend>,
+        rx: queue::Receiver,
+        scan: bool,
+    ) -> Result<DirtyTracker, DirtyTrackerError> {
+        // Watchers report paths below the form they were watched under, and
+        // the normalizer maps them back onto the root; sentinel files and
+        // the like are absolute, so the root has to be as well.
+        if self.filter.files.as_ref().is_some_and(HashSet::is_empty) {
+            return Err(DirtyTrackerError::InvalidOption(
+                "there are no files to track",
+            ));
+        }
+        let path = root::absolute(&self.path).map_err(DirtyTrackerError::Io)?;
+        let path = path.as_path();
+        let parent = match path.parent() {
+            Some(parent) if self.allow_missing_root && !path.exists() => Some(parent),
+            _ => None,
+        };
+
+        #[cfg(target_os = "linux")]
+        if self.check_watches
+            && self.filter.files.is_none()
+            && watcher.backend_kind() == notify::WatcherKind::Inotify
+        {
+            // If the tree can't be scanned, leave it to the watcher to report
+            // the problem.
+            if let (Some(available), Ok(needed)) = (
+                inotify::available_watches(),
+                backend::estimate_watches(notify::WatcherKind::Inotify, path),
+            ) {
+                if needed > available {
+                    return Err(DirtyTrackerError::InsufficientWatches { needed, available });
+                }
+            }
+        }
+
+        // Add a path to be watched. All files and directories at that path and below will be monitored for changes.
+        let watch_paths = match (parent, self.filter.file_dirs()) {
+            (_, Some(dirs)) => dirs
+                .into_iter()
+                .map(|dir| (dir, RecursiveMode::NonRecursive))
+                .collect(),
+            (Some(parent), None) => vec![(parent, RecursiveMode::NonRecursive)],
+            (None, None) => vec![(path, self.filter.recursive_mode())],
+        };
+        let mut normalizer = normalize::Normalizer::new(path);
+        for (watch_path, mode) in watch_paths {
+            watcher
+                .add_watch(watch_path, mode)
+                .map_err(|e| match e.kind {
+                    notify::ErrorKind::MaxFilesWatch => DirtyTrackerError::WatchLimit {
+                        limit: watch_limit(),
+                    },
+                    _ => DirtyTrackerError::Init(e),
+                })?;
+            if self.canonicalize {
+                // Leave paths alone if the tree can't be resolved; the watcher
+                // reports any problems with it.
+                let _ = normalizer.add_root(watch_path);
+            }
+        }
+
+        // Record the current state of the tree, so that we can recover if
+        // events are missed later on.
+        let baseline: Option<Box<dyn Baseline>> = match self.baseline {
+            BaselineOption::TreeSnapshot if !scan => None,
+            BaselineOption::TreeSnapshot if self.filter.files.is_some() => Some(Box::new(
+                snapshot::FileSnapshot::capture(self.filter.files.iter().flatten().cloned()),
+            )),
+            BaselineOption::TreeSnapshot if parent.is_some() => {
+                Some(Box::new(TreeSnapshot::default()))
+            }
+            BaselineOption::TreeSnapshot => Some(Box::new(
+                TreeSnapshot::capture(path).map_err(DirtyTrackerError::Io)?,
+            )),
+            BaselineOption::Custom(baseline) => Some(baseline),
+            BaselineOption::Disabled => None,
+        };
+
+        let contents = if !self.hash_contents {
+            None
+        } else if parent.is_some() {
+            Some(ContentSnapshot::default())
+        } else {
+            Some(ContentSnapshot::capture(path).map_err(DirtyTrackerError::Io)?)
+        };
+
+        let file_ids = if !self.track_file_ids {
+            None
+        } else if parent.is_some() {
+            Some(identity::FileIds::default())
+        } else {
+            Some(identity::FileIds::capture(path).map_err(DirtyTrackerError::Io)?)
+        };
+
+        let shadow = match self.shadow_options.as_ref() {
+            Some(options) if parent.is_some() => {
+                Some(Shadow::empty(path, options).map_err(DirtyTrackerError::Io)?)
+            }
+            Some(options) => Some(Shadow::capture(path, options).map_err(DirtyTrackerError::Io)?),
+            None => None,
+        };
+
+        let sentinel = self.sentinel;
+        let flush = match self.flush {
+            Some(flush) => flush,
+            None if sentinel.dir.is_some() || sentinel.prefix.is_some() => {
+                let mut flush = SentinelFlush::new();
+                if let Some(dir) = sentinel.dir.as_ref() {
+                    flush = flush.dir(&root::absolute(dir).map_err(DirtyTrackerError::Io)?);
+                }
+                if let Some(prefix) = sentinel.prefix.as_ref() {
+                    flush = flush.prefix(prefix);
+                }
+                Box::new(flush)
+            }
+            None => watcher.default_flush(),
+        };
+
+        let mut tracker = DirtyTracker {
+            path: path.to_path_buf(),
+            rx: normalize::EventReceiver::new(rx, normalizer),
+            paths: HashSet::new(),
+            created: relative::RelativeSet::new(path),
+            removed: relative::RelativeSet::new(path),
+            unknown: None,
+            baseline,
+            contents,
+            shadow,
+            shadow_options: self.shadow_options,
+            subscribers: Vec::new(),
+            tee: self.tee,
+            recent: self
+                .recent_events
+                .map(|capacity| (VecDeque::with_capacity(capacity), capacity)),
+            coalesce: self.coalesce_window.map(coalesce::Coalescer::new),
+            journal: None,
+            callbacks: Vec::new(),
+            threshold: self.threshold,
+            threshold_exceeded: false,
+            threshold_callbacks: Vec::new(),
+            transition_callbacks: Vec::new(),
+            published: State::Clean,
+            sequence: 0,
+            // Rolling up to stay within the limit needs the counts of dirty
+            // entries, even if directories are not rolled up otherwise.
+            rollup: match (self.rollup_threshold, self.limit) {
+                (Some(threshold), _) => Some(rollup::Rollup::new(threshold)),
+                (None, Some((_, LimitPolicy::RollUp))) => Some(rollup::Rollup::new(usize::MAX)),
+                (None, _) => None,
+            },
+            limit: self.limit,
+            approximate: self.approximate,
+            consistency: self.consistency,
+            dropped: queue::Dropped::default(),
+            shared: None,
+            relpath_base: match self.relpath_base {
+                Some(base) => root::absolute(&base).map_err(DirtyTrackerError::Io)?,
+                None => path.to_path_buf(),
+            },
+            case_folded: if self.case_insensitive {
+                Some(HashMap::new())
+            } else {
+                None
+            },
+            unicode_form: self.unicode_form,
+            flush: Some(flush),
+            ignored_dirs: Vec::new(),
+            late_sentinels: HashSet::new(),
+            watchdog: self
+                .watchdog
+                .map(|(interval, timeout)| watchdog::Watchdog::new(interval, timeout)),
+            root_id: identity::file_id(path),
+            parent_watch: parent.map(Path::to_path_buf),
+            root_missing: parent.is_some(),
+            root_replaced: false,
+            root_rewatched: 0,
+            eager_drain: self.eager_drain,
+            rearm_root: self.rearm_root,
+            follow_root: self.follow_root,
+            symlink_policy: self.symlink_policy,
+            filter: self.filter,
+            directories: self.directories,
+            dirs: HashSet::new(),
+            accessed: self.track_access.then(HashSet::new),
+            file_ids,
+            renames: HashMap::new(),
+            rename_candidates: HashSet::new(),
+            stats: Stats::default(),
+            metrics: telemetry::Metrics::new(path),
+            #[cfg(feature = "tokio")]
+            state_tx: None,
+            watcher,
+        };
+        // The root may have been created before its parent was watched.
+        if tracker.root_missing && tracker.path.is_dir() {
+            tracker.root_created();
+        }
+        if tracker.symlink_policy == SymlinkPolicy::Follow && !tracker.root_missing {
+            let mut links = Vec::new();
+            let _ = snapshot::walk(path, &mut |path, metadata| {
+                if metadata.file_type().is_symlink() {
+                    links.push(path);
+                }
+            });
+            for link in links {
+                tracker.watch_link_target(&link);
+            }
+        }
+        Ok(tracker)
+    }
+}
+
+impl DirtyTracker {
+    /// Create a new tracker object.
+    ///
+    /// # Arguments
+    /// * `path` - The path to the directory to watch.
+    ///
+    /// # Returns
+    /// A new `DirtyTracker` object, or `DirtyTrackerError::Init` if the
+    /// watcher could not be set up.
+    pub fn new(path: &Path) -> Result<Self, DirtyTrackerError> {
+        DirtyTrackerBuilder::new(path).build()
+    }
+
+    /// Create a tracker for just the files in `files`, with the default
+    /// options; see `DirtyTrackerBuilder::for_files()`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::DirtyTracker;
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let config = td.path().join("config.toml");
+    /// let mut tracker = DirtyTracker::for_files(&[config.clone()]).unwrap();
+    /// std::fs::write(td.path().join("unrelated"), b"").unwrap();
+    /// std::fs::write(&config, b"").unwrap();
+    /// assert_eq!(tracker.paths().unwrap().len(), 1);
+    /// assert!(tracker.is_path_dirty(&config).unwrap());
+    /// ```
+    pub fn for_files(files: &[PathBuf]) -> Result<Self, DirtyTrackerError> {
+        DirtyTrackerBuilder::for_files(files).build()
+    }
+
+    /// Create a builder for a tracker watching `path`.
+    pub fn builder(path: &Path) -> DirtyTrackerBuilder {
+        DirtyTrackerBuilder::new(path)
+    }
+
+    /// Returns the directory being tracked.
+    ///
+    /// This is the path the tracker was created with, unless it followed the
+    /// directory to a new location; see `DirtyTrackerBuilder::follow_root()`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Mark all files as clean.
+    ///
+    /// Note that this can race with file modifications, so it's only safe
+    /// if you're sure that no modifications are happening.
+    ///
+    /// This also records a new baseline for `rescan()`.
+    pub fn mark_clean(&mut self) {
+        self.take_clean();
+    }
+
+    /// Mark all files as clean, like `mark_clean()`, and return the paths
+    /// that were dirty.
+    fn take_clean(&mut self) -> HashSet<PathBuf> {
+        let flushed = self.process_pending(None).is_ok();
+        if let Some(options) = self.shadow_options.as_ref() {
+            if !(flushed && self.update_shadow()) {
+                // Drop the old shadow first, so we don't temporarily need
+                // twice the disk space.
+                self.shadow = None;
+                self.shadow = Shadow::capture(&self.path, options).ok();
+            }
+        }
+        self.reset()
+    }
+
+    /// Record the tree as it is now as the clean one, and forget about the
+    /// changes recorded so far, returning the paths that were dirty.
+    ///
+    /// This doesn't touch the shadow copy, which callers take care of.
+    fn reset(&mut self) -> HashSet<PathBuf> {
+        if let Some(baseline) = self.baseline.as_mut() {
+            // Keep the old baseline if it can't be updated; rescan() then
+            // reports more than has changed, rather than being unavailable.
+            if let Err(e) = baseline.reset(&self.path) {
+                log_warn!("Unable to reset baseline: {}", e);
+            }
+        }
+        if self.contents.is_some() {
+            self.contents = ContentSnapshot::capture(&self.path).ok();
+        }
+        if self.file_ids.is_some() {
+            self.file_ids = identity::FileIds::capture(&self.path).ok();
+        }
+        self.unknown = None;
+        let paths = std::mem::take(&mut self.paths);
+        self.dirs.clear();
+        self.created.clear();
+        self.removed.clear();
+        if let Some(rollup) = self.rollup.as_mut() {
+            rollup.clear();
+        }
+        if let Some(coalesce) = self.coalesce.as_mut() {
+            coalesce.clear();
+        }
+        self.renames.clear();
+        self.rename_candidates.clear();
+        if let Some(accessed) = self.accessed.as_mut() {
+            accessed.clear();
+        }
+        self.reindex_case();
+        self.threshold_exceeded = false;
+        self.publish_state();
+        paths
+    }
+
+    /// Bring the shadow copy up to date by copying the dirty paths into it.
+    ///
+    /// Returns false if that isn't enough to bring it up to date, because
+    /// changes may have gone unrecorded.
+    fn update_shadow(&self) -> bool {
+        let shadow = match self.shadow.as_ref() {
+            Some(shadow) => shadow,
+            None => return false,
+        };
+        if self.unknown.is_some() || self.approximate.is_some() || self.filter.kinds.is_some() {
+            return false;
+        }
+        let mut paths: Vec<_> = self.paths.iter().chain(self.dirs.iter()).collect();
+        // Update parents before their children, so that directories exist
+        // by the time files are copied into them.
+        paths.sort_by_key(|p| p.components().count());
+        paths.into_iter().all(|path| match shadow.update(path) {
+            Ok(()) => true,
+            Err(e) => {
+                log_warn!("Unable to update shadow copy of {}: {}", path.display(), e);
+                false
+            }
+        })
+    }
+
+    /// Record a checkpoint, which changes can later be compared against.
+    ///
+    /// Pending events are processed first, so the changes made before the
+    /// call are recorded before the checkpoint, and those made after it
+    /// after the checkpoint. If they can't be flushed, this returns the
+    /// error rather than record a checkpoint that changes would end up on
+    /// the wrong side of. The checkpoint is in use until it is passed to
+    /// `release()`.
+    ///
+    /// Together with `mark_clean_at()`, this avoids the race between
+    /// reading the dirty paths and marking them clean:
+    ///
+    /// ```rust
+    /// use dirty_tracker::DirtyTracker;
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let mut tracker = DirtyTracker::new(td.path()).unwrap();
+    /// std::fs::write(td.path().join("a"), b"a").unwrap();
+    /// let checkpoint = tracker.checkpoint().unwrap();
+    /// let paths = tracker.paths().unwrap().clone();
+    /// // Changes made while processing the paths are kept.
+    /// std::fs::write(td.path().join("b"), b"b").unwrap();
+    /// tracker.mark_clean_at(checkpoint).unwrap();
+    /// assert_eq!(
+    ///     tracker.paths().unwrap(),
+    ///     &std::collections::HashSet::from([td.path().join("b")])
+    /// );
+    /// ```
+    pub fn checkpoint(&mut self) -> Result<Generation, DirtyTrackerError> {
+        self.process_pending(None)?;
+        Ok(self
+            .journal
+            .get_or_insert_with(journal::Journal::new)
+            .checkpoint())
+    }
+
+    /// Release `checkpoint`, which is no longer needed.
+    ///
+    /// Several checkpoints can be in use at the same time, e.g. by different
+    /// parts of an application that each process the changes at their own
+    /// pace. The tracker only has to remember when paths changed for as far
+    /// back as the oldest checkpoint that hasn't been released, and nothing
+    /// at all once they all have been. Checkpoints should not be used after
+    /// they have been released.
+    pub fn release(&mut self, checkpoint: Generation) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.release(checkpoint);
+        }
+    }
+
+    /// Mark the paths that did not change after `checkpoint` as clean.
+    ///
+    /// Unlike `mark_clean()`, this does not record a new baseline, so
+    /// `rescan()` still reports the paths marked clean. Neither does it
+    /// leave the `State::Unknown` state, as it is unknown when the changes
+    /// that were missed were made. If pending events can't be flushed, this
+    /// returns the error and leaves the dirty set as it is.
+    pub fn mark_clean_at(&mut self, checkpoint: Generation) -> Result<(), DirtyTrackerError> {
+        self.process_pending(None)?;
+        let journal = match self.journal.as_ref() {
+            Some(journal) => journal,
+            None => return Ok(()),
+        };
+        let clean = self
+            .paths
+            .iter()
+            .filter(|path| !journal.changed_since(path, checkpoint))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.dirs
+            .retain(|path| journal.changed_since(path, checkpoint));
+        self.renames
+            .retain(|path, _| journal.changed_since(path, checkpoint));
+        for path in clean {
+            if let Some(rollup) = self.rollup.as_mut() {
+                rollup.unroll(&path);
+            }
+            self.forget(&path);
+        }
+        self.reindex_case();
+        self.threshold_exceeded = false;
+        self.check_threshold();
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Rebuild the dirty set by scanning the watched tree.
+    ///
+    /// With `DirtyTrackerBuilder::tree_snapshot()`, this compares the size
+    /// and modification time of every file against the baseline recorded at
+    /// construction or the last `mark_clean()`. It can be used to recover
+    /// from the `State::Unknown` state, e.g. after the watcher dropped
+    /// events.
+    ///
+    /// Returns `DirtyTrackerError::NoBaseline` if the tracker was built
+    /// without a baseline.
+    pub fn rescan(&mut self) -> Result<(), DirtyTrackerError> {
+        match self.process_pending(None) {
+            // Lost events are what the rescan makes up for.
+            Err(DirtyTrackerError::Unknown(UnknownReason::Overflow)) => {}
+            result => result?,
+        }
+        let baseline = self
+            .baseline
+            .as_ref()
+            .ok_or(DirtyTrackerError::NoBaseline)?;
+        let paths = baseline
+            .dirty_paths(&self.path)
+            .map_err(DirtyTrackerError::Io)?;
+        let mut kinds = HashMap::new();
+        for path in paths {
+            let kind = if !baseline.contains(&path) {
+                ChangeKind::Created
+            } else if std::fs::symlink_metadata(&path).is_err() {
+                ChangeKind::Removed
+            } else {
+                ChangeKind::Modified
+            };
+            if self.filter.excludes(&path) || !self.filter.records(kind) {
+                continue;
+            }
+            let path = self.normalize_unicode(&path).into_owned();
+            match self.approximated(&path) {
+                Some(dir) => kinds.insert(dir, ChangeKind::Modified),
+                None => kinds.insert(path, kind),
+            };
+        }
+        let previous = std::mem::replace(&mut self.paths, kinds.keys().cloned().collect());
+        self.created.clear();
+        self.removed.clear();
+        for (path, kind) in &kinds {
+            match kind {
+                ChangeKind::Created => self.created.insert(path.clone()),
+                ChangeKind::Removed => self.removed.insert(path.clone()),
+                _ => false,
+            };
+        }
+        let previous_dirs = std::mem::take(&mut self.dirs);
+        if self.directories != DirectoryPolicy::Include {
+            let (dirs, files) = std::mem::take(&mut self.paths)
+                .into_iter()
+                .partition(|path| self.is_dir(path));
+            self.paths = files;
+            self.dirs = dirs;
+        }
+        if let Some(rollup) = self.rollup.as_mut() {
+            for dir in rollup.recount(self.paths.iter()) {
+                self.roll_up(dir);
+            }
+        }
+        self.check_limit();
+        // The paths that were already dirty keep the generations they were
+        // recorded in. When the others changed is unknown, so they are
+        // attributed to the current one.
+        if let Some(journal) = self.journal.as_mut() {
+            for (path, kind) in &kinds {
+                if !previous.contains(path) && !previous_dirs.contains(path) {
+                    journal.stamp(path, *kind);
+                }
+            }
+        }
+        // The events that described renames are no longer relevant.
+        self.renames.clear();
+        self.rename_candidates.clear();
+        self.reindex_case();
+        self.unknown = None;
+        // Which paths changed is unknown, so the rescan counts as a change.
+        self.sequence += 1;
+        self.stats.rescans += 1;
+        self.metrics.rescan();
+        self.stats.peak_dirty = self.stats.peak_dirty.max(self.paths.len());
+        self.check_threshold();
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Drop dirty paths whose contents have not actually changed.
+    ///
+    /// Events are also reported for files that were touched or rewritten
+    /// with identical contents. This compares every dirty path against the
+    /// content hashes recorded at construction or the last `mark_clean()`,
+    /// and removes those that are unchanged from the dirty set.
+    ///
+    /// Returns `DirtyTrackerError::NoBaseline` unless the tracker was built
+    /// with `DirtyTrackerBuilder::hash_contents()`, and
+    /// `DirtyTrackerError::Unknown` if the state is unknown, since the dirty
+    /// set is incomplete.
+    pub fn verify(&mut self) -> Result<(), DirtyTrackerError> {
+        self.process_pending(None)?;
+        let contents = self
+            .contents
+            .as_ref()
+            .ok_or(DirtyTrackerError::NoBaseline)?;
+        if let Some(reason) = self.unknown.clone() {
+            return Err(DirtyTrackerError::Unknown(reason));
+        }
+        let mut unchanged = Vec::new();
+        for path in self.paths.iter() {
+            if contents.unchanged(path).map_err(DirtyTrackerError::Io)? {
+                unchanged.push(path.clone());
+            }
+        }
+        for path in unchanged {
+            self.forget(&path);
+        }
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Restore all dirty paths to their original contents.
+    ///
+    /// Files and directories that were modified or removed are restored from
+    /// the shadow copy, and those that were created are removed. Afterwards
+    /// the tracker is clean.
+    ///
+    /// Like `mark_clean()`, this can race with file modifications.
+    ///
+    /// Returns `DirtyTrackerError::NoBaseline` unless the tracker was built
+    /// with `DirtyTrackerBuilder::snapshot_contents()`, and
+    /// `DirtyTrackerError::Unknown` if the state is unknown, since not all
+    /// changes would be undone.
+    pub fn revert(&mut self) -> Result<(), DirtyTrackerError> {
+        self.process_pending(None)?;
+        let shadow = self.shadow.as_ref().ok_or(DirtyTrackerError::NoBaseline)?;
+        if let Some(reason) = self.unknown.clone() {
+            return Err(DirtyTrackerError::Unknown(reason));
+        }
+        let mut paths: Vec<_> = self.paths.iter().chain(self.dirs.iter()).collect();
+        // Restore parents before their children, so that directories exist
+        // by the time files are copied into them.
+        paths.sort_by_key(|p| p.components().count());
+        for path in paths {
+            shadow.restore(path).map_err(DirtyTrackerError::Io)?;
+        }
+        // Process the events for our own changes, so they don't show up
+        // later.
+        self.process_pending(None)?;
+        self.reset();
+        Ok(())
+    }
+
+    /// Restore a single path to its original contents.
+    ///
+    /// If the path did not exist originally, it is removed. If it is a
+    /// directory, only the directory itself is restored, not its contents.
+    ///
+    /// Returns `DirtyTrackerError::NoBaseline` unless the tracker was built
+    /// with `DirtyTrackerBuilder::snapshot_contents()`.
+    pub fn revert_path(&mut self, path: &Path) -> Result<(), DirtyTrackerError> {
+        let path = self.recorded_path(path);
+        let shadow = self.shadow.as_ref().ok_or(DirtyTrackerError::NoBaseline)?;
+        shadow.restore(&path).map_err(DirtyTrackerError::Io)?;
+        self.process_pending(None)?;
+        self.forget(&path);
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Returns a unified diff of all changes to dirty files.
+    ///
+    /// This compares the original contents of each dirty file against its
+    /// current contents. Binary files are reported as differing, without
+    /// showing their contents; directories and symlinks are skipped.
+    ///
+    /// Returns `DirtyTrackerError::NoBaseline` unless the tracker was built
+    /// with `DirtyTrackerBuilder::snapshot_contents()`.
+    pub fn diff(&mut self) -> Result<String, DirtyTrackerError> {
+        self.process_pending(None)?;
+        let mut paths: Vec<_> = self.paths.iter().cloned().collect();
+        paths.sort();
+        let mut ret = String::new();
+        for path in paths {
+            ret.push_str(&self.diff_one(&path)?);
+        }
+        Ok(ret)
+    }
+
+    /// Returns a unified diff of the changes to a single file.
+    ///
+    /// The diff is empty if the file is unchanged.
+    pub fn diff_path(&mut self, path: &Path) -> Result<String, DirtyTrackerError> {
+        self.process_pending(None)?;
+        let path = self.recorded_path(path);
+        self.diff_one(&path)
+    }
+
+    fn diff_one(&self, path: &Path) -> Result<String, DirtyTrackerError> {
+        let shadow = self.shadow.as_ref().ok_or(DirtyTrackerError::NoBaseline)?;
+        let old = shadow
+            .original_contents(path)
+            .map_err(DirtyTrackerError::Io)?;
+        let new = shadow::read_file(path).map_err(DirtyTrackerError::Io)?;
+        let relpath = path.strip_prefix(&self.path).unwrap_or(path);
+        Ok(diff::unified_diff(relpath, old.as_deref(), new.as_deref()))
+    }
+
+    /// Returns true if there are dirty files.
+    #[deprecated(since = "0.2.0", note = "Use state() instead")]
+    pub fn is_dirty(&mut self) -> bool {
+        self.state() == State::Dirty
+    }
+
+    /// Returns an estimate of the number of watches the tracker is using.
+    ///
+    /// For inotify this is the number of directories in the tree, which
+    /// count against `fs.inotify.max_user_watches`; for kqueue it is the
+    /// number of open file descriptors. Other backends use a single watch
+    /// for the tree, or none at all. The tree is scanned to compute this,
+    /// so it reflects the current tree rather than when the tracker was
+    /// created.
+    pub fn estimated_watch_count(&self) -> std::io::Result<usize> {
+        let extra = match self.watcher.backend_kind() {
+            notify::WatcherKind::PollWatcher | notify::WatcherKind::NullWatcher => 0,
+            _ => self.ignored_dirs.len(),
+        };
+        Ok(backend::estimate_watches(self.watcher.backend_kind(), &self.path)? + extra)
+    }
+
+    /// Returns counters describing the activity of the tracker.
+    ///
+    /// This does not process pending events, so it reflects the tracker as
+    /// of the last query.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            dirty: self.paths.len(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Returns the kind of watcher backend in use.
+    pub fn watcher_kind(&self) -> notify::WatcherKind {
+        self.watcher.backend_kind()
+    }
+
+    /// Returns true if the watcher polls the tree, rather than being
+    /// notified of changes by the operating system.
+    ///
+    /// Polling watchers only notice changes some time after they happen,
+    /// and may miss changes that are undone quickly.
+    pub fn is_polling(&self) -> bool {
+        self.watcher_kind() == notify::WatcherKind::PollWatcher
+    }
+
+    /// Returns the state of the tracker.
+    pub fn state(&mut self) -> State {
+        self.state_detail().0
+    }
+
+    /// Returns the state of the tracker, along with the reason if it is
+    /// unknown.
+    pub fn state_detail(&mut self) -> (State, Option<UnknownReason>) {
+        match self.try_state() {
+            Ok(state) => (state, None),
+            Err(reason) => (State::Unknown, Some(reason)),
+        }
+    }
+
+    /// Returns the state of the tracker.
+    ///
+    /// Unlike `state()`, this returns the reason as an error if the state is
+    /// unknown, so it will never return `Ok(State::Unknown)`.
+    pub fn try_state(&mut self) -> Result<State, UnknownReason> {
+        self.refresh()?;
+        if self.paths.is_empty() {
+            Ok(State::Clean)
+        } else {
+            Ok(State::Dirty)
+        }
+    }
+
+    /// Returns the state of the tracker, waiting at most `timeout` for
+    /// pending events to be delivered.
+    ///
+    /// `state()` waits for as long as it takes, which can be forever if the
+    /// watcher loses the events of the flush. This returns
+    /// `DirtyTrackerError::Timeout` instead, and leaves the state alone, so
+    /// a later query can try again.
+    pub fn state_timeout(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> Result<State, DirtyTrackerError> {
+        match self.refresh_within(Some(timeout)) {
+            Ok(()) if self.paths.is_empty() => Ok(State::Clean),
+            Ok(()) => Ok(State::Dirty),
+            Err(UnknownReason::Timeout(timeout)) if self.unknown.is_none() => {
+                Err(DirtyTrackerError::Timeout(timeout))
+            }
+            Err(_) => Ok(State::Unknown),
+        }
+    }
+
+    /// Returns the sequence number of the last change the tracker processed,
+    /// or 0 if there were none.
+    ///
+    /// Every change is numbered, in the order they are processed, and the
+    /// numbers keep increasing for the life of the tracker, also across
+    /// `mark_clean()`. If the number is the same as before, nothing changed
+    /// in between. A `rescan()` counts as a change, as does entering the
+    /// `State::Unknown` state. Subscribers receive the number along with
+    /// each change they are notified of.
+    ///
+    /// This doesn't process pending events, so it only includes the changes
+    /// up to the last query, or `sync()`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::DirtyTracker;
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let mut tracker = DirtyTracker::new(td.path()).unwrap();
+    /// let seen = tracker.sequence();
+    /// std::fs::write(td.path().join("file"), b"hello").unwrap();
+    /// tracker.sync().unwrap();
+    /// assert!(tracker.sequence() > seen);
+    /// ```
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns the approximate number of events that were delivered by the
+    /// watcher, but not yet processed.
+    ///
+    /// Events are only processed when the tracker is queried, so this grows
+    /// while the application is busy elsewhere. A large backlog means the
+    /// next query will take a while, and takes up memory in the meantime;
+    /// applications can watch it to query the tracker before that happens,
+    /// or to decide to use `DirtyTrackerBuilder::eager_drain()`. The count
+    /// includes the tracker's own events, e.g. for sentinel files.
+    pub fn pending_events(&self) -> usize {
+        self.rx.pending()
+    }
+
+    /// Returns the paths of the dirty files.
+    ///
+    /// If the tracker is in an unknown state, this will return None.
+    pub fn paths(&mut self) -> Option<&HashSet<PathBuf>> {
+        self.try_paths().ok()
+    }
+
+    /// Returns an iterator over the paths of the dirty files.
+    ///
+    /// Unlike cloning `paths()`, this doesn't copy the paths. If the tracker
+    /// is in an unknown state, this will return None.
+    pub fn paths_iter(&mut self) -> Option<impl Iterator<Item = &Path> + '_> {
+        Some(self.paths()?.iter().map(PathBuf::as_path))
+    }
+
+    /// Take the paths of the dirty files, and mark the tree as clean.
+    ///
+    /// This hands over the paths without copying them, and is otherwise
+    /// equivalent to calling `paths()` followed by `mark_clean()`, except
+    /// that changes processed in between are included. If the tracker is in
+    /// an unknown state, this will return None and leave it as it is.
+    pub fn drain_paths(&mut self) -> Option<impl Iterator<Item = PathBuf>> {
+        self.try_paths().ok()?;
+        Some(self.take_clean().into_iter())
+    }
+
+    /// Returns the paths of the dirty files that changed after
+    /// `checkpoint`, which was returned by `checkpoint()`.
+    ///
+    /// Paths that changed both before and after the checkpoint are
+    /// included; those that only changed before it are not. If the tracker
+    /// is in an unknown state, this will return None.
+    pub fn dirty_since(&mut self, checkpoint: Generation) -> Option<HashSet<&Path>> {
+        self.try_paths().ok()?;
+        let journal = self.journal.as_ref();
+        Some(
+            self.paths
+                .iter()
+                .filter(|path| {
+                    journal.is_some_and(|journal| journal.changed_since(path, checkpoint))
+                })
+                .map(PathBuf::as_path)
+                .collect(),
+        )
+    }
+
+    /// Returns the paths that changed after checkpoint `from`, but before
+    /// checkpoint `to`, along with the kind of change this amounted to.
+    ///
+    /// Unlike `dirty_since()`, this includes paths that are no longer
+    /// dirty, e.g. because they were marked clean since. Changes are only
+    /// remembered for as far back as the oldest checkpoint that hasn't been
+    /// released. If the tracker is in an unknown state, this will return
+    /// None.
+    pub fn changes_between(
+        &mut self,
+        from: Generation,
+        to: Generation,
+    ) -> Option<BTreeMap<&Path, ChangeKind>> {
+        self.try_paths().ok()?;
+        Some(match self.journal.as_ref() {
+            Some(journal) => journal.between(from, to).collect(),
+            None => BTreeMap::new(),
+        })
+    }
+
+    /// Returns whether `dir`, or anything below it, is dirty.
+    ///
+    /// If the tracker is in an unknown state, this will return None.
+    pub fn is_subtree_dirty(&mut self, dir: &Path) -> Option<bool> {
+        self.try_paths().ok()?;
+        let dir = self.normalize_unicode(dir).into_owned();
+        let dir = self.approximated(&dir).unwrap_or(dir);
+        Some(
+            self.paths
+                .iter()
+                .chain(self.dirs.iter())
+                .any(|path| path.starts_with(&dir)),
+        )
+    }
+
+    /// Returns the paths of the dirty files, in sorted order.
+    ///
+    /// This is the same set as `paths()`, for callers that need a
+    /// deterministic order, e.g. for reports. If the tracker is in an
+    /// unknown state, this will return None.
+    pub fn sorted_paths(&mut self) -> Option<BTreeSet<&Path>> {
+        Some(self.paths()?.iter().map(PathBuf::as_path).collect())
+    }
+
+    /// Returns whether `path` is dirty.
+    ///
+    /// Unlike looking `path` up in `paths()`, this honours
+    /// `DirtyTrackerBuilder::case_insensitive()` and
+    /// `DirtyTrackerBuilder::unicode_normalization()`. If the tracker is in an
+    /// unknown state, this will return None.
+    pub fn is_path_dirty(&mut self, path: &Path) -> Option<bool> {
+        self.try_paths().ok()?;
+        Some(self.dirty_entry(path).is_some())
+    }
+
+    /// Returns the renames since the tree was clean, as pairs of the old and
+    /// the new path, sorted by the new path.
+    ///
+    /// Successive renames of the same entry are reported as one. Both paths
+    /// are in the dirty set as well. Renames are only reported by watchers
+    /// that can tell both sides of a rename apart, such as inotify; others
+    /// report the paths as modified instead. Returns None if the state is
+    /// unknown.
+    pub fn renames(&mut self) -> Option<Vec<(PathBuf, PathBuf)>> {
+        self.try_paths().ok()?;
+        let mut renames = self
+            .renames
+            .iter()
+            .map(|(new, old)| (old.clone(), new.clone()))
+            .collect::<Vec<_>>();
+        renames.sort_by(|a, b| a.1.cmp(&b.1));
+        Some(renames)
+    }
+
+    /// Returns the path the file at `path` had when the tree was last clean,
+    /// if it was moved since.
+    ///
+    /// Returns None if the file wasn't moved, if the state is unknown, or
+    /// unless the tracker was built with
+    /// `DirtyTrackerBuilder::track_file_ids()`.
+    pub fn moved_from(&mut self, path: &Path) -> Option<PathBuf> {
+        self.try_paths().ok()?;
+        self.file_ids
+            .as_ref()?
+            .moved_from(path)
+            .map(Path::to_path_buf)
+    }
+
+    /// Returns the paths of the dirty files.
+    ///
+    /// If the tracker is in an unknown state, this will return the reason.
+    pub fn try_paths(&mut self) -> Result<&HashSet<PathBuf>, UnknownReason> {
+        self.refresh()?;
+        Ok(&self.paths)
+    }
+
+    /// Returns the paths of the dirty files, waiting at most `timeout` for
+    /// pending events to be delivered; see `state_timeout()`.
+    ///
+    /// If the tracker is in an unknown state, this returns
+    /// `DirtyTrackerError::Unknown` with the reason.
+    pub fn paths_timeout(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> Result<&HashSet<PathBuf>, DirtyTrackerError> {
+        match self.refresh_within(Some(timeout)) {
+            Ok(()) => Ok(&self.paths),
+            Err(UnknownReason::Timeout(timeout)) if self.unknown.is_none() => {
+                Err(DirtyTrackerError::Timeout(timeout))
+            }
+            Err(reason) => Err(DirtyTrackerError::Unknown(reason)),
+        }
+    }
+
+    /// Returns the dirty paths along with their types, sorted by path.
+    ///
+    /// The types are determined at the time of the call; for paths that
+    /// have since been removed, they are taken from what the tracker knows
+    /// about them, and may not be known at all. If the tracker is in an
+    /// unknown state, this will return None.
+    pub fn entries(&mut self) -> Option<Vec<DirtyEntry>> {
+        self.refresh().ok()?;
+        let mut entries = self
+            .paths
+            .iter()
+            .map(|path| {
+                let (file_type, exists) = self.file_type(path);
+                DirtyEntry {
+                    path: path.clone(),
+                    file_type,
+                    exists,
+                }
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Some(entries)
+    }
+
+    /// Returns the dirty paths grouped by the directory they are in.
+    ///
+    /// The paths in each group are sorted. If the tracker is in an unknown
+    /// state, this will return None.
+    pub fn paths_by_dir(&mut self) -> Option<BTreeMap<PathBuf, Vec<PathBuf>>> {
+        self.refresh().ok()?;
+        let mut groups = BTreeMap::<PathBuf, Vec<PathBuf>>::new();
+        for path in self.paths.iter() {
+            let dir = path.parent().unwrap_or(path);
+            groups
+                .entry(dir.to_path_buf())
+                .or_default()
+                .push(path.clone());
+        }
+        for paths in groups.values_mut() {
+            paths.sort();
+        }
+        Some(groups)
+    }
+
+    /// Returns a summary of the changes, for logging or for describing a
+    /// change.
+    ///
+    /// The summary counts the dirty paths by kind and lists them relative
+    /// to the `relpath_base()`; format it with `Display`. If the tracker is
+    /// in an unknown state, this returns the reason.
+    pub fn report(&mut self) -> Result<Report, UnknownReason> {
+        self.refresh()?;
+        let renamed = self
+            .renames
+            .iter()
+            .flat_map(|(new, old)| [new, old])
+            .collect::<HashSet<_>>();
+        let changes = self
+            .paths
+            .iter()
+            .chain(self.dirs.iter())
+            .filter(|path| !renamed.contains(path))
+            .map(|path| (path.clone(), self.change_kind(path)))
+            .collect::<Vec<_>>();
+        let renames = self
+            .renames
+            .iter()
+            .map(|(new, old)| (old.clone(), new.clone()));
+        Ok(Report::new(&self.relpath_base, changes, renames))
+    }
+
+    /// Returns the directories whose contents were rolled up into a single
+    /// entry in `paths()`.
+    ///
+    /// This is empty unless the tracker was built with
+    /// `DirtyTrackerBuilder::rollup_threshold()`. If the tracker is in an
+    /// unknown state, this will return None.
+    pub fn rolled_up_dirs(&mut self) -> Option<HashSet<PathBuf>> {
+        self.refresh().ok()?;
+        Some(
+            self.rollup
+                .as_ref()
+                .map(|rollup| rollup.dirs().clone())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Returns the combined size of the dirty files, in bytes.
+    ///
+    /// This is a best-effort estimate of how much data changed: it is based
+    /// on the current sizes of the files that still exist, so removed files
+    /// and directories don't count, and files that were only partially
+    /// rewritten count in full. If the tracker is in an unknown state, this
+    /// will return None.
+    pub fn dirty_bytes(&mut self) -> Option<u64> {
+        self.refresh().ok()?;
+        Some(
+            self.paths
+                .iter()
+                .filter_map(|path| std::fs::symlink_metadata(path).ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+                .sum(),
+        )
+    }
+
+    /// Returns the paths of the dirty directories.
+    ///
+    /// This is empty unless the tracker was built with
+    /// `DirectoryPolicy::Separate`, in which case these are left out of
+    /// `paths()`. If the tracker is in an unknown state, this will return
+    /// None.
+    pub fn dirty_dirs(&mut self) -> Option<&HashSet<PathBuf>> {
+        self.refresh().ok()?;
+        Some(&self.dirs)
+    }
+
+    /// Returns the paths of the files opened since the tree was last clean.
+    ///
+    /// These are kept separately from the dirty set; files that were only
+    /// read are not dirty. This returns None unless the tracker was built
+    /// with `DirtyTrackerBuilder::track_access()`, or if it is in an unknown
+    /// state.
+    pub fn accessed(&mut self) -> Option<&HashSet<PathBuf>> {
+        self.refresh().ok()?;
+        self.accessed.as_ref()
+    }
+
+    /// Wait until the tracker reflects all changes made before the call.
+    ///
+    /// Once this returns, the events for all file system operations that
+    /// completed before it was called have been processed, including those
+    /// for entries created inside new directories before the watcher picked
+    /// them up. Queries such as `state()` do this implicitly, unless the
+    /// tracker was built with `Consistency::Eventual`; calling it
+    /// explicitly is mostly useful to surface errors, or before inspecting
+    /// the tracker through notifications.
+    ///
+    /// This guarantee holds for the default `SentinelFlush` strategy, and
+    /// for custom strategies that provide it; `SettleFlush` and `NoFlush`
+    /// only make a best effort.
+    pub fn sync(&mut self) -> Result<(), DirtyTrackerError> {
+        self.process_pending(None)
+    }
+
+    /// Process pending events, and check whether the dirty set can be trusted.
+    fn refresh(&mut self) -> Result<(), UnknownReason> {
+        self.refresh_within(None)
+    }
+
+    /// Like `refresh()`, giving up on flushing after `timeout` if set.
+    fn refresh_within(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), UnknownReason> {
+        if let Err(e) = self
+            .sweep()
+            .and_then(|_| match self.consistency {
+                Consistency::Strong => self.process_pending(timeout),
+                Consistency::Eventual => Ok(()),
+            })
+            .and_then(|_| self.check_watcher())
+        {
+            // Flushing fails once the tree is gone, e.g. because no sentinel
+            // file can be created in it.
+            if !self.root_missing && !self.path.exists() {
+                self.root_gone();
+                return match self.unknown.clone() {
+                    Some(reason) => Err(reason),
+                    // Followed to its new location, so try again there.
+                    None => self.refresh_within(timeout),
+                };
+            }
+            self.send_state(State::Unknown);
+            return Err(UnknownReason::from_error(&e));
+        }
+        self.collapse_atomic_saves();
+        self.reconcile_file_ids();
+        self.publish_state();
+        match &self.unknown {
+            Some(reason) => Err(reason.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Run the watchdog, if enabled and a check is due.
+    fn check_watcher(&mut self) -> Result<(), DirtyTrackerError> {
+        let mut watchdog = match self.watchdog.take() {
+            Some(watchdog) => watchdog,
+            None => return Ok(()),
+        };
+        let result = watchdog.check(self);
+        self.watchdog = Some(watchdog);
+        result
+    }
+
+    /// Wait for a burst of changes to settle, and return the dirty paths.
+    ///
+    /// This blocks until no new events have arrived for `idle`, and returns
+    /// `DirtyTrackerError::Timeout` if that doesn't happen within `max`.
+    pub fn wait_for_quiescence(
+        &mut self,
+        idle: std::time::Duration,
+        max: std::time::Duration,
+    ) -> Result<&HashSet<PathBuf>, DirtyTrackerError> {
+        self.settle(idle, Some(max))?;
+        Ok(self.try_paths()?)
+    }
+
+    /// Process events until none have arrived for `idle`.
+    ///
+    /// Returns `DirtyTrackerError::Timeout` if that doesn't happen within
+    /// `max`.
+    fn settle(
+        &mut self,
+        idle: std::time::Duration,
+        max: Option<std::time::Duration>,
+    ) -> Result<(), DirtyTrackerError> {
+        let max = match max {
+            Some(max) => max,
+            None => {
+                while self.process_one(idle)? {}
+                return Ok(());
+            }
+        };
+        let deadline = std::time::Instant::now() + max;
+        loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(DirtyTrackerError::Timeout(max));
+            }
+            if !self.process_one(idle.min(remaining))? {
+                if idle <= remaining {
+                    return Ok(());
+                }
+                return Err(DirtyTrackerError::Timeout(max));
+            }
+        }
+    }
+
+    /// Wait until `predicate` holds for the set of dirty paths.
+    ///
+    /// Events are processed as they arrive, and `predicate` is checked after
+    /// each of them. Returns `DirtyTrackerError::Timeout` if the predicate
+    /// does not become true within `timeout`, or `DirtyTrackerError::Unknown`
+    /// if the tracker ends up in the `State::Unknown` state.
+    pub fn wait_until<F>(
+        &mut self,
+        mut predicate: F,
+        timeout: std::time::Duration,
+    ) -> Result<&HashSet<PathBuf>, DirtyTrackerError>
+    where
+        F: FnMut(&HashSet<PathBuf>) -> bool,
+    {
+        let deadline = std::time::Instant::now() + timeout;
+        self.refresh()?;
+        while !predicate(&self.paths) {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() || !self.process_one(remaining)? {
+                return Err(DirtyTrackerError::Timeout(timeout));
+            }
+            if let Some(reason) = self.unknown.as_ref() {
+                return Err(DirtyTrackerError::Unknown(reason.clone()));
+            }
+        }
+        Ok(&self.paths)
+    }
+
+    /// Wait until `path` is dirty.
+    ///
+    /// Returns `DirtyTrackerError::Timeout` if it isn't within `timeout`.
+    pub fn wait_for_path(
+        &mut self,
+        path: &Path,
+        timeout: std::time::Duration,
+    ) -> Result<(), DirtyTrackerError> {
+        let path = self.normalize_unicode(path).into_owned();
+        let folded = self
+            .case_folded
+            .as_ref()
+            .map(|_| normalize::fold_case(&path));
+        self.wait_until(
+            |paths| match folded.as_ref() {
+                _ if paths.contains(&path) => true,
+                Some(folded) => paths.iter().any(|p| normalize::fold_case(p) == *folded),
+                None => false,
+            },
+            timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Subscribe to notifications about individual changes.
+    ///
+    /// A notification is sent for every change the tracker processes, in
+    /// addition to it being recorded in the dirty set. Note that events are
+    /// processed when the tracker is queried, e.g. by `state()` or
+    /// `paths()`, so that is also when notifications are sent.
+    ///
+    /// Dropping the receiver unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<DirtyNotification> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Returns the most recent events from the watcher, oldest first, after
+    /// processing the pending ones.
+    ///
+    /// This is meant for debugging platform-specific behaviour, and for
+    /// policies of one's own on top of the tracker's. Events are only kept
+    /// if the tracker was built with
+    /// `DirtyTrackerBuilder::keep_recent_events()`. Paths are as the tracker
+    /// sees them, i.e. after normalization. Pending events are flushed
+    /// first; if that fails, this returns the error.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::DirtyTracker;
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let mut tracker = DirtyTracker::builder(td.path())
+    ///     .keep_recent_events(16)
+    ///     .build()
+    ///     .unwrap();
+    /// std::fs::write(td.path().join("file"), b"hello").unwrap();
+    /// assert!(tracker
+    ///     .recent_events()
+    ///     .unwrap()
+    ///     .any(|event| event.paths == [td.path().join("file")]));
+    /// ```
+    pub fn recent_events(
+        &mut self,
+    ) -> Result<impl Iterator<Item = &Event> + '_, DirtyTrackerError> {
+        match self.refresh() {
+            // The events are of interest even if the state is unknown.
+            Err(reason) if self.unknown.as_ref() != Some(&reason) => {
+                return Err(DirtyTrackerError::Unknown(reason))
+            }
+            _ => {}
+        }
+        Ok(self.recent.iter().flat_map(|(recent, _)| recent.iter()))
+    }
+
+    /// Register a callback that is invoked for every change.
+    ///
+    /// The callback is invoked while the tracker processes events, i.e.
+    /// from within calls such as `state()` or `paths()`, so it should be
+    /// quick and must not call back into the tracker.
+    pub fn on_change<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Path, ChangeKind) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Returns true if the number of dirty paths has reached the threshold
+    /// set with `DirtyTrackerBuilder::dirty_threshold()` since the tracker
+    /// was last marked clean.
+    ///
+    /// This only reflects events that have already been processed.
+    pub fn threshold_exceeded(&self) -> bool {
+        self.threshold_exceeded
+    }
+
+    /// Register a callback that is invoked with the number of dirty paths
+    /// when it reaches the threshold set with
+    /// `DirtyTrackerBuilder::dirty_threshold()`.
+    ///
+    /// The callback is invoked at most once until the tracker is marked
+    /// clean again. Like `on_change()` callbacks, it is invoked while events
+    /// are processed.
+    pub fn on_threshold_exceeded<F>(&mut self, callback: F)
+    where
+        F: FnMut(usize) + Send + 'static,
+    {
+        self.threshold_callbacks.push(Box::new(callback));
+    }
+
+    /// Register a callback that is invoked whenever the state of the tracker
+    /// changes, e.g. from `State::Clean` to `State::Dirty` when the first
+    /// change is recorded, back when it is marked clean, or to
+    /// `State::Unknown` when events were missed.
+    ///
+    /// Like `on_change()` callbacks, it is invoked while events are
+    /// processed, and also from within `mark_clean()` and `rescan()`.
+    ///
+    /// # Example
+    /// ```rust
+    /// use dirty_tracker::{DirtyTracker, State};
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let td = tempfile::tempdir().unwrap();
+    /// let mut tracker = DirtyTracker::new(td.path()).unwrap();
+    /// let ready = Arc::new(AtomicBool::new(true));
+    /// let gauge = ready.clone();
+    /// tracker.on_transition(move |transition| {
+    ///     gauge.store(transition.to == State::Clean, Ordering::SeqCst)
+    /// });
+    /// std::fs::write(td.path().join("file"), b"hello").unwrap();
+    /// assert_eq!(tracker.state(), State::Dirty);
+    /// assert!(!ready.load(Ordering::SeqCst));
+    /// tracker.mark_clean();
+    /// assert!(ready.load(Ordering::SeqCst));
+    /// ```
+    pub fn on_transition<F>(&mut self, callback: F)
+    where
+        F: FnMut(&Transition) + Send + 'static,
+    {
+        self.transition_callbacks.push(Box::new(callback));
+    }
+
+    /// Returns a receiver that is updated whenever the state of the tracker
+    /// changes.
+    ///
+    /// The state is updated as events are processed, i.e. during calls such
+    /// as `state()` or `paths()`, so async tasks can wait for a transition
+    /// with `changed().await` rather than polling themselves.
+    #[cfg(feature = "tokio")]
+    pub fn state_watch(&mut self) -> tokio::sync::watch::Receiver<State> {
+        if let Some(tx) = self.state_tx.as_ref() {
+            return tx.subscribe();
+        }
+        let (tx, rx) = tokio::sync::watch::channel(State::Clean);
+        self.state_tx = Some(tx);
+        self.publish_state();
+        rx
+    }
+
+    /// Returns the relative paths of the dirty files.
+    ///
+    /// Paths are relative to the watched directory, unless a different base
+    /// was set with `DirtyTrackerBuilder::relpath_base()`. Watchers can
+    /// report paths outside the watched directory, e.g. when it is reached
+    /// through a symbolic link; such paths are left out, and logged.
+    ///
+    /// If the tracker is in an unknown state, this will return None.
+    pub fn relpaths(&mut self) -> Option<HashSet<&Path>> {
+        let base = self.relpath_base.clone();
+        let paths = self.paths()?;
+        let mut relpaths = HashSet::with_capacity(paths.len());
+        for path in paths {
+            match path.strip_prefix(&base) {
+                Ok(relpath) => {
+                    relpaths.insert(relpath);
+                }
+                Err(_) => {
+                    log_warn!(
+                        "Ignoring dirty path {} outside of {}",
+                        path.display(),
+                        base.display()
+                    );
+                }
+            }
+        }
+        Some(relpaths)
+    }
+
+    /// Returns the relative paths of the dirty files, as owned paths.
+    ///
+    /// This is the same as `relpaths()`, but the result doesn't borrow from
+    /// the tracker, so it can be stored or sent to another thread.
+    pub fn relpaths_owned(&mut self) -> Option<HashSet<PathBuf>> {
+        self.relpaths()
+            .map(|paths| paths.into_iter().map(Path::to_path_buf).collect())
+    }
+
+    /// Returns the paths of the dirty files, relative to `base`.
+    ///
+    /// Dirty paths that are not below `base` are left out, so this can also
+    /// be used to get the changes to a subdirectory.
+    ///
+    /// If the tracker is in an unknown state, this will return None.
+    pub fn relpaths_relative_to(&mut self, base: &Path) -> Option<HashSet<&Path>> {
+        self.paths().map(|paths| {
+            paths
+                .iter()
+                .filter_map(|p| p.strip_prefix(base).ok())
+                .collect()
+        })
+    }
+
+    fn process_pending_event(&mut self, mut event: Event) {
+        self.stats.events += 1;
+        self.metrics.event();
+        self.check_dropped();
+        if event.need_rescan() {
+            log_warn!("Watcher dropped events for {}", self.path.display());
+            self.stats.rescans_requested += 1;
+            if self.unknown.is_none() {
+                self.set_unknown(UnknownReason::Overflow);
+            }
+            self.publish_state();
+        }
+        if !self.late_sentinels.is_empty()
+            && !event.paths.is_empty()
+            && event
+                .paths
+                .iter()
+                .all(|path| self.late_sentinels.contains(path))
+        {
+            // Nothing more happens to a sentinel once it is removed.
+            if matches!(event.kind, EventKind::Remove(_)) {
+                for path in event.paths.iter() {
+                    self.late_sentinels.remove(path);
+                }
+            }
+            return;
+        }
+        if let Some(parent) = self.parent_watch.as_ref() {
+            if self.root_missing {
+                if event.paths.iter().any(|path| path == &self.path) && self.path.is_dir() {
+                    self.root_created();
+                    return;
+                }
+                // Events from the old tree may still have been queued when
+                // it went away; nothing else can happen below the root
+                // until it is recreated.
+                if !event
+                    .paths
+                    .iter()
+                    .any(|path| path.starts_with(&self.path) && path != &self.path)
+                {
+                    return;
+                }
+            }
+            // Only events for the root itself are of interest in its parent.
+            if event
+                .paths
+                .iter()
+                .all(|path| path.parent() == Some(parent) && path != &self.path)
+            {
+                return;
+            }
+        }
+        // A shared watcher also reports events for other trees.
+        if self.shared.is_some() {
+            event.paths.retain(|path| path.starts_with(&self.path));
+            if event.paths.is_empty() {
+                return;
+            }
+        }
+        if let Some((recent, capacity)) = self.recent.as_mut() {
+            if recent.len() == *capacity {
+                recent.pop_front();
+            }
+            if *capacity > 0 {
+                recent.push_back(event.clone());
+            }
+        }
+        if let Some(tee) = self.tee.as_ref() {
+            if tee.send(event.clone()).is_err() {
+                self.tee = None;
+            }
+        }
+        if matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)))
+            && event.paths.iter().any(|path| path == &self.path)
+        {
+            // Renamed back and forth before we got to look.
+            if self.root_id.is_none() || identity::file_id(&self.path) != self.root_id {
+                self.root_gone();
+            }
+            return;
+        }
+        if self.filter.excludes_event(&event.kind) {
+            return;
+        }
+        if let EventKind::Access(AccessKind::Open(_) | AccessKind::Read) = event.kind {
+            self.record_access(event.paths);
+            return;
+        }
+        let kind = match event.kind {
+            EventKind::Create(_) => ChangeKind::Created,
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::RenamedFrom,
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::RenamedTo,
+            // The watcher reports the two sides of the rename separately as
+            // well, so this only pairs them up.
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                if let [from, to] = &event.paths[..] {
+                    self.record_rename(from, to);
+                }
+                return;
+            }
+            EventKind::Modify(_) => ChangeKind::Modified,
+            EventKind::Remove(_) => ChangeKind::Removed,
+            _ => return,
+        };
+        let ambiguous_rename = matches!(
+            event.kind,
+            EventKind::Modify(ModifyKind::Name(RenameMode::Any | RenameMode::Other))
+        );
+        for path in event.paths {
+            if self.ignored_dirs.iter().any(|dir| path.starts_with(dir)) {
+                continue;
+            }
+            if path == self.path {
+                match kind {
+                    ChangeKind::Removed => self.root_removed(),
+                    // Only reported by the watch on the parent, in which case
+                    // root_created() has dealt with it.
+                    ChangeKind::Created => {}
+                    _ => self.record_change(path, kind),
+                }
+                continue;
+            }
+            if self.symlink_policy == SymlinkPolicy::Ignore && !path.starts_with(&self.path) {
+                continue;
+            }
+            if kind == ChangeKind::Created && self.symlink_policy == SymlinkPolicy::Follow {
+                if let Ok(metadata) = path.symlink_metadata() {
+                    if metadata.file_type().is_symlink() {
+                        self.watch_link_target(&path);
+                    }
+                }
+            }
+            match kind {
+                ChangeKind::Created if path.is_dir() => self.record_new_dir(&path),
+                ChangeKind::RenamedTo if path.is_dir() => self.record_moved_dir(&path),
+                ChangeKind::Removed | ChangeKind::RenamedFrom => {
+                    self.record_removed_dir(&path, kind)
+                }
+                _ => {}
+            }
+            if ambiguous_rename && self.created.contains(&path) {
+                self.rename_candidates.insert(path.clone());
+            }
+            self.record_change(path, kind);
+        }
+    }
+
+    /// Record the contents of a newly created directory.
+    ///
+    /// The watcher only starts watching new directories once it has seen
+    /// them being created, so anything created inside them before then
+    /// would otherwise be missed.
+    fn record_new_dir(&mut self, dir: &Path) {
+        let mut entries = Vec::new();
+        // The directory may have been removed again already, in which case
+        // its removal will be reported by the watcher.
+        let _ = snapshot::walk(dir, &mut |path, _| entries.push(path));
+        for path in entries {
+            self.record_change(path, ChangeKind::Created);
+        }
+    }
+
+    /// Record that the files at `paths` were opened.
+    fn record_access(&mut self, paths: Vec<PathBuf>) {
+        let accessed = match self.accessed.as_mut() {
+            Some(accessed) => accessed,
+            None => return,
+        };
+        for path in paths {
+            // Directories are opened to list them, e.g. when walking the
+            // tree for a snapshot.
+            if self.ignored_dirs.iter().any(|dir| path.starts_with(dir))
+                || self.filter.excludes(&path)
+                || path.is_dir()
+            {
+                continue;
+            }
+            accessed.insert(path);
+        }
+    }
+
+    /// Record the entries below a directory that was moved to `dir`.
+    ///
+    /// Entries that were not part of the clean tree at their new path are
+    /// considered created, so that moving them away again makes them clean.
+    fn record_moved_dir(&mut self, dir: &Path) {
+        let mut entries = Vec::new();
+        let _ = snapshot::walk(dir, &mut |path, _| entries.push(path));
+        for path in entries {
+            let created = self
+                .baseline
+                .as_ref()
+                .is_none_or(|baseline| !baseline.contains(&path));
+            self.record_change(path.clone(), ChangeKind::RenamedTo);
+            if created {
+                self.created.insert(path);
+            }
+        }
+    }
+
+    /// Record the entries below `dir`, which was removed or moved away.
+    ///
+    /// The entries are taken from the baseline, and from the dirty set for
+    /// those created since. Entries that are already dirty aren't reported
+    /// again, as is the case for removals the watcher reports one by one.
+    fn record_removed_dir(&mut self, dir: &Path, kind: ChangeKind) {
+        let mut entries = self
+            .baseline
+            .as_ref()
+            .map(|baseline| baseline.entries_below(dir))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| !self.paths.contains(path))
+            .chain(self.created.below(dir))
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return;
+        }
+        // Report children before their parents, as a recursive removal
+        // would.
+        entries.sort_by(|a, b| b.cmp(a));
+        for path in entries {
+            self.record_change(path, kind);
+        }
+    }
+
+    /// Returns `path` in the Unicode normalization form paths are recorded
+    /// in.
+    ///
+    /// Only the part below the root is normalized, so that paths keep
+    /// starting with the root as it was passed in.
+    fn normalize_unicode<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let form = match self.unicode_form {
+            Some(form) => form,
+            None => return Cow::Borrowed(path),
+        };
+        match path.strip_prefix(&self.path) {
+            Ok(relpath) => match form.normalize(relpath) {
+                Cow::Owned(relpath) => self.path.join(relpath).into(),
+                Cow::Borrowed(_) => path.into(),
+            },
+            Err(_) => form.normalize(path),
+        }
+    }
+
+    /// Returns the entry in the dirty set for `path`, if there is one.
+    fn dirty_entry(&self, path: &Path) -> Option<&PathBuf> {
+        let path = self.normalize_unicode(path);
+        if let Some(entry) = self.paths.get(&*path) {
+            return Some(entry);
+        }
+        self.case_folded
+            .as_ref()?
+            .get(&normalize::fold_case(&path))
+            .filter(|entry| self.paths.contains(*entry))
+    }
+
+    /// Returns the form `path` is recorded in, or would be if it became
+    /// dirty now.
+    fn recorded_path(&self, path: &Path) -> PathBuf {
+        match self.dirty_entry(path) {
+            Some(entry) => entry.clone(),
+            None => self.normalize_unicode(path).into_owned(),
+        }
+    }
+
+    /// Rebuild the index of case-folded paths from the dirty set.
+    fn reindex_case(&mut self) {
+        if let Some(index) = self.case_folded.as_mut() {
+            index.clear();
+            for path in self.paths.iter() {
+                index.insert(normalize::fold_case(path), path.clone());
+            }
+        }
+    }
+
+    fn record_change(&mut self, path: PathBuf, kind: ChangeKind) {
+        let (path, kind) = match self.approximated(&path) {
+            Some(ancestor) => (ancestor, ChangeKind::Modified),
+            None => (path, kind),
+        };
+        // Nothing more is recorded once the limit was exceeded.
+        if matches!(self.unknown, Some(UnknownReason::TooManyChanges { .. }))
+            || self.filter.excludes(&path)
+        {
+            return;
+        }
+        let path = if self.case_folded.is_some() || self.unicode_form.is_some() {
+            self.recorded_path(&path)
+        } else {
+            path
+        };
+        let dir = self.directories != DirectoryPolicy::Include && self.is_dir(&path);
+        let dirty = if dir { &mut self.dirs } else { &mut self.paths };
+        if !self.filter.records(kind) || (dir && self.directories == DirectoryPolicy::Omit) {
+            // Keep track of created paths regardless, so that they can be
+            // left out once they are removed again.
+            match kind {
+                ChangeKind::Created => {
+                    self.created.insert(path);
+                }
+                ChangeKind::Removed | ChangeKind::RenamedFrom if self.created.remove(&path) => {
+                    dirty.remove(&path);
+                }
+                _ => {}
+            }
+            return;
+        }
+        if let Some(index) = self.case_folded.as_mut() {
+            index.insert(normalize::fold_case(&path), path.clone());
+        }
+        if let Some(file_ids) = self.file_ids.as_mut() {
+            if !matches!(kind, ChangeKind::Removed | ChangeKind::RenamedFrom) {
+                file_ids.seen(&path);
+            }
+        }
+        let repeated = self
+            .coalesce
+            .as_mut()
+            .is_some_and(|coalesce| !coalesce.admit(&path, kind, std::time::Instant::now()));
+        self.sequence += 1;
+        if !repeated {
+            for callback in self.callbacks.iter_mut() {
+                callback(&path, kind);
+            }
+        }
+        if !self.subscribers.is_empty() && !repeated {
+            let notification = DirtyNotification {
+                path: path.clone(),
+                kind,
+                sequence: self.sequence,
+            };
+            // Drop subscribers that have gone away.
+            self.subscribers
+                .retain(|tx| tx.send(notification.clone()).is_ok());
+        }
+        match kind {
+            ChangeKind::Created => self.stats.created += 1,
+            ChangeKind::Modified | ChangeKind::RenamedTo => self.stats.modified += 1,
+            ChangeKind::Removed | ChangeKind::RenamedFrom => self.stats.removed += 1,
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.stamp(&path, kind);
+            // Changes below a rolled up directory change the directory.
+            if let Some(rollup) = self.rollup.as_ref() {
+                for dir in path.ancestors().filter(|dir| rollup.dirs().contains(*dir)) {
+                    journal.stamp(dir, ChangeKind::Modified);
+                }
+            }
+        }
+        if self
+            .rollup
+            .as_ref()
+            .is_some_and(|rollup| rollup.covers(&path))
+        {
+            return;
+        }
+        let dirty = if dir { &mut self.dirs } else { &mut self.paths };
+        let added = match kind {
+            ChangeKind::Created => {
+                // Recreating a path that was removed modifies it.
+                if !self.removed.remove(&path) {
+                    self.created.insert(path.clone());
+                }
+                dirty.insert(path.clone())
+            }
+            ChangeKind::Modified | ChangeKind::RenamedTo => {
+                self.removed.remove(&path);
+                dirty.insert(path.clone())
+            }
+            // A path that was renamed away is gone, just like a removed one.
+            ChangeKind::Removed | ChangeKind::RenamedFrom => {
+                if self.created.remove(&path) {
+                    if dirty.remove(&path) && !dir {
+                        if let Some(rollup) = self.rollup.as_mut() {
+                            rollup.removed(&path);
+                        }
+                    }
+                    false
+                } else {
+                    self.removed.insert(path.clone());
+                    dirty.insert(path.clone())
+                }
+            }
+        };
+        if added && !dir {
+            if let Some(dir) = self.rollup.as_mut().and_then(|rollup| rollup.added(&path)) {
+                self.roll_up(dir);
+            }
+            self.check_limit();
+        }
+        self.stats.peak_dirty = self.stats.peak_dirty.max(self.paths.len());
+        self.check_threshold();
+        self.publish_transition(Some(&path));
+    }
+
+    /// Returns the type of `path`, and whether it still exists.
+    ///
+    /// For paths that have gone away, this is the type they had when they
+    /// were recorded or when the tree was clean, as far as known.
+    fn file_type(&self, path: &Path) -> (Option<FileType>, bool) {
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) => (Some(metadata.file_type().into()), true),
+            Err(_) if self.dirs.contains(path) => (Some(FileType::Dir), false),
+            Err(_) => (
+                self.baseline
+                    .as_ref()
+                    .and_then(|baseline| baseline.file_type(path)),
+                false,
+            ),
+        }
+    }
+
+    /// Returns the ancestor of `path` that changes to it are recorded for in
+    /// approximate mode, if it is too deep to be recorded itself.
+    fn approximated(&self, path: &Path) -> Option<PathBuf> {
+        let depth = self.approximate?;
+        let relpath = path.strip_prefix(&self.path).ok()?;
+        if relpath.components().count() <= depth {
+            return None;
+        }
+        Some(
+            self.path
+                .join(relpath.components().take(depth).collect::<PathBuf>()),
+        )
+    }
+
+    /// Returns the kind of change that the recorded changes to `path`, which
+    /// is dirty, amount to.
+    fn change_kind(&self, path: &Path) -> ChangeKind {
+        if self.created.contains(path) {
+            ChangeKind::Created
+        } else if self.removed.contains(path) {
+            ChangeKind::Removed
+        } else {
+            ChangeKind::Modified
+        }
+    }
+
+    /// Drop `path` from the dirty set, e.g. because it turned out not to
+    /// have changed after all.
+    fn forget(&mut self, path: &Path) {
+        self.created.remove(path);
+        self.removed.remove(path);
+        if self.paths.remove(path) {
+            if let Some(rollup) = self.rollup.as_mut() {
+                rollup.removed(path);
+            }
+        }
+    }
+
+    /// Make sure the dirty set stays within the limit set with
+    /// `DirtyTrackerBuilder::max_dirty_paths()`.
+    fn check_limit(&mut self) {
+        let (limit, policy) = match self.limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        while self.paths.len() + self.dirs.len() > limit {
+            match policy {
+                LimitPolicy::Unknown => {
+                    log_warn!("More than {} paths changed, giving up", limit);
+                    self.set_unknown(UnknownReason::TooManyChanges { limit });
+                    // The paths are of no use any more.
+                    self.paths = HashSet::new();
+                    self.dirs = HashSet::new();
+                    self.created.clear();
+                    self.removed.clear();
+                    self.reindex_case();
+                    self.publish_state();
+                    return;
+                }
+                LimitPolicy::RollUp => {
+                    let busiest = self.rollup.as_ref().and_then(|rollup| rollup.busiest());
+                    match busiest {
+                        Some(dir) if dir.starts_with(&self.path) => self.roll_up(dir),
+                        // As a last resort, the whole tree is dirty.
+                        _ => {
+                            self.roll_up(self.path.clone());
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record `dir` instead of the dirty entries below it.
+    fn roll_up(&mut self, mut dir: PathBuf) {
+        loop {
+            // Never roll up beyond the root.
+            if !dir.starts_with(&self.path) {
+                return;
+            }
+            log_debug!("Rolling up dirty entries below {}", dir.display());
+            let below = |path: &PathBuf| path.starts_with(&dir) && path != &dir;
+            self.paths.retain(|path| !below(path));
+            self.dirs.retain(|path| !below(path));
+            let rollup = match self.rollup.as_mut() {
+                Some(rollup) => rollup,
+                None => return,
+            };
+            rollup.rolled_up(&dir);
+            if let Some(journal) = self.journal.as_mut() {
+                journal.stamp(&dir, ChangeKind::Modified);
+            }
+            if !self.paths.insert(dir.clone()) {
+                break;
+            }
+            match rollup.added(&dir) {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+        self.reindex_case();
+    }
+
+    /// Returns true if `path` is, or was until it went away, a directory.
+    fn is_dir(&self, path: &Path) -> bool {
+        self.file_type(path).0 == Some(FileType::Dir)
+    }
+
+    /// Record that `from` was renamed to `to`.
+    fn record_rename(&mut self, from: &Path, to: &Path) {
+        let (from, to) = if self.case_folded.is_some() || self.unicode_form.is_some() {
+            (self.recorded_path(from), self.recorded_path(to))
+        } else {
+            (from.to_path_buf(), to.to_path_buf())
+        };
+        self.stats.renamed += 1;
+        if let Some(file_ids) = self.file_ids.as_mut() {
+            file_ids.renamed(&from, &to);
+        }
+        // A file that was created since the tree was clean and then renamed,
+        // as editors do when saving through a temporary file, has already
+        // been dropped from the dirty set, and is not a rename as far as the
+        // clean tree is concerned.
+        if !self.renames.contains_key(&from)
+            && !self.paths.contains(&from)
+            && !self.dirs.contains(&from)
+        {
+            self.renames.remove(&to);
+            if let Some(baseline) = self.baseline.as_ref() {
+                if !baseline.contains(&to) {
+                    self.created.insert(to);
+                }
+            }
+            return;
+        }
+        // Collapse chains of renames into a single one.
+        let origin = self.renames.remove(&from).unwrap_or(from);
+        if origin != to {
+            self.renames.insert(to, origin);
+        }
+    }
+
+    /// Notify `state_watch()` receivers and `on_transition()` callbacks of
+    /// the current state.
+    fn publish_state(&mut self) {
+        self.publish_transition(None);
+    }
+
+    /// Like `publish_state()`, for a state that may have been changed by a
+    /// change to `path`.
+    fn publish_transition(&mut self, path: Option<&Path>) {
+        let state = if self.unknown.is_some() {
+            State::Unknown
+        } else if self.paths.is_empty() {
+            State::Clean
+        } else {
+            State::Dirty
+        };
+        if state != self.published {
+            let transition = Transition {
+                from: std::mem::replace(&mut self.published, state),
+                to: state,
+                path: path.map(Path::to_path_buf),
+            };
+            log_debug!("State changed from {:?} to {:?}", transition.from, state);
+            for callback in self.transition_callbacks.iter_mut() {
+                callback(&transition);
+            }
+        }
+        self.send_state(state);
+    }
+
+    #[cfg(feature = "tokio")]
+    fn send_state(&mut self, state: State) {
+        self.metrics.state(state, self.paths.len());
+        if let Some(tx) = self.state_tx.as_ref() {
+            tx.send_if_modified(|current| {
+                let modified = *current != state;
+                *current = state;
+                modified
+            });
+        }
+    }
+
+    #[cfg(not(feature = "tokio"))]
+    fn send_state(&mut self, state: State) {
+        self.metrics.state(state, self.paths.len());
+    }
+
+    fn check_threshold(&mut self) {
+        match self.threshold {
+            Some(threshold) if !self.threshold_exceeded && self.paths.len() >= threshold => {
+                self.threshold_exceeded = true;
+                let len = self.paths.len();
+                log_debug!("{} dirty paths, exceeding threshold of {}", len, threshold);
+                for callback in self.threshold_callbacks.iter_mut() {
+                    callback(len);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Wait up to `timeout` for a single event, and process it.
+    ///
+    /// Returns false if no event arrived in time.
+    fn process_one(&mut self, timeout: std::time::Duration) -> Result<bool, DirtyTrackerError> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(Ok(event)) => self.process_pending_event(event),
+            Ok(Err(e)) => self.process_watcher_error(e),
+            Err(RecvTimeoutError::Timeout) => return Ok(false),
+            Err(RecvTimeoutError::Disconnected) => return Err(self.disconnected()),
+        }
+        Ok(true)
+    }
+
+    /// Drop paths that were created and then renamed away since the tree was
+    /// clean, such as the temporary files editors save to.
+    ///
+    /// Watchers that report both sides of a rename let `record_change()`
+    /// take care of these; this is for those that don't.
+    fn collapse_atomic_saves(&mut self) {
+        if self.rename_candidates.is_empty() {
+            return;
+        }
+        for path in std::mem::take(&mut self.rename_candidates) {
+            if self.created.contains(&path) && std::fs::symlink_metadata(&path).is_err() {
+                log_debug!("{} was renamed away after being created", path.display());
+                self.forget(&path);
+            }
+        }
+        self.reindex_case();
+    }
+
+    /// Drop the temporary names of files that were moved elsewhere in the
+    /// tree from the dirty set.
+    fn reconcile_file_ids(&mut self) {
+        let temporary = match self.file_ids.as_mut() {
+            Some(file_ids) => file_ids.reconcile(&self.paths),
+            None => return,
+        };
+        for path in temporary.iter() {
+            log_debug!("{} was a temporary name, ignoring it", path.display());
+            self.forget(path);
+        }
+        if !temporary.is_empty() {
+            self.reindex_case();
+        }
+    }
+
+    /// Watch the target of the symbolic link `link`, if it is outside the
+    /// tree, and report changes to it as changes to `link`.
+    fn watch_link_target(&mut self, link: &Path) {
+        // Dangling links have nothing to watch.
+        let target = match self.rx.normalizer.add_link(&self.path, link) {
+            Ok(Some(target)) => target,
+            Ok(None) | Err(_) => return,
+        };
+        let mode = if target.is_dir() {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        if let Err(e) = self.watcher.add_watch(&target, mode) {
+            log_warn!(
+                "Unable to watch {}, the target of {}: {}",
+                target.display(),
+                link.display(),
+                e
+            );
+        }
+    }
+
+    /// Start watching the root, now that it has been created.
+    ///
+    /// The parent stays watched, as removing its watch could lose events
+    /// for sentinel files that are still pending.
+    fn root_created(&mut self) {
+        self.root_missing = false;
+        let path = self.path.clone();
+        if let Err(e) = self.watcher.add_watch(&path, self.filter.recursive_mode()) {
+            self.process_watcher_error(e);
+            return;
+        }
+        log_debug!("Watching {} now that it exists", path.display());
+        self.root_rewatched += 1;
+        self.root_id = identity::file_id(&path);
+        if std::mem::take(&mut self.root_replaced) {
+            self.record_change(path.clone(), ChangeKind::Modified);
+            let mut entries = Vec::new();
+            let _ = snapshot::walk(&path, &mut |path, _| entries.push(path));
+            for entry in entries {
+                self.record_change(entry, ChangeKind::Modified);
+            }
+        } else {
+            self.record_new_dir(&path);
+        }
+        self.publish_state();
+    }
+
+    /// Wait for the root to reappear, after it was removed or moved away.
+    ///
+    /// Returns false if its parent can't be watched.
+    fn rearm(&mut self) -> bool {
+        let parent = match self.path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return false,
+        };
+        if self.parent_watch.is_none() {
+            if let Err(e) = self.watcher.add_watch(&parent, RecursiveMode::NonRecursive) {
+                log_warn!("Unable to watch {}: {}", parent.display(), e);
+                return false;
+            }
+            self.parent_watch = Some(parent);
+        }
+        // A directory that was moved aside is still being watched.
+        let _ = self.watcher.remove_watch(&self.path.clone());
+        log_debug!("Waiting for {} to be recreated", self.path.display());
+        self.root_missing = true;
+        self.root_replaced = true;
+        // It may have been recreated before its parent was watched.
+        if self.path.is_dir() {
+            self.root_created();
+        }
+        true
+    }
+
+    /// Work out what happened to the root, after it disappeared from its
+    /// path.
+    fn root_gone(&mut self) {
+        let moved = self.root_id.and_then(|id| root::find_moved(&self.path, id));
+        match moved {
+            Some(to) if self.follow_root => {
+                if let Err(e) = self.follow(&to) {
+                    log_warn!(
+                        "Unable to follow {} to {}: {}",
+                        self.path.display(),
+                        to.display(),
+                        e
+                    );
+                    self.set_unknown(UnknownReason::RootMoved);
+                    self.publish_state();
+                }
+            }
+            Some(to) => {
+                log_warn!(
+                    "Watched directory {} was moved to {}",
+                    self.path.display(),
+                    to.display()
+                );
+                if !(self.rearm_root && self.rearm()) {
+                    self.set_unknown(UnknownReason::RootMoved);
+                    self.publish_state();
+                }
+            }
+            None => self.root_removed(),
+        }
+    }
+
+    /// Watch the root at its new location `to`, and move everything recorded
+    /// about it there.
+    fn follow(&mut self, to: &Path) -> notify::Result<()> {
+        let from = self.path.clone();
+        self.drain_moved(&from, to);
+        // The old watch may already have gone away along with its path. It
+        // has to be removed first, as inotify would otherwise reuse it for
+        // the new path, since it refers to the same directory.
+        let _ = self.watcher.remove_watch(&from);
+        self.watcher.add_watch(to, self.filter.recursive_mode())?;
+        log_debug!("Following {} to {}", from.display(), to.display());
+
+        let rebase = |paths: HashSet<PathBuf>| {
+            paths
+                .into_iter()
+                .map(|path| root::rebase(&path, &from, to))
+                .collect::<HashSet<_>>()
+        };
+        self.paths = rebase(std::mem::take(&mut self.paths));
+        self.dirs = rebase(std::mem::take(&mut self.dirs));
+        self.created.relocate(to);
+        self.removed.relocate(to);
+        if let Some(rollup) = self.rollup.as_mut() {
+            rollup.relocate(&from, to);
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.relocate(&from, to);
+        }
+        if let Some(glob) = self.filter.glob.as_mut() {
+            glob.relocate(to);
+        }
+        for dir in self.ignored_dirs.iter_mut() {
+            *dir = root::rebase(dir, &from, to);
+        }
+        self.relpath_base = root::rebase(&self.relpath_base, &from, to);
+        if let Some(baseline) = self.baseline.as_mut() {
+            baseline.relocate(&from, to);
+        }
+        if let Some(contents) = self.contents.as_mut() {
+            contents.relocate(&from, to);
+        }
+        if let Some(shadow) = self.shadow.as_mut() {
+            shadow.relocate(to);
+        }
+        if let Some(file_ids) = self.file_ids.as_mut() {
+            file_ids.relocate(&from, to);
+        }
+        self.renames = self
+            .renames
+            .drain()
+            .map(|(new, old)| (root::rebase(&new, &from, to), root::rebase(&old, &from, to)))
+            .collect();
+        // Events that were queued before the move still use the old path.
+        self.rx.normalizer.relocate(&from, to);
+        let _ = self.rx.normalizer.add_root(to);
+        self.path = to.to_path_buf();
+        self.reindex_case();
+        Ok(())
+    }
+
+    /// Process the events for changes made before the tree was moved from
+    /// `from` to `to`.
+    ///
+    /// Removing the old watch drops the events the watcher has yet to read,
+    /// so this creates and removes a sentinel file in the tree, and
+    /// processes events until its removal is reported, for at most a
+    /// second.
+    fn drain_moved(&mut self, from: &Path, to: &Path) {
+        let sentinel = match tempfile::NamedTempFile::new_in(to) {
+            Ok(sentinel) => sentinel,
+            Err(_) => return,
+        };
+        let name = match sentinel.path().file_name() {
+            Some(name) => name.to_owned(),
+            None => return,
+        };
+        if sentinel.close().is_err() {
+            return;
+        }
+        // Depending on the watcher, the sentinel is reported at either.
+        let sentinels = [from.join(&name), to.join(&name)];
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(1);
+        loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let event = match self.rx.recv_timeout(remaining) {
+                Ok(Ok(event)) => event,
+                Ok(Err(e)) => {
+                    self.process_watcher_error(e);
+                    continue;
+                }
+                Err(_) => return,
+            };
+            if event.paths.iter().any(|path| sentinels.contains(path)) {
+                if matches!(event.kind, EventKind::Remove(_)) {
+                    return;
+                }
+                continue;
+            }
+            // The move itself is what is being dealt with.
+            if event.paths.iter().any(|path| path == from) {
+                continue;
+            }
+            self.process_pending_event(event);
+        }
+    }
+
+    /// Record that the watched directory was removed.
+    ///
+    /// Nothing below it can be tracked any more, so unless `rearm_root()`
+    /// was set, the tracker stays in the `State::Unknown` state until
+    /// `mark_clean()` is called.
+    fn root_removed(&mut self) {
+        if self.rearm_root && self.rearm() {
+            return;
+        }
+        if self.unknown != Some(UnknownReason::RootRemoved) {
+            log_warn!("Watched directory {} was removed", self.path.display());
+        }
+        self.set_unknown(UnknownReason::RootRemoved);
+        self.publish_state();
+    }
+
+    /// Record that the watcher's event channel was disconnected.
+    ///
+    /// No more events will arrive, so the tracker stays in the
+    /// `State::Unknown` state until `mark_clean()` is called.
+    fn disconnected(&mut self) -> DirtyTrackerError {
+        if self.unknown != Some(UnknownReason::Disconnected) {
+            log_warn!("Watcher for {} disconnected", self.path.display());
+        }
+        if self.unknown.is_none() {
+            self.set_unknown(UnknownReason::Disconnected);
+        }
+        self.publish_state();
+        DirtyTrackerError::Disconnected
+    }
+
+    /// Process the events that have already been delivered.
+    ///
+    /// Returns `DirtyTrackerError::Disconnected` if the channel turns out
+    /// to be disconnected, so that there is no point in flushing.
+    fn sweep(&mut self) -> Result<(), DirtyTrackerError> {
+        self.check_dropped();
+        loop {
+            match self.rx.try_recv() {
+                Ok(Ok(event)) => self.process_pending_event(event),
+                Ok(Err(e)) => self.process_watcher_error(e),
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(self.disconnected()),
+            }
+        }
+    }
+
+    /// Check whether events were dropped because the event queue was full.
+    fn check_dropped(&mut self) {
+        let dropped = self.dropped.take();
+        if dropped == 0 {
+            return;
+        }
+        log_warn!(
+            "Dropped {} events for {}, as the event queue was full",
+            dropped,
+            self.path.display()
+        );
+        // The queue follows the dropped events with a request for a rescan,
+        // which is counted when it is processed.
+        self.stats.events_dropped += dropped;
+        if self.unknown.is_none() {
+            self.set_unknown(UnknownReason::Overflow);
+        }
+        self.publish_state();
+    }
+
+    fn process_watcher_error(&mut self, error: notify::Error) {
+        log_warn!("Watcher error for {}: {}", self.path.display(), error);
+        self.stats.watcher_errors += 1;
+        // The watcher may have dropped events, so we can no longer vouch for
+        // the contents of the dirty set.
+        if self.unknown.is_none() {
+            self.set_unknown(UnknownReason::from_notify(&error));
+        }
+        self.publish_state();
+    }
+
+    /// Enter the `State::Unknown` state for `reason`.
+    ///
+    /// Entering it counts as a change, as the dirty set can no longer be
+    /// relied upon; switching to another reason doesn't.
+    fn set_unknown(&mut self, reason: UnknownReason) {
+        if self.unknown.is_none() {
+            self.sequence += 1;
+        }
+        self.unknown = Some(reason);
+    }
+
+    fn process_pending(
+        &mut self,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<(), DirtyTrackerError> {
+        /// Puts the strategy back when the flush is done, even if a callback
+        /// panics halfway through.
+        struct Guard<'a> {
+            tracker: &'a mut DirtyTracker,
+            flush: Option<Box<dyn FlushStrategy>>,
+        }
+
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                self.tracker.flush = self.flush.take();
+            }
+        }
+
+        let flush = self
+            .flush
+            .take()
+            .unwrap_or_else(|| Box::new(SentinelFlush::new()));
+        let start = std::time::Instant::now();
+        let result = {
+            let mut guard = Guard {
+                tracker: self,
+                flush: Some(flush),
+            };
+            let Guard { tracker, flush } = &mut guard;
+            flush
+                .as_mut()
+                .unwrap()
+                .flush(&mut PendingEvents::new(tracker), timeout)
+        };
+        let elapsed = start.elapsed();
+        self.stats.last_flush = Some(elapsed);
+        self.stats.max_flush = self.stats.max_flush.max(Some(elapsed));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn wait_for(
+        tracker: &mut DirtyTracker,
+        expected_paths: &HashSet<PathBuf>,
+        expected_state: State,
+    ) {
+        let state = tracker.state();
+        let paths = tracker.paths().unwrap().clone();
+        if state == State::Unknown {
+            panic!("Unexpected unknown state");
+        }
+        assert_eq!(state, expected_state);
+        assert_eq!(paths, *expected_paths);
+    }
+
+    #[test]
+    fn test_new_missing_path() {
+        let dir = tempdir().unwrap();
+        let result = DirtyTracker::new(&dir.path().join("missing"));
+        assert!(matches!(result, Err(DirtyTrackerError::Init(_))));
+    }
+
+    #[test]
+    fn test_relative_root() {
+        let cwd = std::env::current_dir().unwrap();
+        let dir = tempfile::tempdir_in(&cwd).unwrap();
+        let relpath = dir.path().strip_prefix(&cwd).unwrap();
+        assert!(relpath.is_relative());
+        let mut tracker = DirtyTracker::new(relpath).unwrap();
+        assert_eq!(tracker.path(), dir.path());
+        assert_eq!(tracker.state(), State::Clean);
+        std::fs::write(relpath.join("file"), b"hello").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("file")])
+        );
+    }
+
+    #[test]
+    fn test_no_changes() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        wait_for(&mut tracker, &maplit::hashset![], State::Clean);
+
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![]));
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_simple_create() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        let file = dir.path().join("file");
+        let mut f = File::create(&file).unwrap();
+        f.write_all(b"hello").unwrap();
+        f.sync_all().unwrap();
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(
+            tracker.relpaths(),
+            Some(maplit::hashset![Path::new("file")])
+        );
+        assert_eq!(tracker.state(), State::Dirty);
+    }
+
+    #[test]
+    fn test_relpaths_owned() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        inject(&sender, CREATE, &dir.path().join("sub/file"));
+        let relpaths = tracker.relpaths_owned();
+        // The paths outlive the borrow of the tracker.
+        tracker.mark_clean();
+        assert_eq!(relpaths, Some(maplit::hashset![PathBuf::from("sub/file")]));
+
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.relpaths_owned(), None);
+    }
+
+    #[test]
+    fn test_try_state() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        assert_eq!(tracker.try_state(), Ok(State::Clean));
+        let file = dir.path().join("file");
+        inject(&sender, CREATE, &file);
+        assert_eq!(tracker.try_state(), Ok(State::Dirty));
+        assert_eq!(tracker.try_paths(), Ok(&maplit::hashset![file]));
+
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.try_state(), Err(UnknownReason::Overflow));
+        assert_eq!(tracker.try_paths(), Err(UnknownReason::Overflow));
+    }
+
+    #[test]
+    fn test_simple_modify() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        std::fs::write(&file, b"world").unwrap();
+
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(
+            tracker.relpaths(),
+            Some(maplit::hashset![Path::new("file")])
+        );
+        assert_eq!(tracker.state(), State::Dirty);
+    }
+
+    #[test]
+    fn test_delete() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        std::fs::remove_file(&file).unwrap();
+
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(
+            tracker.relpaths(),
+            Some(maplit::hashset![Path::new("file")])
+        );
+        assert_eq!(tracker.state(), State::Dirty);
+    }
+
+    /// Build a tracker whose events are injected through the returned
+    /// sender, rather than coming from the file system.
+    fn build_injected(builder: DirtyTrackerBuilder) -> (DirtyTracker, EventSender) {
+        let mut sender = None;
+        let tracker = builder
+            .build_with_backend(|tx| {
+                sender = Some(tx.clone());
+                notify::NullWatcher::new(tx, notify::Config::default())
+            })
+            .unwrap();
+        (tracker, sender.unwrap())
+    }
+
+    fn inject(sender: &EventSender, kind: EventKind, path: &Path) {
+        sender
+            .send(Ok(Event::new(kind).add_path(path.to_path_buf())))
+            .unwrap();
+    }
+
+    const CREATE: EventKind = EventKind::Create(notify::event::CreateKind::File);
+    const MODIFY: EventKind = EventKind::Modify(notify::event::ModifyKind::Any);
+    const REMOVE: EventKind = EventKind::Remove(notify::event::RemoveKind::File);
+
+    #[test]
+    fn test_relpaths_outside_root() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        inject(&sender, CREATE, Path::new("/elsewhere/file"));
+        inject(&sender, CREATE, &dir.path().join("file"));
+        assert_eq!(tracker.paths().unwrap().len(), 2);
+        assert_eq!(
+            tracker.relpaths(),
+            Some(maplit::hashset![Path::new("file")])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_canonicalize_paths() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("target");
+        let link = dir.path().join("link");
+        std::fs::create_dir(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let canonical = target.canonicalize().unwrap().join("file");
+
+        for (canonicalize, expected) in [(true, link.join("file")), (false, canonical.clone())] {
+            let (mut tracker, sender) =
+                build_injected(DirtyTracker::builder(&link).canonicalize_paths(canonicalize));
+            inject(&sender, CREATE, &canonical);
+            assert_eq!(tracker.paths(), Some(&maplit::hashset![expected]));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_renames() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let c = dir.path().join("c");
+        std::fs::write(&a, b"a").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        let changes = tracker.subscribe();
+        std::fs::rename(&a, &b).unwrap();
+        std::fs::rename(&b, &c).unwrap();
+        assert_eq!(tracker.renames(), Some(vec![(a.clone(), c.clone())]));
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![a.clone(), b, c.clone()])
+        );
+        assert_eq!(
+            changes.try_iter().take(2).collect::<Vec<_>>(),
+            vec![
+                DirtyNotification {
+                    path: a.clone(),
+                    kind: ChangeKind::RenamedFrom,
+                    sequence: 1,
+                },
+                DirtyNotification {
+                    path: dir.path().join("b"),
+                    kind: ChangeKind::RenamedTo,
+                    sequence: 2,
+                },
+            ]
+        );
+
+        // Renaming it back is not a rename.
+        std::fs::rename(&c, &a).unwrap();
+        assert_eq!(tracker.renames(), Some(vec![]));
+        tracker.mark_clean();
+
+        // A file that was created and renamed away is gone.
+        std::fs::write(dir.path().join("tmp"), b"tmp").unwrap();
+        std::fs::rename(dir.path().join("tmp"), &c).unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![c.clone()]));
+    }
+
+    #[test]
+    fn test_dir_removed() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        std::fs::create_dir_all(sub.join("nested")).unwrap();
+        std::fs::write(sub.join("a"), b"a").unwrap();
+        std::fs::write(sub.join("nested/b"), b"b").unwrap();
+
+        // The watcher only reports the directory itself.
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).tree_snapshot(true));
+        inject(&sender, CREATE, &sub.join("new"));
+        std::fs::remove_dir_all(&sub).unwrap();
+        inject(
+            &sender,
+            EventKind::Remove(notify::event::RemoveKind::Folder),
+            &sub,
+        );
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![
+                sub.clone(),
+                sub.join("a"),
+                sub.join("nested"),
+                sub.join("nested/b")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_dir_removed_without_snapshot() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        std::fs::create_dir_all(sub.join("nested")).unwrap();
+        std::fs::write(sub.join("a"), b"a").unwrap();
+
+        // Without a snapshot, the original entries below the directory are
+        // unknown; those created since are not dirty any more.
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        inject(&sender, CREATE, &sub.join("new"));
+        std::fs::remove_dir_all(&sub).unwrap();
+        inject(
+            &sender,
+            EventKind::Remove(notify::event::RemoveKind::Folder),
+            &sub,
+        );
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![sub.clone()]));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dir_moved() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        let moved = dir.path().join("moved");
+        std::fs::create_dir_all(sub.join("nested")).unwrap();
+        std::fs::write(sub.join("nested/a"), b"a").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .build()
+            .unwrap();
+        std::fs::rename(&sub, &moved).unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![
+                sub.clone(),
+                sub.join("nested"),
+                sub.join("nested/a"),
+                moved.clone(),
+                moved.join("nested"),
+                moved.join("nested/a")
+            ])
+        );
+
+        // Entries that weren't there before are new.
+        std::fs::remove_file(moved.join("nested/a")).unwrap();
+        assert!(!tracker.paths().unwrap().contains(&moved.join("nested/a")));
+    }
+
+    #[test]
+    fn test_ignore_editor_files() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .ignore_editor_files(true)
+            .build()
+            .unwrap();
+        std::fs::write(dir.path().join(".file.swp"), b"swap").unwrap();
+        std::fs::write(dir.path().join("file~"), b"backup").unwrap();
+        std::fs::write(dir.path().join("file"), b"file").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("file")])
+        );
+        tracker.rescan().unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("file")])
+        );
+    }
+
+    #[test]
+    fn test_change_kinds() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        std::fs::write(&a, b"a").unwrap();
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path())
+                .change_kinds(&[ChangeKind::Created, ChangeKind::Removed]),
+        );
+        inject(&sender, MODIFY, &a);
+        assert_eq!(tracker.state(), State::Clean);
+        inject(&sender, CREATE, &b);
+        inject(&sender, MODIFY, &b);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![b.clone()]));
+        inject(&sender, REMOVE, &b);
+        assert_eq!(tracker.state(), State::Clean);
+
+        // Without removals, paths that come and go are left out too.
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).change_kinds(&[ChangeKind::Modified]));
+        inject(&sender, CREATE, &b);
+        inject(&sender, MODIFY, &b);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![b.clone()]));
+        inject(&sender, REMOVE, &b);
+        assert_eq!(tracker.state(), State::Clean);
+        inject(&sender, REMOVE, &a);
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ignore_metadata_changes() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"a").unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .ignore_metadata_changes(true)
+            .build()
+            .unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        std::fs::write(&file, b"b").unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file]));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_track_access() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"a").unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .track_access(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.accessed(), Some(&HashSet::new()));
+        assert_eq!(std::fs::read(&file).unwrap(), b"a");
+        assert_eq!(tracker.accessed(), Some(&maplit::hashset![file]));
+        assert_eq!(tracker.state(), State::Clean);
+        tracker.mark_clean();
+        assert_eq!(tracker.accessed(), Some(&HashSet::new()));
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.accessed(), None);
+    }
+
+    #[test]
+    fn test_directories() {
+        let dir = tempdir().unwrap();
+        let old = dir.path().join("old");
+        std::fs::create_dir(&old).unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .directories(DirectoryPolicy::Separate)
+            .build()
+            .unwrap();
+        let changes = tracker.subscribe();
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        std::fs::write(dir.path().join("subdir/file"), b"a").unwrap();
+        std::fs::remove_dir(&old).unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("subdir/file")])
+        );
+        assert_eq!(
+            tracker.dirty_dirs(),
+            Some(&maplit::hashset![dir.path().join("subdir"), old.clone()])
+        );
+        assert!(changes
+            .try_iter()
+            .any(|change| change.path == dir.path().join("subdir")));
+        tracker.rescan().unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("subdir/file")])
+        );
+        assert_eq!(
+            tracker.dirty_dirs(),
+            Some(&maplit::hashset![dir.path().join("subdir"), old.clone()])
+        );
+
+        // Removing a directory that was just created leaves nothing behind.
+        tracker.mark_clean();
+        std::fs::create_dir(&old).unwrap();
+        std::fs::remove_dir(&old).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert_eq!(tracker.dirty_dirs(), Some(&HashSet::new()));
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .directories(DirectoryPolicy::Omit)
+            .build()
+            .unwrap();
+        let changes = tracker.subscribe();
+        std::fs::create_dir(dir.path().join("other")).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert_eq!(tracker.dirty_dirs(), Some(&HashSet::new()));
+        assert!(changes.try_iter().next().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_entries() {
+        let dir = tempdir().unwrap();
+        let removed = dir.path().join("removed");
+        let gone = dir.path().join("gone");
+        std::fs::create_dir(&removed).unwrap();
+        std::os::unix::fs::symlink("target", &gone).unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .build()
+            .unwrap();
+        std::fs::remove_dir(&removed).unwrap();
+        std::fs::remove_file(&gone).unwrap();
+        std::fs::write(dir.path().join("file"), b"a").unwrap();
+        let entry = |name: &str, file_type, exists| DirtyEntry {
+            path: dir.path().join(name),
+            file_type: Some(file_type),
+            exists,
+        };
+        assert_eq!(
+            tracker.entries(),
+            Some(vec![
+                entry("file", FileType::File, true),
+                entry("gone", FileType::Symlink, false),
+                entry("removed", FileType::Dir, false),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_is_subtree_dirty() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::create_dir(dir.path().join("c")).unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.is_subtree_dirty(dir.path()), Some(false));
+        std::fs::write(dir.path().join("a/b/file"), b"a").unwrap();
+        assert_eq!(tracker.is_subtree_dirty(dir.path()), Some(true));
+        assert_eq!(tracker.is_subtree_dirty(&dir.path().join("a")), Some(true));
+        assert_eq!(tracker.is_subtree_dirty(&dir.path().join("c")), Some(false));
+        std::fs::remove_file(dir.path().join("a/b/file")).unwrap();
+        assert_eq!(tracker.is_subtree_dirty(&dir.path().join("a")), Some(false));
+    }
+
+    #[test]
+    fn test_approximate() {
+        let dir = tempdir().unwrap();
+        let a = dir.path().join("a");
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).approximate(1));
+        inject(&sender, CREATE, &a.join("b/c"));
+        inject(&sender, CREATE, &a.join("d"));
+        inject(&sender, REMOVE, &a.join("d"));
+        inject(&sender, CREATE, &dir.path().join("e"));
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![a.clone(), dir.path().join("e")])
+        );
+        assert_eq!(tracker.is_subtree_dirty(&a.join("x/y")), Some(true));
+        assert_eq!(tracker.is_subtree_dirty(&dir.path().join("f")), Some(false));
+
+        // Changes right at the depth are tracked exactly.
+        inject(&sender, REMOVE, &dir.path().join("e"));
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![a]));
+    }
+
+    #[test]
+    fn test_drain_paths() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+        assert_eq!(
+            tracker.paths_iter().unwrap().collect::<Vec<_>>(),
+            vec![dir.path().join("a").as_path()]
+        );
+        assert_eq!(
+            tracker.drain_paths().unwrap().collect::<Vec<_>>(),
+            vec![dir.path().join("a")]
+        );
+        assert_eq!(tracker.state(), State::Clean);
+        assert_eq!(tracker.drain_paths().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_eventual_consistency() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .consistency(Consistency::Eventual)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        // No sentinel file is written.
+        assert_eq!(tracker.stats().last_flush, None);
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+        tracker.sync().unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("a")])
+        );
+    }
+
+    #[test]
+    fn test_event_queue_capacity() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).event_queue_capacity(2));
+        for i in 0..10 {
+            inject(&sender, MODIFY, &dir.path().join(i.to_string()));
+        }
+        let start = std::time::Instant::now();
+        while tracker.try_state() != Err(UnknownReason::Overflow) {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(tracker.stats().events_dropped > 0);
+        tracker.mark_clean();
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_event_queue_overflow_during_flush() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .event_queue_capacity(1)
+            .build()
+            .unwrap();
+        for i in 0..100 {
+            std::fs::write(dir.path().join(i.to_string()), b"hello").unwrap();
+        }
+        // The sentinel's events are dropped along with the others, which
+        // must not leave the query waiting for them.
+        assert_eq!(
+            tracker.try_state(),
+            Err(UnknownReason::Overflow),
+            "{:?}",
+            tracker.stats()
+        );
+    }
+
+    #[test]
+    fn test_event_queue_capacity_zero() {
+        let dir = tempdir().unwrap();
+        let result = DirtyTracker::builder(dir.path())
+            .event_queue_capacity(0)
+            .build();
+        assert!(matches!(result, Err(DirtyTrackerError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn test_eager_drain() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path())
+                .eager_drain(true)
+                .event_queue_capacity(2),
+        );
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        for _ in 0..10 {
+            inject(&sender, MODIFY, &a);
+        }
+        inject(&sender, MODIFY, &b);
+        let start = std::time::Instant::now();
+        while tracker.try_paths().unwrap() != &maplit::hashset![a.clone(), b.clone()] {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(tracker.stats().events_dropped, 0);
+    }
+
+    #[cfg(feature = "debounce")]
+    #[test]
+    fn test_debounce() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).debounce(std::time::Duration::from_millis(200)),
+        );
+        let a = dir.path().join("a");
+        for _ in 0..5 {
+            inject(&sender, MODIFY, &a);
+        }
+        assert_eq!(tracker.try_state(), Ok(State::Clean));
+        let start = std::time::Instant::now();
+        while tracker.try_state() != Ok(State::Dirty) {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(tracker.stats().events, 1);
+    }
+
+    #[test]
+    fn test_coalesce_window() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).coalesce_window(std::time::Duration::from_secs(60)),
+        );
+        let rx = tracker.subscribe();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        for _ in 0..3 {
+            inject(&sender, MODIFY, &a);
+        }
+        inject(&sender, MODIFY, &b);
+        assert_eq!(
+            tracker.paths().unwrap(),
+            &maplit::hashset![a.clone(), b.clone()]
+        );
+        assert_eq!(
+            rx.try_iter().map(|n| n.path).collect::<Vec<_>>(),
+            vec![a.clone(), b]
+        );
+
+        // A clean tree starts afresh.
+        tracker.mark_clean();
+        inject(&sender, MODIFY, &a);
+        assert_eq!(tracker.state(), State::Dirty);
+        assert_eq!(rx.try_iter().map(|n| n.path).collect::<Vec<_>>(), vec![a]);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let c = dir.path().join("c");
+        inject(&sender, MODIFY, &a);
+        inject(&sender, MODIFY, &b);
+        let first = tracker.checkpoint().unwrap();
+        inject(&sender, MODIFY, &b);
+        inject(&sender, MODIFY, &c);
+        let second = tracker.checkpoint().unwrap();
+        assert!(first < second);
+        tracker.mark_clean_at(first).unwrap();
+        assert_eq!(tracker.paths().unwrap(), &maplit::hashset![b, c]);
+        tracker.mark_clean_at(second).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_checkpoint_flush_error() {
+        struct Failing;
+
+        impl FlushStrategy for Failing {
+            fn flush(
+                &mut self,
+                _events: &mut PendingEvents<'_>,
+                _timeout: Option<std::time::Duration>,
+            ) -> Result<(), DirtyTrackerError> {
+                Err(DirtyTrackerError::Sentinel(std::io::Error::other("full")))
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).flush_strategy(NoFlush));
+        let a = dir.path().join("a");
+        let first = tracker.checkpoint().unwrap();
+        inject(&sender, MODIFY, &a);
+        tracker.flush = Some(Box::new(Failing));
+        assert!(matches!(
+            tracker.checkpoint(),
+            Err(DirtyTrackerError::Sentinel(_))
+        ));
+        assert!(matches!(
+            tracker.mark_clean_at(first),
+            Err(DirtyTrackerError::Sentinel(_))
+        ));
+        assert!(tracker.recent_events().is_err());
+    }
+
+    #[test]
+    fn test_dirty_since() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let c = dir.path().join("c");
+        inject(&sender, MODIFY, &a);
+        inject(&sender, MODIFY, &b);
+        let first = tracker.checkpoint().unwrap();
+        assert_eq!(tracker.dirty_since(first).unwrap(), HashSet::new());
+        inject(&sender, MODIFY, &b);
+        inject(&sender, MODIFY, &c);
+        let second = tracker.checkpoint().unwrap();
+        assert_eq!(
+            tracker.dirty_since(first).unwrap(),
+            maplit::hashset![b.as_path(), c.as_path()]
+        );
+        inject(&sender, MODIFY, &a);
+        assert_eq!(
+            tracker.dirty_since(second).unwrap(),
+            maplit::hashset![a.as_path()]
+        );
+
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.dirty_since(second), None);
+    }
+
+    #[test]
+    fn test_release_checkpoint() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let first = tracker.checkpoint().unwrap();
+        inject(&sender, MODIFY, &a);
+        let second = tracker.checkpoint().unwrap();
+        inject(&sender, MODIFY, &b);
+        tracker.release(first);
+        assert_eq!(
+            tracker.dirty_since(second).unwrap(),
+            maplit::hashset![b.as_path()]
+        );
+        tracker.release(second);
+        inject(&sender, MODIFY, &a);
+        let third = tracker.checkpoint().unwrap();
+        assert!(tracker.dirty_since(third).unwrap().is_empty());
+        assert_eq!(tracker.paths().unwrap(), &maplit::hashset![a, b]);
+    }
+
+    #[test]
+    fn test_changes_between() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let start = tracker.checkpoint().unwrap();
+        inject(&sender, CREATE, &a);
+        inject(&sender, MODIFY, &a);
+        let built = tracker.checkpoint().unwrap();
+        inject(&sender, MODIFY, &b);
+        tracker.mark_clean();
+        inject(&sender, REMOVE, &a);
+        let tested = tracker.checkpoint().unwrap();
+        assert_eq!(
+            tracker.changes_between(start, built).unwrap(),
+            maplit::btreemap! {a.as_path() => ChangeKind::Created}
+        );
+        assert_eq!(
+            tracker.changes_between(built, tested).unwrap(),
+            maplit::btreemap! {a.as_path() => ChangeKind::Removed, b.as_path() => ChangeKind::Modified}
+        );
+        assert_eq!(
+            tracker.changes_between(start, tested).unwrap(),
+            maplit::btreemap! {b.as_path() => ChangeKind::Modified}
+        );
+    }
+
+    #[test]
+    fn test_changes_between_rescan() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).tree_snapshot(true));
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let start = tracker.checkpoint().unwrap();
+        std::fs::write(&a, b"a").unwrap();
+        inject(&sender, CREATE, &a);
+        let before = tracker.checkpoint().unwrap();
+        // The event for b was missed.
+        std::fs::write(&b, b"b").unwrap();
+        tracker.rescan().unwrap();
+        let after = tracker.checkpoint().unwrap();
+        assert_eq!(
+            tracker.changes_between(start, before).unwrap(),
+            maplit::btreemap! {a.as_path() => ChangeKind::Created}
+        );
+        assert_eq!(
+            tracker.changes_between(before, after).unwrap(),
+            maplit::btreemap! {b.as_path() => ChangeKind::Created}
+        );
+    }
+
+    #[test]
+    fn test_views() {
+        let dir = tempdir().unwrap();
+        let (tracker, _) = build_injected(DirtyTracker::builder(dir.path()));
+        assert!(matches!(
+            DirtyTracker::builder(dir.path()).build_view(&tracker),
+            Err(DirtyTrackerError::ViewsNotAllowed)
+        ));
+
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).allow_views(true));
+        let mut view = DirtyTracker::builder(dir.path())
+            .build_view(&tracker)
+            .unwrap();
+        let a = dir.path().join("a");
+        inject(&sender, MODIFY, &a);
+        let start = std::time::Instant::now();
+        while view.try_state() != Ok(State::Dirty) || tracker.try_state() != Ok(State::Dirty) {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        view.mark_clean();
+        assert_eq!(view.try_state(), Ok(State::Clean));
+        assert_eq!(tracker.try_paths().unwrap(), &maplit::hashset![a.clone()]);
+
+        // Views outlive the tracker.
+        drop(tracker);
+        inject(&sender, MODIFY, &a);
+        while view.try_state() != Ok(State::Dirty) {
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_tee() {
+        let dir = tempdir().unwrap();
+        let (tx, rx) = channel();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()).tee(tx));
+        let a = dir.path().join("a");
+        inject(&sender, CREATE, &a);
+        assert_eq!(tracker.paths().unwrap(), &maplit::hashset![a.clone()]);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            Event::new(CREATE).add_path(a.clone())
+        );
+        assert!(rx.try_recv().is_err());
+
+        // Dropping the receiver stops the copies.
+        drop(rx);
+        inject(&sender, MODIFY, &a);
+        assert_eq!(tracker.state(), State::Dirty);
+        assert!(tracker.tee.is_none());
+    }
+
+    #[test]
+    fn test_from_glob() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        std::fs::create_dir_all(src.join("sub")).unwrap();
+        let pattern = format!("{}/src/**/*.rs", dir.path().display());
+        let mut tracker = DirtyTrackerBuilder::from_glob(&pattern).build().unwrap();
+        assert_eq!(tracker.path(), src);
+        std::fs::write(dir.path().join("build.rs"), b"").unwrap();
+        std::fs::write(src.join("lib.rs"), b"").unwrap();
+        std::fs::write(src.join("sub/mod.rs"), b"").unwrap();
+        std::fs::write(src.join("sub/README"), b"").unwrap();
+        assert_eq!(
+            tracker.paths().unwrap(),
+            &maplit::hashset![src.join("lib.rs"), src.join("sub/mod.rs")]
+        );
+
+        // Without wildcard directories, only the base itself is watched.
+        let pattern = format!("{}/src/*.rs", dir.path().display());
+        let (mut tracker, sender) = build_injected(DirtyTrackerBuilder::from_glob(&pattern));
+        inject(&sender, CREATE, &src.join("main.rs"));
+        inject(&sender, CREATE, &src.join("sub/lib.rs"));
+        assert_eq!(
+            tracker.paths().unwrap(),
+            &maplit::hashset![src.join("main.rs")]
+        );
+        assert_eq!(tracker.filter.recursive_mode(), RecursiveMode::NonRecursive);
+    }
+
+    #[test]
+    fn test_from_glob_relative() {
+        let cwd = std::env::current_dir().unwrap();
+        let dir = tempfile::tempdir_in(&cwd).unwrap();
+        let src = dir.path().join("src");
+        std::fs::create_dir(&src).unwrap();
+        let relpath = src.strip_prefix(&cwd).unwrap();
+        let pattern = format!("{}/**/*.rs", relpath.display());
+        let mut tracker = DirtyTrackerBuilder::from_glob(&pattern).build().unwrap();
+        assert_eq!(tracker.path(), src);
+        assert_eq!(tracker.state(), State::Clean);
+        std::fs::write(src.join("lib.rs"), b"").unwrap();
+        std::fs::write(src.join("README"), b"").unwrap();
+        assert_eq!(
+            tracker.paths().unwrap(),
+            &maplit::hashset![src.join("lib.rs")]
+        );
+    }
+
+    #[test]
+    fn test_for_files() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::create_dir_all(a.join("sub")).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        let (config, cert) = (a.join("config"), b.join("cert"));
+        std::fs::write(&cert, b"old").unwrap();
+        let mut tracker = DirtyTracker::for_files(&[config.clone(), cert.clone()]).unwrap();
+        assert_eq!(tracker.path(), dir.path());
+        std::fs::write(a.join("other"), b"").unwrap();
+        std::fs::write(a.join("sub/config"), b"").unwrap();
+        std::fs::write(&config, b"").unwrap();
+        std::fs::write(&cert, b"new!").unwrap();
+        assert_eq!(
+            tracker.paths().unwrap(),
+            &maplit::hashset![config.clone(), cert.clone()]
+        );
+
+        tracker.mark_clean();
+        std::fs::remove_file(&cert).unwrap();
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.paths().unwrap(), &maplit::hashset![cert]);
+    }
+
+    #[test]
+    fn test_for_files_empty() {
+        assert!(matches!(
+            DirtyTracker::for_files(&[]),
+            Err(DirtyTrackerError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn test_for_files_relative() {
+        let cwd = std::env::current_dir().unwrap();
+        let dir = tempfile::tempdir_in(&cwd).unwrap();
+        let config = dir.path().join("config");
+        let relpath = config.strip_prefix(&cwd).unwrap();
+        let mut tracker = DirtyTracker::for_files(&[relpath.to_path_buf()]).unwrap();
+        assert_eq!(tracker.path(), dir.path());
+        assert_eq!(tracker.state(), State::Clean);
+        std::fs::write(dir.path().join("other"), b"").unwrap();
+        std::fs::write(&config, b"").unwrap();
+        assert_eq!(tracker.paths().unwrap(), &maplit::hashset![config]);
+    }
+
+    #[test]
+    fn test_on_transition() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        let transitions = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = transitions.clone();
+        tracker.on_transition(move |transition| seen.lock().unwrap().push(transition.clone()));
+        let take = || std::mem::take(&mut *transitions.lock().unwrap());
+        let a = dir.path().join("a");
+        inject(&sender, CREATE, &a);
+        inject(&sender, MODIFY, &a);
+        assert_eq!(tracker.state(), State::Dirty);
+        assert_eq!(
+            take(),
+            vec![Transition {
+                from: State::Clean,
+                to: State::Dirty,
+                path: Some(a.clone()),
+            }]
+        );
+
+        tracker.mark_clean();
+        sender
+            .send(Ok(
+                Event::new(EventKind::Other).set_flag(notify::event::Flag::Rescan)
+            ))
+            .unwrap();
+        assert_eq!(tracker.state(), State::Unknown);
+        assert_eq!(
+            take(),
+            vec![
+                Transition {
+                    from: State::Dirty,
+                    to: State::Clean,
+                    path: None,
+                },
+                Transition {
+                    from: State::Clean,
+                    to: State::Unknown,
+                    path: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recent_events() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).keep_recent_events(2));
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        inject(&sender, CREATE, &a);
+        inject(&sender, MODIFY, &a);
+        inject(&sender, REMOVE, &b);
+        assert_eq!(
+            tracker
+                .recent_events()
+                .unwrap()
+                .cloned()
+                .collect::<Vec<_>>(),
+            vec![
+                Event::new(MODIFY).add_path(a.clone()),
+                Event::new(REMOVE).add_path(b.clone()),
+            ]
+        );
+
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        inject(&sender, CREATE, &a);
+        assert_eq!(tracker.recent_events().unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_eventual_consistency_nonblocking() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path())
+                .consistency(Consistency::Eventual)
+                .flush_strategy(SettleFlush::new(std::time::Duration::from_secs(3600))),
+        );
+        assert_eq!(tracker.state(), State::Clean);
+        inject(&sender, CREATE, &dir.path().join("a"));
+        let start = std::time::Instant::now();
+        assert_eq!(tracker.state(), State::Dirty);
+        assert!(start.elapsed() < std::time::Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_state_timeout() {
+        let dir = tempdir().unwrap();
+        let hour = std::time::Duration::from_secs(3600);
+        let timeout = std::time::Duration::from_millis(10);
+        // Events can't settle within the timeout.
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).flush_strategy(SettleFlush::new(hour)),
+        );
+        let a = dir.path().join("a");
+        inject(&sender, CREATE, &a);
+        assert!(matches!(
+            tracker.state_timeout(timeout),
+            Err(DirtyTrackerError::Timeout(t)) if t == timeout
+        ));
+        assert!(matches!(
+            tracker.paths_timeout(timeout),
+            Err(DirtyTrackerError::Timeout(_))
+        ));
+        tracker.consistency = Consistency::Eventual;
+        assert_eq!(tracker.state(), State::Dirty);
+
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).flush_strategy(SettleFlush::new(timeout)),
+        );
+        inject(&sender, CREATE, &a);
+        assert_eq!(tracker.state_timeout(hour).unwrap(), State::Dirty);
+        assert_eq!(
+            tracker.paths_timeout(hour).unwrap(),
+            &maplit::hashset![a.clone()]
+        );
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.state_timeout(hour).unwrap(), State::Unknown);
+        assert!(matches!(
+            tracker.paths_timeout(hour),
+            Err(DirtyTrackerError::Unknown(UnknownReason::Overflow))
+        ));
+    }
+
+    #[test]
+    fn test_late_sentinel_events() {
+        let dir = tempdir().unwrap();
+        let timeout = std::time::Duration::from_millis(10);
+        // The watcher never reports the sentinel.
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).flush_strategy(SentinelFlush::new()));
+        let rx = tracker.subscribe();
+        assert!(matches!(
+            tracker.state_timeout(timeout),
+            Err(DirtyTrackerError::Timeout(_))
+        ));
+        let sentinel = tracker.late_sentinels.iter().next().unwrap().clone();
+        inject(&sender, CREATE, &sentinel);
+        inject(&sender, MODIFY, &sentinel);
+        inject(&sender, REMOVE, &sentinel);
+        tracker.consistency = Consistency::Eventual;
+        assert_eq!(tracker.state(), State::Clean);
+        assert_eq!(rx.try_iter().count(), 0);
+        assert!(tracker.late_sentinels.is_empty());
+    }
+
+    #[test]
+    fn test_pending_events() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        assert_eq!(tracker.pending_events(), 0);
+        let a = dir.path().join("a");
+        inject(&sender, CREATE, &a);
+        inject(&sender, MODIFY, &a);
+        assert_eq!(tracker.pending_events(), 2);
+        inject(&sender, REMOVE, &dir.path().join("b"));
+        assert_eq!(tracker.pending_events(), 3);
+        assert_eq!(
+            tracker.paths().unwrap(),
+            &maplit::hashset![a, dir.path().join("b")]
+        );
+        assert_eq!(tracker.pending_events(), 0);
+    }
+
+    #[test]
+    fn test_sequence() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path())
+                .tree_snapshot(true)
+                .coalesce_window(std::time::Duration::from_secs(3600)),
+        );
+        let rx = tracker.subscribe();
+        let a = dir.path().join("a");
+        assert_eq!(tracker.sequence(), 0);
+        inject(&sender, CREATE, &a);
+        inject(&sender, MODIFY, &a);
+        // Coalesced repeats are numbered, but not passed on.
+        inject(&sender, MODIFY, &a);
+        tracker.sync().unwrap();
+        assert_eq!(tracker.sequence(), 3);
+        assert_eq!(
+            rx.try_iter().map(|n| n.sequence).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        tracker.mark_clean();
+        assert_eq!(tracker.sequence(), 3);
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.sequence(), 4);
+
+        // Losing track of the changes is a change, but only once.
+        let rescan = Event::new(EventKind::Other).set_flag(notify::event::Flag::Rescan);
+        sender.send(Ok(rescan.clone())).unwrap();
+        sender.send(Ok(rescan)).unwrap();
+        assert_eq!(tracker.try_state(), Err(UnknownReason::Overflow));
+        assert_eq!(tracker.sequence(), 5);
+    }
+
+    #[test]
+    fn test_sorted_paths() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        for name in ["c", "a", "b"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let (a, b, c) = (
+            dir.path().join("a"),
+            dir.path().join("b"),
+            dir.path().join("c"),
+        );
+        assert_eq!(
+            tracker
+                .sorted_paths()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![a.as_path(), b.as_path(), c.as_path()]
+        );
+    }
+
+    #[test]
+    fn test_paths_by_dir() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.paths_by_dir(), Some(BTreeMap::new()));
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        std::fs::write(dir.path().join("subdir/b"), b"b").unwrap();
+        std::fs::write(dir.path().join("subdir/a"), b"a").unwrap();
+        std::fs::write(dir.path().join("c"), b"c").unwrap();
+        let subdir = dir.path().join("subdir");
+        assert_eq!(
+            tracker.paths_by_dir(),
+            Some(maplit::btreemap! {
+                dir.path().to_path_buf() => vec![dir.path().join("c"), subdir.clone()],
+                subdir.clone() => vec![subdir.join("a"), subdir.join("b")],
+            })
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+        std::fs::write(dir.path().join("b"), b"b").unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.report().unwrap().to_string(), "No changes\n");
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        std::fs::write(dir.path().join("subdir/c"), b"c").unwrap();
+        std::fs::write(dir.path().join("a"), b"A").unwrap();
+        std::fs::remove_file(dir.path().join("b")).unwrap();
+        let report = tracker.report().unwrap();
+        assert_eq!(report.created, 2);
+        assert_eq!(report.modified, 1);
+        assert_eq!(report.removed, 1);
+        assert_eq!(
+            report.dirs,
+            maplit::btreemap! { PathBuf::from("subdir") => 1 }
+        );
+        assert_eq!(
+            report.max_paths(3).to_string(),
+            "4 paths changed: 2 created, 1 modified, 1 removed\n\
+             Directories: subdir (1)\n  \
+             M a\n  \
+             D b\n  \
+             A subdir\n  \
+             ... and 1 more\n"
+        );
+        // Recreating a removed file makes it modified.
+        std::fs::write(dir.path().join("b"), b"B").unwrap();
+        assert_eq!(tracker.report().unwrap().modified, 2);
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.report(), Err(UnknownReason::Overflow));
+    }
+
+    #[test]
+    fn test_rollup_threshold() {
+        let dir = tempdir().unwrap();
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir(&subdir).unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).rollup_threshold(2));
+        inject(&sender, MODIFY, &subdir.join("a"));
+        inject(&sender, MODIFY, &subdir.join("b"));
+        inject(&sender, MODIFY, &dir.path().join("c"));
+        assert_eq!(tracker.paths().unwrap().len(), 3);
+        inject(&sender, MODIFY, &subdir.join("d"));
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![subdir.clone(), dir.path().join("c")])
+        );
+        assert_eq!(
+            tracker.rolled_up_dirs(),
+            Some(maplit::hashset![subdir.clone()])
+        );
+
+        // Later changes are absorbed.
+        inject(&sender, MODIFY, &subdir.join("nested/e"));
+        assert_eq!(tracker.paths().unwrap().len(), 2);
+
+        tracker.mark_clean();
+        assert_eq!(tracker.rolled_up_dirs(), Some(HashSet::new()));
+        inject(&sender, MODIFY, &subdir.join("a"));
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![subdir.join("a")]));
+    }
+
+    #[test]
+    fn test_max_dirty_paths() {
+        let dir = tempdir().unwrap();
+        let subdir = dir.path().join("subdir");
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).max_dirty_paths(3, LimitPolicy::Unknown),
+        );
+        for name in ["a", "b", "c"] {
+            inject(&sender, MODIFY, &subdir.join(name));
+        }
+        assert_eq!(tracker.paths().unwrap().len(), 3);
+        inject(&sender, MODIFY, &subdir.join("d"));
+        assert_eq!(
+            tracker.try_state(),
+            Err(UnknownReason::TooManyChanges { limit: 3 })
+        );
+        tracker.mark_clean();
+        assert_eq!(tracker.state(), State::Clean);
+
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).max_dirty_paths(3, LimitPolicy::RollUp),
+        );
+        inject(&sender, MODIFY, &dir.path().join("e"));
+        for name in ["a", "b", "c"] {
+            inject(&sender, MODIFY, &subdir.join(name));
+        }
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![subdir.clone(), dir.path().join("e")])
+        );
+        inject(&sender, MODIFY, &dir.path().join("f"));
+        inject(&sender, MODIFY, &dir.path().join("g"));
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().to_path_buf()])
+        );
+    }
+
+    #[test]
+    fn test_dirty_bytes() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("removed"), b"removed").unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.dirty_bytes(), Some(0));
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        std::fs::write(dir.path().join("subdir/a"), b"abc").unwrap();
+        std::fs::write(dir.path().join("b"), b"de").unwrap();
+        std::fs::remove_file(dir.path().join("removed")).unwrap();
+        assert_eq!(tracker.dirty_bytes(), Some(5));
+    }
+
+    #[test]
+    fn test_atomic_save() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        let tmp = dir.path().join("file.tmp");
+        std::fs::write(&file, b"old").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        std::fs::write(&tmp, b"new").unwrap();
+        std::fs::rename(&tmp, &file).unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(tracker.renames(), Some(vec![]));
+
+        // Watchers that can't tell the two sides of a rename apart.
+        let rename = EventKind::Modify(notify::event::ModifyKind::Name(RenameMode::Any));
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        inject(&sender, CREATE, &tmp);
+        inject(&sender, rename, &tmp);
+        inject(&sender, rename, &file);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_track_file_ids() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        let tmp = dir.path().join("file.tmp");
+        std::fs::write(&file, b"old").unwrap();
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .track_file_ids(true)
+            .build()
+            .unwrap();
+        std::fs::write(&tmp, b"new").unwrap();
+        std::fs::rename(&tmp, &file).unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(tracker.moved_from(&file), None);
+
+        std::fs::rename(dir.path().join("a"), dir.path().join("b")).unwrap();
+        assert_eq!(
+            tracker.moved_from(&dir.path().join("b")),
+            Some(dir.path().join("a"))
+        );
+        tracker.mark_clean();
+        assert_eq!(tracker.moved_from(&dir.path().join("b")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_follow() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("file"), b"file").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("dir")).unwrap();
+        std::os::unix::fs::symlink(outside.join("file"), root.join("file")).unwrap();
+
+        let mut tracker = DirtyTracker::builder(&root)
+            .symlinks(SymlinkPolicy::Follow)
+            .build()
+            .unwrap();
+        std::fs::write(outside.join("file"), b"changed").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![root.join("dir/file"), root.join("file")])
+        );
+
+        // Links created later on are followed as well.
+        tracker.mark_clean();
+        std::os::unix::fs::symlink(&outside, root.join("new")).unwrap();
+        tracker.sync().unwrap();
+        tracker.mark_clean();
+        std::fs::write(outside.join("other"), b"other").unwrap();
+        let paths = tracker.paths().unwrap();
+        assert!(paths.contains(&root.join("new/other")));
+        assert!(paths.iter().all(|path| path.starts_with(&root)));
+    }
+
+    #[test]
+    fn test_symlinks_ignore() {
+        let dir = tempdir().unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).symlinks(SymlinkPolicy::Ignore));
+        inject(&sender, MODIFY, Path::new("/elsewhere/file"));
+        inject(&sender, MODIFY, &dir.path().join("file"));
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![dir.path().join("file")])
+        );
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        let dir = tempdir().unwrap();
+        let nfc = dir.path().join("caf\u{e9}");
+        let nfd = dir.path().join("cafe\u{301}");
+
+        let (mut tracker, sender) = build_injected(
+            DirtyTracker::builder(dir.path()).unicode_normalization(UnicodeForm::Nfc),
+        );
+        inject(&sender, MODIFY, &nfd);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![nfc.clone()]));
+        assert_eq!(tracker.is_path_dirty(&nfd), Some(true));
+        assert_eq!(tracker.is_path_dirty(&nfc), Some(true));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("File");
+        let upper = dir.path().join("FILE");
+
+        let (mut tracker, sender) = build_injected(DirtyTracker::builder(dir.path()));
+        inject(&sender, MODIFY, &file);
+        inject(&sender, MODIFY, &upper);
+        assert_eq!(tracker.paths().unwrap().len(), 2);
+        assert_eq!(tracker.is_path_dirty(&dir.path().join("file")), Some(false));
+
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(dir.path()).case_insensitive(true));
+        inject(&sender, MODIFY, &file);
+        inject(&sender, MODIFY, &upper);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(tracker.is_path_dirty(&dir.path().join("file")), Some(true));
+        tracker
+            .wait_for_path(&upper, std::time::Duration::from_secs(1))
+            .unwrap();
+
+        // Removing a newly created path undoes its creation, whatever the
+        // casing.
+        inject(&sender, CREATE, &dir.path().join("New"));
+        inject(&sender, REMOVE, &dir.path().join("new"));
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file]));
+    }
+
+    #[test]
+    fn test_relpath_base() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        std::fs::create_dir_all(sub.join("a")).unwrap();
+
+        let mut tracker = DirtyTracker::builder(&sub)
+            .relpath_base(dir.path())
+            .build()
+            .unwrap();
+        std::fs::write(sub.join("file"), b"hello").unwrap();
+        std::fs::write(sub.join("a/file"), b"hello").unwrap();
+        assert_eq!(
+            tracker.relpaths(),
+            Some(maplit::hashset![
+                Path::new("sub/file"),
+                Path::new("sub/a/file")
+            ])
+        );
+        assert_eq!(
+            tracker.relpaths_relative_to(&sub.join("a")),
+            Some(maplit::hashset![Path::new("file")])
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        let new_file = dir.path().join("new_file");
+        std::fs::rename(&file, &new_file).unwrap();
+
+        wait_for(
+            &mut tracker,
+            &maplit::hashset![new_file.clone(), file.clone()],
+            State::Dirty,
+        );
+
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![file.clone(), new_file.clone()])
+        );
+        assert_eq!(tracker.state(), State::Dirty);
+    }
+
+    #[test]
+    fn test_mark_clean() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        std::fs::write(&file, b"world").unwrap();
+
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(tracker.state(), State::Dirty);
+
+        tracker.mark_clean();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        let file2 = dir.path().join("file2");
+        std::fs::write(&file2, b"world").unwrap();
+
+        wait_for(&mut tracker, &maplit::hashset![file2.clone()], State::Dirty);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file2.clone()]));
+        assert_eq!(tracker.state(), State::Dirty);
+
+        std::fs::remove_file(&file2).unwrap();
+
+        wait_for(&mut tracker, &maplit::hashset![], State::Clean);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![]));
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_follow_subdir() {
+        let dir = tempdir().unwrap();
+
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir(&subdir).unwrap();
+
+        let file = subdir.join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        std::fs::write(&file, b"world").unwrap();
+
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert_eq!(tracker.state(), State::Dirty);
+    }
+
+    #[test]
+    fn test_create_and_follow_subdir() {
+        let dir = tempdir().unwrap();
+
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.paths().unwrap().is_empty());
+
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir(&subdir).unwrap();
+
+        wait_for(
+            &mut tracker,
+            &maplit::hashset![subdir.clone()],
+            State::Dirty,
+        );
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![subdir.clone()]));
+
+        let file = subdir.join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        wait_for(
+            &mut tracker,
+            &maplit::hashset![subdir.clone(), file.clone()],
+            State::Dirty,
+        );
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![subdir.clone(), file.clone()])
+        );
+        assert_eq!(tracker.state(), State::Dirty);
+    }
+
+    #[test]
+    fn test_root_removed() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+
+        let mut tracker = DirtyTracker::new(&root).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        std::fs::remove_dir(&root).unwrap();
+
+        assert_eq!(
+            tracker.state_detail(),
+            (State::Unknown, Some(UnknownReason::RootRemoved))
+        );
+        assert_eq!(tracker.state(), State::Unknown);
+        assert_eq!(tracker.paths(), None);
+        assert_eq!(tracker.try_paths(), Err(UnknownReason::RootRemoved));
+
+        // The same applies if the removal is only noticed through events.
+        std::fs::create_dir(&root).unwrap();
+        let (mut tracker, sender) =
+            build_injected(DirtyTracker::builder(&root).flush_strategy(NoFlush));
+        inject(&sender, REMOVE, &root);
+        assert_eq!(
+            tracker.state_detail(),
+            (State::Unknown, Some(UnknownReason::RootRemoved))
+        );
+    }
+
+    #[test]
+    fn test_allow_missing_root() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        assert!(DirtyTracker::new(&root).is_err());
+
+        let mut tracker = DirtyTracker::builder(&root)
+            .allow_missing_root(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        std::fs::write(dir.path().join("sibling"), b"sibling").unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/file"), b"file").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![root.join("sub"), root.join("sub/file")])
+        );
+
+        std::fs::write(root.join("other"), b"other").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![
+                root.join("sub"),
+                root.join("sub/file"),
+                root.join("other")
+            ])
+        );
+    }
+
+    #[test]
+    fn test_rearm_root() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a"), b"a").unwrap();
+
+        let mut tracker = DirtyTracker::builder(&root)
+            .rearm_root(true)
+            .build()
+            .unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(tracker.state(), State::Dirty);
+
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("b"), b"b").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![
+                root.clone(),
+                root.join("a"),
+                root.join("b")
+            ])
+        );
+
+        // Changes in the new tree are picked up.
+        tracker.mark_clean();
+        std::fs::write(root.join("c"), b"c").unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![root.join("c")]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rearm_root_replaced() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let old = dir.path().join("old");
+        let new = dir.path().join("new");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::create_dir(&new).unwrap();
+        std::fs::write(new.join("a"), b"a").unwrap();
+
+        let mut tracker = DirtyTracker::builder(&root)
+            .rearm_root(true)
+            .build()
+            .unwrap();
+        std::fs::rename(&root, &old).unwrap();
+        std::fs::rename(&new, &root).unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![root.clone(), root.join("a")])
+        );
+
+        // The old tree is no longer watched.
+        tracker.mark_clean();
+        std::fs::write(old.join("b"), b"b").unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_root_moved() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+
+        let mut tracker = DirtyTracker::new(&root).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        std::fs::rename(&root, dir.path().join("moved")).unwrap();
+        assert_eq!(
+            tracker.state_detail(),
+            (State::Unknown, Some(UnknownReason::RootMoved))
+        );
+        assert_eq!(tracker.path(), root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_root() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let moved = dir.path().join("moved");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a"), b"a").unwrap();
+
+        let mut tracker = DirtyTracker::builder(&root)
+            .tree_snapshot(true)
+            .follow_root(true)
+            .build()
+            .unwrap();
+        std::fs::write(root.join("a"), b"changed").unwrap();
+        std::fs::rename(&root, &moved).unwrap();
+        assert_eq!(tracker.state(), State::Dirty);
+        assert_eq!(tracker.path(), moved);
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![moved.join("a")]));
+
+        std::fs::write(moved.join("b"), b"b").unwrap();
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![moved.join("a"), moved.join("b")])
+        );
+
+        // The baseline moved along with the tree.
+        std::fs::remove_file(moved.join("b")).unwrap();
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![moved.join("a")]));
+    }
+
+    #[test]
+    fn test_rescan() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        // Pretend the watcher dropped some events.
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.state(), State::Unknown);
+
+        std::fs::write(&file, b"hello world").unwrap();
+        let file2 = dir.path().join("file2");
+        std::fs::write(&file2, b"world").unwrap();
+
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.state(), State::Dirty);
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![file.clone(), file2.clone()])
+        );
+
+        // Newly created files that are removed again are no longer dirty.
+        std::fs::remove_file(&file2).unwrap();
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+
+        tracker.mark_clean();
+        tracker.unknown = Some(UnknownReason::Overflow);
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_rescan_custom_baseline() {
+        struct Manifest(std::collections::HashMap<PathBuf, Vec<u8>>);
+
+        impl Baseline for Manifest {
+            fn dirty_paths(&self, root: &Path) -> std::io::Result<HashSet<PathBuf>> {
+                let mut dirty = HashSet::new();
+                for entry in std::fs::read_dir(root)? {
+                    let path = entry?.path();
+                    if self.0.get(&path) != Some(&std::fs::read(&path)?) {
+                        dirty.insert(path);
+                    }
+                }
+                Ok(dirty)
+            }
+
+            fn contains(&self, path: &Path) -> bool {
+                self.0.contains_key(path)
+            }
+        }
+
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let manifest = Manifest(maplit::hashmap![file.clone() => b"world".to_vec()]);
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .baseline(manifest)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        tracker.unknown = Some(UnknownReason::Overflow);
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        assert!(tracker.created.is_empty());
+    }
+
+    #[test]
+    fn test_rescan_without_baseline() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .without_baseline()
+            .build()
+            .unwrap();
+        assert!(matches!(
+            tracker.rescan(),
+            Err(DirtyTrackerError::NoBaseline)
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        let file2 = dir.path().join("file2");
+        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(&file2, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .hash_contents(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(&file2, b"world").unwrap();
+        wait_for(
+            &mut tracker,
+            &maplit::hashset![file.clone(), file2.clone()],
+            State::Dirty,
+        );
+
+        tracker.verify().unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file2.clone()]));
+
+        tracker.mark_clean();
+        std::fs::write(&file2, b"world").unwrap();
+        tracker.verify().unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert!(matches!(
+            tracker.verify(),
+            Err(DirtyTrackerError::Unknown(UnknownReason::Overflow))
+        ));
+    }
+
+    #[test]
+    fn test_revert() {
+        let dir = tempdir().unwrap();
+
+        let file = dir.path().join("file");
+        let removed = dir.path().join("removed");
+        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(&removed, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .snapshot_contents(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+
+        let added = dir.path().join("added");
+        std::fs::write(&file, b"world").unwrap();
+        std::fs::remove_file(&removed).unwrap();
+        std::fs::write(&added, b"new").unwrap();
+        wait_for(
+            &mut tracker,
+            &maplit::hashset![file.clone(), removed.clone(), added.clone()],
+            State::Dirty,
+        );
+
+        tracker.revert_path(&file).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"hello");
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![removed.clone(), added.clone()])
+        );
+
+        tracker.revert().unwrap();
+        assert_eq!(std::fs::read(&removed).unwrap(), b"hello");
+        assert!(!added.exists());
+        assert_eq!(tracker.state(), State::Clean);
+
+        // Restored files match the rescan baseline too.
+        tracker.unknown = Some(UnknownReason::Overflow);
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+    }
+
+    #[test]
+    fn test_revert_created_dir() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .snapshot_contents(true)
+            .directories(DirectoryPolicy::Separate)
+            .build()
+            .unwrap();
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(subdir.join("new"), b"new").unwrap();
+        std::fs::write(&file, b"world").unwrap();
+        tracker.sync().unwrap();
+        assert!(tracker.dirs.contains(&subdir));
+
+        tracker.revert().unwrap();
+        assert!(!subdir.exists());
+        assert_eq!(std::fs::read(&file).unwrap(), b"hello");
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(tracker.dirs.is_empty());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs::File;
-    use std::io::Write;
-    use tempfile::tempdir;
+    #[test]
+    fn test_revert_after_drain_paths() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
 
-    fn wait_for(
-        tracker: &mut DirtyTracker,
-        expected_paths: &HashSet<PathBuf>,
-        expected_state: State,
-    ) {
-        let state = tracker.state();
-        let paths = tracker.paths().unwrap().clone();
-        if state == State::Unknown {
-            panic!("Unexpected unknown state");
-        }
-        assert_eq!(state, expected_state);
-        assert_eq!(paths, *expected_paths);
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .snapshot_contents(true)
+            .build()
+            .unwrap();
+        std::fs::write(&file, b"world").unwrap();
+        assert_eq!(
+            tracker.drain_paths().unwrap().collect::<Vec<_>>(),
+            vec![file.clone()]
+        );
+
+        std::fs::write(&file, b"again").unwrap();
+        tracker.revert().unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"world");
     }
 
     #[test]
-    fn test_no_changes() {
+    fn test_revert_after_mark_clean() {
         let dir = tempdir().unwrap();
-        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        let subdir = dir.path().join("subdir");
+        let file = subdir.join("file");
+        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(&file, b"hello").unwrap();
 
-        wait_for(&mut tracker, &maplit::hashset![], State::Clean);
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .snapshot_contents(true)
+            .build()
+            .unwrap();
+        std::fs::write(&file, b"world").unwrap();
+        let added = dir.path().join("added");
+        std::fs::write(&added, b"new").unwrap();
+        tracker.mark_clean();
 
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![]));
-        assert_eq!(tracker.state(), State::Clean);
+        // Only the dirty paths were copied again.
+        std::fs::write(&file, b"again").unwrap();
+        std::fs::remove_file(&added).unwrap();
+        tracker.revert().unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"world");
+        assert_eq!(std::fs::read(&added).unwrap(), b"new");
+
+        // Not all changes would be undone if the state is unknown.
+        std::fs::write(&file, b"again").unwrap();
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert!(matches!(
+            tracker.revert(),
+            Err(DirtyTrackerError::Unknown(UnknownReason::Overflow))
+        ));
+        assert_eq!(std::fs::read(&file).unwrap(), b"again");
     }
 
     #[test]
-    fn test_simple_create() {
+    fn test_diff() {
         let dir = tempdir().unwrap();
-        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
 
         let file = dir.path().join("file");
-        let mut f = File::create(&file).unwrap();
-        f.write_all(b"hello").unwrap();
-        f.sync_all().unwrap();
-        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        std::fs::write(&file, b"hello\n").unwrap();
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .snapshot_contents(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.diff().unwrap(), "");
+
+        std::fs::write(&file, b"world\n").unwrap();
+        std::fs::write(dir.path().join("binary"), b"\0").unwrap();
         assert_eq!(
-            tracker.relpaths(),
-            Some(maplit::hashset![Path::new("file")])
+            tracker.diff().unwrap(),
+            "Binary files /dev/null and b/binary differ\n\
+             --- a/file\n+++ b/file\n@@ -1 +1 @@\n-hello\n+world\n"
+        );
+        assert_eq!(
+            tracker.diff_path(&file).unwrap(),
+            "--- a/file\n+++ b/file\n@@ -1 +1 @@\n-hello\n+world\n"
         );
-        assert_eq!(tracker.state(), State::Dirty);
     }
 
     #[test]
-    fn test_simple_modify() {
+    fn test_wait_for_quiescence() {
         let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
 
         let file = dir.path().join("file");
-        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(&file, b"start").unwrap();
+        let writer = {
+            let file = file.clone();
+            std::thread::spawn(move || {
+                for i in 0..5 {
+                    std::fs::write(&file, format!("{}", i)).unwrap();
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+            })
+        };
+
+        let paths = tracker
+            .wait_for_quiescence(
+                std::time::Duration::from_millis(200),
+                std::time::Duration::from_secs(10),
+            )
+            .unwrap();
+        assert_eq!(paths, &maplit::hashset![file.clone()]);
+        writer.join().unwrap();
+    }
 
+    #[test]
+    fn test_wait_for_quiescence_timeout() {
+        let dir = tempdir().unwrap();
         let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        assert!(matches!(
+            tracker.wait_for_quiescence(
+                std::time::Duration::from_secs(10),
+                std::time::Duration::from_millis(10),
+            ),
+            Err(DirtyTrackerError::Timeout(_))
+        ));
+    }
 
-        std::fs::write(&file, b"world").unwrap();
+    #[test]
+    fn test_wait_until() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
 
-        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
-        assert_eq!(
-            tracker.relpaths(),
-            Some(maplit::hashset![Path::new("file")])
-        );
-        assert_eq!(tracker.state(), State::Dirty);
+        let writer = {
+            let dir = dir.path().to_path_buf();
+            std::thread::spawn(move || {
+                for i in 0..3 {
+                    std::fs::write(dir.join(format!("file{}", i)), b"hello").unwrap();
+                }
+            })
+        };
+
+        let paths = tracker
+            .wait_until(|paths| paths.len() >= 3, std::time::Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(paths.len(), 3);
+        writer.join().unwrap();
+
+        assert!(matches!(
+            tracker.wait_until(
+                |paths| paths.len() > 3,
+                std::time::Duration::from_millis(10)
+            ),
+            Err(DirtyTrackerError::Timeout(_))
+        ));
     }
 
     #[test]
-    fn test_delete() {
+    fn test_wait_for_path() {
         let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
 
         let file = dir.path().join("file");
-        std::fs::write(&file, b"hello").unwrap();
+        let writer = {
+            let file = file.clone();
+            std::thread::spawn(move || std::fs::write(file, b"hello").unwrap())
+        };
+
+        tracker
+            .wait_for_path(&file, std::time::Duration::from_secs(10))
+            .unwrap();
+        writer.join().unwrap();
+
+        assert!(matches!(
+            tracker.wait_for_path(
+                &dir.path().join("other"),
+                std::time::Duration::from_millis(10)
+            ),
+            Err(DirtyTrackerError::Timeout(_))
+        ));
+    }
 
+    #[test]
+    fn test_subscribe() {
+        let dir = tempdir().unwrap();
         let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        let rx = tracker.subscribe();
 
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
         std::fs::remove_file(&file).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
 
-        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        let notifications: Vec<_> = rx.try_iter().collect();
         assert_eq!(
-            tracker.relpaths(),
-            Some(maplit::hashset![Path::new("file")])
+            notifications.first(),
+            Some(&DirtyNotification {
+                path: file.clone(),
+                kind: ChangeKind::Created,
+                sequence: 1,
+            })
+        );
+        assert_eq!(
+            notifications.last(),
+            Some(&DirtyNotification {
+                path: file.clone(),
+                kind: ChangeKind::Removed,
+                sequence: tracker.sequence(),
+            })
         );
+        assert!(notifications.iter().all(|n| n.path == file));
+
+        std::mem::drop(rx);
+        std::fs::write(&file, b"hello").unwrap();
         assert_eq!(tracker.state(), State::Dirty);
+        assert!(tracker.subscribers.is_empty());
     }
 
     #[test]
-    fn test_rename() {
+    fn test_on_change() {
         let dir = tempdir().unwrap();
-
         let file = dir.path().join("file");
         std::fs::write(&file, b"hello").unwrap();
 
         let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
-
-        let new_file = dir.path().join("new_file");
-        std::fs::rename(&file, &new_file).unwrap();
-
-        wait_for(
-            &mut tracker,
-            &maplit::hashset![new_file.clone(), file.clone()],
-            State::Dirty,
-        );
+        let changes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        tracker.on_change({
+            let changes = changes.clone();
+            move |path, kind| changes.lock().unwrap().push((path.to_path_buf(), kind))
+        });
 
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(tracker.state(), State::Dirty);
         assert_eq!(
-            tracker.paths(),
-            Some(&maplit::hashset![file.clone(), new_file.clone()])
+            changes.lock().unwrap().last(),
+            Some(&(file.clone(), ChangeKind::Removed))
         );
-        assert_eq!(tracker.state(), State::Dirty);
     }
 
     #[test]
-    fn test_mark_clean() {
+    fn test_on_change_panic() {
         let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        let panicked = std::sync::atomic::AtomicBool::new(false);
+        tracker.on_change(move |_, _| {
+            if !panicked.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                panic!("callback failed");
+            }
+        });
 
-        let file = dir.path().join("file");
-        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tracker.state()));
+        assert!(result.is_err());
+        // The flush strategy survives the panic.
+        std::fs::write(dir.path().join("b"), b"b").unwrap();
+        assert_eq!(tracker.state(), State::Dirty);
+        assert!(tracker.paths().unwrap().contains(&dir.path().join("b")));
+    }
 
-        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+    #[test]
+    fn test_dirty_threshold() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .dirty_threshold(10)
+            .build()
+            .unwrap();
+        let calls = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        tracker.on_threshold_exceeded({
+            let calls = calls.clone();
+            move |len| calls.lock().unwrap().push(len)
+        });
 
-        std::fs::write(&file, b"world").unwrap();
+        for i in 0..9 {
+            std::fs::write(dir.path().join(format!("file{}", i)), b"hello").unwrap();
+        }
+        assert_eq!(tracker.state(), State::Dirty);
+        assert!(!tracker.threshold_exceeded());
 
-        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+        for i in 9..20 {
+            std::fs::write(dir.path().join(format!("file{}", i)), b"hello").unwrap();
+        }
         assert_eq!(tracker.state(), State::Dirty);
+        assert!(tracker.threshold_exceeded());
+        assert_eq!(*calls.lock().unwrap(), vec![10]);
 
         tracker.mark_clean();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        assert!(!tracker.threshold_exceeded());
     }
 
+    #[cfg(feature = "tokio")]
     #[test]
-    fn test_add_and_remove() {
+    fn test_state_watch() {
         let dir = tempdir().unwrap();
-
-        let file = dir.path().join("file");
-        std::fs::write(file, b"hello").unwrap();
-
         let mut tracker = DirtyTracker::new(dir.path()).unwrap();
-        assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        let mut rx = tracker.state_watch();
+        assert_eq!(*rx.borrow_and_update(), State::Clean);
 
-        let file2 = dir.path().join("file2");
-        std::fs::write(&file2, b"world").unwrap();
-
-        wait_for(&mut tracker, &maplit::hashset![file2.clone()], State::Dirty);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![file2.clone()]));
+        std::fs::write(dir.path().join("file"), b"hello").unwrap();
         assert_eq!(tracker.state(), State::Dirty);
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(*rx.borrow_and_update(), State::Dirty);
 
-        std::fs::remove_file(&file2).unwrap();
+        tracker.mark_clean();
+        assert_eq!(*rx.borrow_and_update(), State::Clean);
 
-        wait_for(&mut tracker, &maplit::hashset![], State::Clean);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![]));
-        assert_eq!(tracker.state(), State::Clean);
+        tracker.unknown = Some(UnknownReason::Overflow);
+        assert_eq!(tracker.state(), State::Unknown);
+        assert_eq!(*rx.borrow_and_update(), State::Unknown);
     }
 
     #[test]
-    fn test_follow_subdir() {
+    fn test_settle_window() {
         let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .settle_window(std::time::Duration::from_millis(50))
+            .build()
+            .unwrap();
 
-        let subdir = dir.path().join("subdir");
-        std::fs::create_dir(&subdir).unwrap();
+        // Queries should not modify the tree.
+        let (tx, rx) = channel();
+        let mut watcher = RecommendedWatcher::new(tx, notify::Config::default()).unwrap();
+        watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert_eq!(tracker.state(), State::Clean);
+        assert!(rx.try_recv().is_err());
 
-        let file = subdir.join("file");
+        let file = dir.path().join("file");
         std::fs::write(&file, b"hello").unwrap();
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
+    }
 
-        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+    #[test]
+    fn test_sentinel_dir() {
+        let dir = tempdir().unwrap();
+        let sentinel_dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .sentinel_dir(sentinel_dir.path())
+            .sentinel_prefix(".dirty-tracker-")
+            .build()
+            .unwrap();
+
+        // Queries should not modify the tree.
+        let (tx, rx) = channel();
+        let mut watcher = RecommendedWatcher::new(tx, notify::Config::default()).unwrap();
+        watcher.watch(dir.path(), RecursiveMode::Recursive).unwrap();
         assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        assert!(rx.try_recv().is_err());
 
-        std::fs::write(&file, b"world").unwrap();
+        // Other changes to the sentinel directory are ignored.
+        std::fs::write(sentinel_dir.path().join("other"), b"hello").unwrap();
+        assert_eq!(tracker.state(), State::Clean);
 
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
         wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
-        assert_eq!(tracker.state(), State::Dirty);
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_create_and_follow_subdir() {
+    fn test_read_only() {
+        use std::os::unix::fs::PermissionsExt;
         let dir = tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        if tempfile::tempfile_in(dir.path()).is_ok() {
+            // Root can still write to read-only directories, so there is
+            // nothing to test.
+            return;
+        }
 
         let mut tracker = DirtyTracker::new(dir.path()).unwrap();
         assert_eq!(tracker.state(), State::Clean);
-        assert!(tracker.paths().unwrap().is_empty());
+        assert_eq!(tracker.ignored_dirs.len(), 1);
 
-        let subdir = dir.path().join("subdir");
-        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(&file, b"world").unwrap();
+        wait_for(&mut tracker, &maplit::hashset![file.clone()], State::Dirty);
 
-        wait_for(
-            &mut tracker,
-            &maplit::hashset![subdir.clone()],
-            State::Dirty,
-        );
-        assert_eq!(tracker.paths(), Some(&maplit::hashset![subdir.clone()]));
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
 
-        let file = subdir.join("file");
-        std::fs::write(&file, b"hello").unwrap();
+    #[test]
+    fn test_sync() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::new(dir.path()).unwrap();
+        tracker.sync().unwrap();
 
-        wait_for(
-            &mut tracker,
-            &maplit::hashset![subdir.clone(), file.clone()],
-            State::Dirty,
+        // Create nested directories quickly, before the watcher has a
+        // chance to watch them.
+        for i in 0..10 {
+            let subdir = dir.path().join(format!("subdir{}", i));
+            let nested = subdir.join("nested");
+            std::fs::create_dir_all(&nested).unwrap();
+            std::fs::write(nested.join("file"), b"hello").unwrap();
+            tracker.sync().unwrap();
+            assert!(tracker.paths.contains(&nested.join("file")));
+        }
+    }
+
+    #[test]
+    fn test_watch_limit() {
+        let reason =
+            UnknownReason::from_notify(&notify::Error::new(notify::ErrorKind::MaxFilesWatch));
+        let limit = match reason {
+            UnknownReason::TooManyFiles { limit } => limit,
+            other => panic!("unexpected reason: {:?}", other),
+        };
+        #[cfg(target_os = "linux")]
+        if std::path::Path::new("/proc/sys/fs/inotify/max_user_watches").exists() {
+            assert!(limit.is_some());
+            assert!(reason.to_string().contains("fs.inotify.max_user_watches"));
+        }
+        assert_eq!(
+            UnknownReason::from_error(&DirtyTrackerError::WatchLimit { limit }),
+            reason
         );
+    }
+
+    #[test]
+    fn test_estimated_watch_count() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+        let builder = DirtyTracker::builder(dir.path());
+        let estimate = builder.estimated_watch_count().unwrap();
+        let tracker = builder.build().unwrap();
+        assert_eq!(tracker.estimated_watch_count().unwrap(), estimate);
+        #[cfg(target_os = "linux")]
+        assert_eq!(estimate, 2);
+    }
+
+    #[test]
+    fn test_polling_policy() {
+        use notify::WatcherKind;
+        assert!(PollingPolicy::Allow.check(WatcherKind::PollWatcher).is_ok());
+        assert!(PollingPolicy::Refuse.check(WatcherKind::Inotify).is_ok());
+        assert!(matches!(
+            PollingPolicy::Refuse.check(WatcherKind::PollWatcher),
+            Err(DirtyTrackerError::LowPerformanceWatcher(
+                WatcherKind::PollWatcher
+            ))
+        ));
+
+        let dir = tempdir().unwrap();
+        let tracker = DirtyTracker::builder(dir.path())
+            .polling_policy(PollingPolicy::Refuse)
+            .build();
+        #[cfg(target_os = "linux")]
+        assert!(!tracker.unwrap().is_polling());
+        #[cfg(not(target_os = "linux"))]
+        let _ = tracker;
+    }
+
+    #[test]
+    fn test_disconnected() {
+        struct Gone;
+
+        impl WatcherBackend for Gone {
+            fn add_watch(&mut self, _path: &Path, _mode: RecursiveMode) -> notify::Result<()> {
+                Ok(())
+            }
+
+            fn remove_watch(&mut self, _path: &Path) -> notify::Result<()> {
+                Ok(())
+            }
+
+            fn backend_kind(&self) -> notify::WatcherKind {
+                notify::WatcherKind::NullWatcher
+            }
+
+            fn default_flush(&self) -> Box<dyn FlushStrategy> {
+                struct Unreachable;
+
+                impl FlushStrategy for Unreachable {
+                    fn flush(
+                        &mut self,
+                        _events: &mut PendingEvents<'_>,
+                        _timeout: Option<std::time::Duration>,
+                    ) -> Result<(), DirtyTrackerError> {
+                        panic!("flush attempted on a disconnected tracker")
+                    }
+                }
+
+                Box::new(Unreachable)
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .build_with_backend(|tx| {
+                tx.send(Ok(Event::new(EventKind::Create(
+                    notify::event::CreateKind::File,
+                ))
+                .add_path(dir.path().join("file"))))
+                    .unwrap();
+                Ok(Gone)
+            })
+            .unwrap();
         assert_eq!(
-            tracker.paths(),
-            Some(&maplit::hashset![subdir.clone(), file.clone()])
+            tracker.state_detail(),
+            (State::Unknown, Some(UnknownReason::Disconnected))
         );
-        assert_eq!(tracker.state(), State::Dirty);
+        // Events delivered before the disconnection are still processed.
+        assert!(tracker.paths.contains(&dir.path().join("file")));
+        assert_eq!(tracker.unknown, Some(UnknownReason::Disconnected));
+    }
+
+    #[test]
+    fn test_stats() {
+        let dir = tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .tree_snapshot(true)
+            .build()
+            .unwrap();
+        assert_eq!(tracker.stats(), Stats::default());
+
+        let file = dir.path().join("file");
+        let other = dir.path().join("other");
+        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(&other, b"hello").unwrap();
+        std::fs::remove_file(&other).unwrap();
+        assert_eq!(tracker.paths(), Some(&maplit::hashset![file.clone()]));
+
+        let stats = tracker.stats();
+        assert!(stats.events >= 3);
+        assert!(stats.created >= 2);
+        assert_eq!(stats.removed, 1);
+        assert_eq!(stats.dirty, 1);
+        assert_eq!(stats.peak_dirty, 2);
+        assert!(stats.last_flush.is_some());
+        assert!(stats.max_flush >= stats.last_flush);
+
+        tracker.rescan().unwrap();
+        assert_eq!(tracker.stats().rescans, 1);
     }
 
     #[test]
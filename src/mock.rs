@@ -0,0 +1,193 @@
+//! A fake watcher backend, for testing code that uses a `DirtyTracker`.
+//!
+//! This is only available with the `test-util` feature.
+//!
+//! # Example
+//! ```rust
+//! use dirty_tracker::{DirtyTracker, State};
+//! use std::path::Path;
+//!
+//! let (mut tracker, mock) = DirtyTracker::builder(Path::new("/nonexistent"))
+//!     .build_mock()
+//!     .unwrap();
+//! assert_eq!(tracker.state(), State::Clean);
+//!
+//! mock.emit_create("file");
+//! assert_eq!(tracker.state(), State::Dirty);
+//! assert_eq!(
+//!     tracker.paths(),
+//!     Some(&maplit::hashset![Path::new("/nonexistent/file").to_path_buf()])
+//! );
+//! ```
+
+use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventHandler, EventKind, RecursiveMode, WatcherKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+struct Inner {
+    handler: Box<dyn EventHandler>,
+    watched: Vec<PathBuf>,
+}
+
+/// A watcher that doesn't look at the file system, but delivers whatever
+/// events are injected through its `MockEvents` handle.
+pub struct MockWatcher {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MockWatcher {
+    /// Returns a handle that can be used to inject events.
+    pub fn events(&self) -> MockEvents {
+        MockEvents {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl notify::Watcher for MockWatcher {
+    fn new<F: EventHandler>(event_handler: F, _config: notify::Config) -> notify::Result<Self> {
+        Ok(MockWatcher {
+            inner: Arc::new(Mutex::new(Inner {
+                handler: Box::new(event_handler),
+                watched: Vec::new(),
+            })),
+        })
+    }
+
+    fn watch(&mut self, path: &Path, _recursive_mode: RecursiveMode) -> notify::Result<()> {
+        self.inner.lock().unwrap().watched.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) -> notify::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.watched.iter().position(|p| p == path) {
+            Some(i) => {
+                inner.watched.remove(i);
+                Ok(())
+            }
+            None => Err(notify::Error::watch_not_found()),
+        }
+    }
+
+    fn kind() -> WatcherKind {
+        WatcherKind::NullWatcher
+    }
+}
+
+/// A handle for injecting events into a `MockWatcher`.
+///
+/// Events are delivered synchronously, so they are reflected by the next
+/// query on the tracker. Relative paths are interpreted relative to the
+/// first watched path, i.e. the root of the tracked tree.
+#[derive(Clone)]
+pub struct MockEvents {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MockEvents {
+    fn resolve(inner: &Inner, path: &Path) -> PathBuf {
+        match inner.watched.first() {
+            Some(root) if path.is_relative() => root.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    /// Deliver an arbitrary event.
+    pub fn emit(&self, event: Event) {
+        let mut inner = self.inner.lock().unwrap();
+        let paths = event
+            .paths
+            .iter()
+            .map(|p| Self::resolve(&inner, p))
+            .collect();
+        inner.handler.handle_event(Ok(Event { paths, ..event }));
+    }
+
+    /// Deliver a watcher error.
+    pub fn emit_error(&self, error: notify::Error) {
+        self.inner.lock().unwrap().handler.handle_event(Err(error));
+    }
+
+    /// Report that the file at `path` was created.
+    pub fn emit_create(&self, path: impl AsRef<Path>) {
+        self.emit(
+            Event::new(EventKind::Create(CreateKind::File)).add_path(path.as_ref().to_path_buf()),
+        );
+    }
+
+    /// Report that the directory at `path` was created.
+    pub fn emit_create_dir(&self, path: impl AsRef<Path>) {
+        self.emit(
+            Event::new(EventKind::Create(CreateKind::Folder)).add_path(path.as_ref().to_path_buf()),
+        );
+    }
+
+    /// Report that the contents of `path` were modified.
+    pub fn emit_modify(&self, path: impl AsRef<Path>) {
+        self.emit(
+            Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Any)))
+                .add_path(path.as_ref().to_path_buf()),
+        );
+    }
+
+    /// Report that the file at `path` was removed.
+    pub fn emit_remove(&self, path: impl AsRef<Path>) {
+        self.emit(
+            Event::new(EventKind::Remove(RemoveKind::File)).add_path(path.as_ref().to_path_buf()),
+        );
+    }
+
+    /// Report that `from` was renamed to `to`.
+    pub fn emit_rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) {
+        self.emit(
+            Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+                .add_path(from.as_ref().to_path_buf())
+                .add_path(to.as_ref().to_path_buf()),
+        );
+    }
+
+    /// Report that the watcher dropped events, and a rescan is needed.
+    pub fn emit_overflow(&self) {
+        self.emit(Event::new(EventKind::Other).set_flag(notify::event::Flag::Rescan));
+    }
+
+    /// Returns the paths that are being watched.
+    pub fn watched(&self) -> Vec<PathBuf> {
+        self.inner.lock().unwrap().watched.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DirtyTracker, State, UnknownReason};
+    use std::path::Path;
+
+    #[test]
+    fn test_mock() {
+        let root = Path::new("/nonexistent");
+        let (mut tracker, mock) = DirtyTracker::builder(root).build_mock().unwrap();
+        assert_eq!(mock.watched(), vec![root.to_path_buf()]);
+        assert_eq!(tracker.state(), State::Clean);
+
+        mock.emit_create("new");
+        mock.emit_modify(root.join("existing"));
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![root.join("new"), root.join("existing")])
+        );
+
+        mock.emit_remove("new");
+        assert_eq!(
+            tracker.paths(),
+            Some(&maplit::hashset![root.join("existing")])
+        );
+
+        mock.emit_overflow();
+        assert_eq!(
+            tracker.state_detail(),
+            (State::Unknown, Some(UnknownReason::Overflow))
+        );
+    }
+}
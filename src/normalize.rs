@@ -0,0 +1,278 @@
+//! Normalization of the paths reported by watchers.
+//!
+//! Watchers don't necessarily report paths in the form they were watched
+//! under; e.g. on macOS, events for a tree below `/tmp` arrive under
+//! `/private/tmp`, and on Windows they may carry an extended-length `\\?\`
+//! prefix. All events pass through an `EventReceiver`, which maps their
+//! paths back onto the form the rest of the tracker uses.
+
+use crate::queue::Receiver;
+use notify::Event;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+/// Rewrites paths reported by the watcher.
+#[derive(Debug, Default)]
+pub(crate) struct Normalizer {
+    /// Pairs of a canonical path, and the path to report instead.
+    aliases: Vec<(PathBuf, PathBuf)>,
+    /// Pairs of the canonical target of a symbolic link, and the link.
+    ///
+    /// Unlike aliases, a target can be reported under several links.
+    links: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Normalizer {
+    /// Create a normalizer for a tree rooted at `root`.
+    ///
+    /// This only undoes differences in notation, such as extended-length
+    /// prefixes on Windows; use `add_root()` to resolve symbolic links.
+    pub(crate) fn new(root: &Path) -> Self {
+        let mut normalizer = Self::default();
+        normalizer.add_alias(simplify(root.to_path_buf()), root);
+        normalizer
+    }
+
+    fn add_alias(&mut self, alias: PathBuf, path: &Path) {
+        if alias != path {
+            self.aliases.push((alias, path.to_path_buf()));
+        }
+    }
+
+    /// Report paths below the canonical form of `path` as being below
+    /// `path`.
+    pub(crate) fn add_root(&mut self, path: &Path) -> std::io::Result<()> {
+        let canonical = simplify(path.canonicalize()?);
+        self.add_alias(canonical, path);
+        Ok(())
+    }
+
+    /// Report paths below the target of the symbolic link `link` as being
+    /// below `link`, if the target is outside the tree rooted at `root`.
+    ///
+    /// Returns the canonical path of the target if it was added.
+    pub(crate) fn add_link(
+        &mut self,
+        root: &Path,
+        link: &Path,
+    ) -> std::io::Result<Option<PathBuf>> {
+        let root = simplify(root.canonicalize()?);
+        let target = simplify(link.canonicalize()?);
+        if target.starts_with(&root) {
+            return Ok(None);
+        }
+        self.links.push((target.clone(), link.to_path_buf()));
+        Ok(Some(target))
+    }
+
+    /// Report paths below `from` as being below `to` instead, e.g. because
+    /// the tree was moved there.
+    pub(crate) fn relocate(&mut self, from: &Path, to: &Path) {
+        for (_, root) in self.aliases.iter_mut().chain(self.links.iter_mut()) {
+            *root = crate::root::rebase(root, from, to);
+        }
+        self.add_alias(from.to_path_buf(), to);
+    }
+
+    pub(crate) fn normalize(&self, path: PathBuf) -> PathBuf {
+        let path = simplify(path);
+        for (canonical, root) in self.aliases.iter() {
+            if let Ok(relpath) = path.strip_prefix(canonical) {
+                if relpath.as_os_str().is_empty() {
+                    return root.clone();
+                }
+                return root.join(relpath);
+            }
+        }
+        path
+    }
+
+    /// Returns the paths of the links `path` can be reached through, or
+    /// just `path` if it is not below the target of any.
+    fn expand_links(&self, path: PathBuf) -> Vec<PathBuf> {
+        let linked = self
+            .links
+            .iter()
+            .filter(|(target, _)| path.starts_with(target))
+            .map(|(target, link)| crate::root::rebase(&path, target, link))
+            .collect::<Vec<_>>();
+        if linked.is_empty() {
+            vec![path]
+        } else {
+            linked
+        }
+    }
+
+    fn normalize_event(&self, mut event: Event) -> Event {
+        event.paths = event
+            .paths
+            .into_iter()
+            .flat_map(|path| self.expand_links(self.normalize(path)))
+            .collect();
+        event
+    }
+}
+
+/// Returns `path` in the notation used by the rest of the tracker.
+///
+/// This is a no-op except on Windows.
+fn simplify(path: PathBuf) -> PathBuf {
+    if !cfg!(windows) {
+        return path;
+    }
+    match path.to_str().map(simplify_windows) {
+        Some(Cow::Owned(simplified)) => simplified.into(),
+        _ => path,
+    }
+}
+
+/// Returns a Windows path without its extended-length prefix, and with
+/// forward slashes replaced by backslashes.
+fn simplify_windows(path: &str) -> Cow<'_, str> {
+    let path = if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        Cow::Owned(format!(r"\\{}", rest))
+    } else {
+        match path.strip_prefix(r"\\?\") {
+            // Only drive paths have a regular equivalent.
+            Some(rest)
+                if rest.len() >= 2
+                    && rest.as_bytes()[0].is_ascii_alphabetic()
+                    && rest.as_bytes()[1] == b':' =>
+            {
+                Cow::Borrowed(rest)
+            }
+            _ => Cow::Borrowed(path),
+        }
+    };
+    // Remaining verbatim paths are left alone, as forward slashes are part
+    // of their file names.
+    if path.starts_with(r"\\?\") || !path.contains('/') {
+        return path;
+    }
+    Cow::Owned(path.replace('/', "\\"))
+}
+
+/// A Unicode normalization form for paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeForm {
+    /// Canonical composition, as used by most applications.
+    Nfc,
+    /// Canonical decomposition, as reported by macOS.
+    Nfd,
+}
+
+impl UnicodeForm {
+    /// Returns `path` in this normalization form.
+    ///
+    /// Paths that are not valid Unicode are returned unchanged.
+    pub(crate) fn normalize<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+        let s = match path.to_str() {
+            Some(s) => s,
+            None => return Cow::Borrowed(path),
+        };
+        match self {
+            UnicodeForm::Nfc if !is_nfc(s) => Cow::Owned(s.nfc().collect::<String>().into()),
+            UnicodeForm::Nfd if !is_nfd(s) => Cow::Owned(s.nfd().collect::<String>().into()),
+            _ => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// Returns the key used to compare `path` case-insensitively.
+pub(crate) fn fold_case(path: &Path) -> PathBuf {
+    path.to_string_lossy().to_lowercase().into()
+}
+
+/// The receiving end of a watcher's event channel, which normalizes the
+/// paths of events as they are received.
+pub(crate) struct EventReceiver {
+    rx: Receiver,
+    pub(crate) normalizer: Normalizer,
+}
+
+impl EventReceiver {
+    pub(crate) fn new(rx: Receiver, normalizer: Normalizer) -> Self {
+        EventReceiver { rx, normalizer }
+    }
+
+    fn map(&self, result: notify::Result<Event>) -> notify::Result<Event> {
+        result.map(|event| self.normalizer.normalize_event(event))
+    }
+
+    pub(crate) fn recv(&self) -> Result<notify::Result<Event>, RecvError> {
+        self.rx.recv().map(|result| self.map(result))
+    }
+
+    pub(crate) fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<notify::Result<Event>, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout).map(|result| self.map(result))
+    }
+
+    pub(crate) fn pending(&self) -> usize {
+        self.rx.pending()
+    }
+
+    pub(crate) fn exempt(&self, dir: &Path) {
+        self.rx.exempt(dir)
+    }
+
+    pub(crate) fn on_push(&self, hook: crate::queue::Hook) -> bool {
+        self.rx.on_push(hook)
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<notify::Result<Event>, TryRecvError> {
+        self.rx.try_recv().map(|result| self.map(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_normalize() {
+        let dir = tempfile::tempdir().unwrap();
+        let link = dir.path().join("link");
+        let target = dir.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let mut normalizer = Normalizer::new(&link);
+        normalizer.add_root(&link).unwrap();
+        let target = target.canonicalize().unwrap();
+        assert_eq!(normalizer.normalize(target.join("a/b")), link.join("a/b"));
+        assert_eq!(normalizer.normalize(target.clone()), link);
+        assert_eq!(
+            normalizer.normalize(PathBuf::from("/elsewhere")),
+            Path::new("/elsewhere")
+        );
+    }
+
+    #[test]
+    fn test_simplify_windows() {
+        assert_eq!(simplify_windows(r"\\?\C:\foo\bar"), r"C:\foo\bar");
+        assert_eq!(
+            simplify_windows(r"\\?\UNC\server\share\foo"),
+            r"\\server\share\foo"
+        );
+        assert_eq!(simplify_windows(r"C:/foo/bar"), r"C:\foo\bar");
+        assert_eq!(simplify_windows(r"C:\foo/bar"), r"C:\foo\bar");
+        assert_eq!(simplify_windows(r"\\?\Volume{x}\a/b"), r"\\?\Volume{x}\a/b");
+        assert!(matches!(simplify_windows(r"C:\foo"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_unicode_form() {
+        let nfc = Path::new("caf\u{e9}");
+        let nfd = Path::new("cafe\u{301}");
+        assert_eq!(UnicodeForm::Nfc.normalize(nfd), nfc);
+        assert_eq!(UnicodeForm::Nfc.normalize(nfc), nfc);
+        assert_eq!(UnicodeForm::Nfd.normalize(nfc), nfd);
+    }
+}
@@ -0,0 +1,126 @@
+//! Sharing one watcher between many trackers.
+
+use crate::backend::{EventSender, WatcherBackend};
+use crate::view::{self, Shared, Views};
+use crate::DirtyTrackerError;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+/// A watcher shared by the trackers created in it, with
+/// `DirtyTrackerBuilder::build_in()`.
+///
+/// Every watcher takes up resources, and some backends limit how many
+/// there can be; e.g. inotify allows 128 instances per user by default.
+/// Trackers in a pool share a single watcher instead, which watches the
+/// trees of all of them; a tree below another tree in the pool doesn't
+/// need watches of its own. The watcher is created along with the first
+/// tracker, with its options, and goes away with the last one.
+///
+/// # Example
+/// ```rust
+/// use dirty_tracker::{DirtyTracker, State, WatcherPool};
+///
+/// let td = tempfile::tempdir().unwrap();
+/// let (a, b) = (td.path().join("a"), td.path().join("b"));
+/// std::fs::create_dir(&a).unwrap();
+/// std::fs::create_dir(&b).unwrap();
+/// let pool = WatcherPool::global();
+/// let mut first = DirtyTracker::builder(&a).build_in(pool).unwrap();
+/// let mut second = DirtyTracker::builder(&b).build_in(pool).unwrap();
+/// std::fs::write(a.join("file"), b"hello").unwrap();
+/// assert_eq!(first.state(), State::Dirty);
+/// assert_eq!(second.state(), State::Clean);
+/// ```
+#[derive(Debug, Default)]
+pub struct WatcherPool {
+    shared: Mutex<Weak<Shared>>,
+}
+
+impl WatcherPool {
+    /// Create an empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the pool for the whole process.
+    pub fn global() -> &'static WatcherPool {
+        static POOL: OnceLock<WatcherPool> = OnceLock::new();
+        POOL.get_or_init(WatcherPool::new)
+    }
+
+    /// Returns the watcher of the pool, creating it with `create` if there
+    /// is none.
+    pub(crate) fn shared<B, F>(&self, create: F) -> Result<Arc<Shared>, DirtyTrackerError>
+    where
+        B: WatcherBackend + 'static,
+        F: FnOnce(EventSender) -> notify::Result<B>,
+    {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(shared) = shared.upgrade() {
+            return Ok(shared);
+        }
+        let views = Views::default();
+        let tx = view::fan_out(views.clone()).map_err(DirtyTrackerError::Io)?;
+        let backend = create(tx).map_err(DirtyTrackerError::Init)?;
+        let created = Shared::new(Box::new(backend), views);
+        *shared = Arc::downgrade(&created);
+        Ok(created)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DirtyTracker, State};
+
+    #[test]
+    fn test_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("a/b");
+        std::fs::create_dir_all(&b).unwrap();
+        let pool = WatcherPool::new();
+        let mut outer = DirtyTracker::builder(&a).build_in(&pool).unwrap();
+        let mut inner = DirtyTracker::builder(&b).build_in(&pool).unwrap();
+        let shared = pool.shared.lock().unwrap().upgrade().unwrap();
+        assert!(Arc::ptr_eq(&shared, outer.shared.as_ref().unwrap()));
+        assert!(Arc::ptr_eq(&shared, inner.shared.as_ref().unwrap()));
+        drop(shared);
+
+        std::fs::write(a.join("file"), b"hello").unwrap();
+        assert_eq!(outer.paths().unwrap(), &maplit::hashset![a.join("file")]);
+        assert_eq!(inner.state(), State::Clean);
+        std::fs::write(b.join("file"), b"hello").unwrap();
+        assert_eq!(inner.paths().unwrap(), &maplit::hashset![b.join("file")]);
+
+        // The inner tree is still watched once the outer tracker is gone.
+        drop(outer);
+        std::fs::write(b.join("other"), b"hello").unwrap();
+        assert_eq!(
+            inner.paths().unwrap(),
+            &maplit::hashset![b.join("file"), b.join("other")]
+        );
+        drop(inner);
+        assert!(pool.shared.lock().unwrap().upgrade().is_none());
+    }
+
+    #[test]
+    fn test_pool_outer_later() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("a/b");
+        std::fs::create_dir_all(&b).unwrap();
+        let pool = WatcherPool::new();
+        let mut inner = DirtyTracker::builder(&b).build_in(&pool).unwrap();
+        let mut outer = DirtyTracker::builder(&a).build_in(&pool).unwrap();
+        std::fs::write(b.join("file"), b"hello").unwrap();
+        assert_eq!(inner.paths().unwrap(), &maplit::hashset![b.join("file")]);
+        assert_eq!(outer.paths().unwrap(), &maplit::hashset![b.join("file")]);
+
+        drop(outer);
+        std::fs::write(b.join("other"), b"hello").unwrap();
+        assert_eq!(
+            inner.paths().unwrap(),
+            &maplit::hashset![b.join("file"), b.join("other")]
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! Delivery of events from the watcher to the tracker.
+//!
+//! Backends deliver their events on an unbounded channel, which grows
+//! without limit if the tracker isn't queried for a long time while the
+//! tree keeps changing. Optionally, a queue sits in between: a thread moves
+//! events from the backend's channel into it as soon as they arrive. The
+//! queue can be bounded, in which case the events that didn't fit are
+//! counted and followed by a request for a rescan, so that the tracker can
+//! tell it needs one (and stops waiting for events that were lost), it can
+//! merge repeats of the event at its tail, which are redundant, and it can
+//! debounce events (see the `debounce` module, with the `debounce`
+//! feature). A hook lets the tracker process the events as soon as they
+//! were queued, see `DirtyTrackerBuilder::eager_drain()`.
+
+use crate::backend::EventSender;
+use notify::Event;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvError, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of events that were dropped because the queue was full.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Dropped(Arc<AtomicU64>);
+
+impl Dropped {
+    /// Returns the number of events dropped since the last call.
+    pub(crate) fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    events: VecDeque<notify::Result<Event>>,
+    disconnected: bool,
+    /// Whether events were dropped since the queue was last below its
+    /// capacity, and a request for a rescan was queued for them.
+    overflowed: bool,
+}
+
+/// Called by the thread after it moved events into the queue.
+pub(crate) type Hook = Box<dyn FnMut() + Send>;
+
+#[derive(Default)]
+pub(crate) struct Queue {
+    inner: Mutex<Inner>,
+    ready: Condvar,
+    /// Directories whose events are never dropped, such as those of
+    /// sentinel files.
+    exempt: Mutex<Vec<PathBuf>>,
+    hook: Mutex<Option<Hook>>,
+}
+
+impl Queue {
+    fn recv_deadline(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<notify::Result<Event>, RecvTimeoutError> {
+        let mut inner = self.inner.lock().unwrap();
+        loop {
+            if let Some(event) = inner.events.pop_front() {
+                return Ok(event);
+            }
+            if inner.disconnected {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            inner = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    self.ready.wait_timeout(inner, deadline - now).unwrap().0
+                }
+                None => self.ready.wait(inner).unwrap(),
+            };
+        }
+    }
+}
+
+/// The receiving end of the events, either straight from the backend or
+/// through a queue.
+pub(crate) enum Receiver {
+    /// The channel, and the events taken off it by `pending()` that have
+    /// yet to be received.
+    Channel(
+        mpsc::Receiver<notify::Result<Event>>,
+        RefCell<VecDeque<notify::Result<Event>>>,
+    ),
+    Queue(Arc<Queue>),
+}
+
+impl From<mpsc::Receiver<notify::Result<Event>>> for Receiver {
+    fn from(rx: mpsc::Receiver<notify::Result<Event>>) -> Self {
+        Receiver::Channel(rx, RefCell::default())
+    }
+}
+
+impl Receiver {
+    pub(crate) fn recv(&self) -> Result<notify::Result<Event>, RecvError> {
+        match self {
+            Receiver::Channel(rx, taken) => match taken.borrow_mut().pop_front() {
+                Some(event) => Ok(event),
+                None => rx.recv(),
+            },
+            Receiver::Queue(queue) => queue.recv_deadline(None).map_err(|_| RecvError),
+        }
+    }
+
+    pub(crate) fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<notify::Result<Event>, RecvTimeoutError> {
+        match self {
+            Receiver::Channel(rx, taken) => match taken.borrow_mut().pop_front() {
+                Some(event) => Ok(event),
+                None => rx.recv_timeout(timeout),
+            },
+            Receiver::Queue(queue) => queue.recv_deadline(Some(Instant::now() + timeout)),
+        }
+    }
+
+    /// Returns the number of events that were delivered but not yet
+    /// received.
+    ///
+    /// With a queue, this leaves out the events its thread has yet to move
+    /// into it, e.g. because of debouncing.
+    pub(crate) fn pending(&self) -> usize {
+        match self {
+            Receiver::Channel(rx, taken) => {
+                // Channels can't tell how many events they hold, so they
+                // have to be taken off.
+                let mut taken = taken.borrow_mut();
+                taken.extend(rx.try_iter());
+                taken.len()
+            }
+            Receiver::Queue(queue) => queue.inner.lock().unwrap().events.len(),
+        }
+    }
+
+    /// Never drop events for paths below `dir` because the queue is full.
+    pub(crate) fn exempt(&self, dir: &Path) {
+        if let Receiver::Queue(queue) = self {
+            let mut exempt = queue.exempt.lock().unwrap();
+            // The watcher may report the directory in its canonical form.
+            if let Ok(canonical) = dir.canonicalize() {
+                exempt.push(canonical);
+            }
+            exempt.push(dir.to_path_buf());
+        }
+    }
+
+    /// Call `hook` on the queue's thread whenever it has moved events into
+    /// the queue.
+    ///
+    /// Returns false if there is no queue.
+    pub(crate) fn on_push(&self, hook: Hook) -> bool {
+        match self {
+            Receiver::Channel(..) => false,
+            Receiver::Queue(queue) => {
+                *queue.hook.lock().unwrap() = Some(hook);
+                true
+            }
+        }
+    }
+
+    pub(crate) fn try_recv(&self) -> Result<notify::Result<Event>, TryRecvError> {
+        match self {
+            Receiver::Channel(rx, taken) => match taken.borrow_mut().pop_front() {
+                Some(event) => Ok(event),
+                None => rx.try_recv(),
+            },
+            Receiver::Queue(queue) => {
+                let mut inner = queue.inner.lock().unwrap();
+                match inner.events.pop_front() {
+                    Some(event) => Ok(event),
+                    None if inner.disconnected => Err(TryRecvError::Disconnected),
+                    None => Err(TryRecvError::Empty),
+                }
+            }
+        }
+    }
+}
+
+/// How events are moved into the queue.
+#[derive(Clone, Default)]
+pub(crate) struct Options {
+    /// The maximum number of events in the queue.
+    pub(crate) capacity: Option<usize>,
+    /// Whether to leave out events that repeat the one at the tail of the
+    /// queue.
+    pub(crate) merge: bool,
+    /// How long to hold back events for debouncing.
+    #[cfg(feature = "debounce")]
+    pub(crate) debounce: Option<Duration>,
+    /// Where to send copies of the events for views of the tracker.
+    pub(crate) views: Option<crate::view::Views>,
+}
+
+impl Options {
+    /// Returns whether the events need to go through a queue at all.
+    pub(crate) fn is_needed(&self) -> bool {
+        #[cfg(feature = "debounce")]
+        if self.debounce.is_some() {
+            return true;
+        }
+        self.capacity.is_some() || self.merge || self.views.is_some()
+    }
+}
+
+/// Returns whether `event` repeats `last`, so that queueing it as well
+/// would make no difference to the tracker.
+fn repeats(last: Option<&notify::Result<Event>>, event: &notify::Result<Event>) -> bool {
+    match (last, event) {
+        (Some(Ok(last)), Ok(event)) => last == event,
+        _ => false,
+    }
+}
+
+impl Queue {
+    /// Returns true if `event` only concerns paths in exempt directories.
+    fn is_exempt(&self, event: &notify::Result<Event>) -> bool {
+        let exempt = self.exempt.lock().unwrap();
+        match event {
+            Ok(event) if !exempt.is_empty() && !event.paths.is_empty() => event
+                .paths
+                .iter()
+                .all(|path| exempt.iter().any(|dir| path.starts_with(dir))),
+            _ => false,
+        }
+    }
+
+    fn push(&self, options: &Options, dropped: &Dropped, event: notify::Result<Event>) {
+        let exempt = self.is_exempt(&event);
+        let mut inner = self.inner.lock().unwrap();
+        if options.merge && repeats(inner.events.back(), &event) {
+            return;
+        }
+        match options.capacity {
+            Some(capacity) if inner.events.len() >= capacity && !exempt => {
+                dropped.0.fetch_add(1, Ordering::Relaxed);
+                if !inner.overflowed {
+                    // Let the tracker know right away, rather than leave it
+                    // waiting for events that will never arrive.
+                    inner.overflowed = true;
+                    inner
+                        .events
+                        .push_back(Ok(Event::new(notify::EventKind::Other)
+                            .set_flag(notify::event::Flag::Rescan)));
+                    self.ready.notify_one();
+                }
+                return;
+            }
+            Some(capacity) if inner.events.len() < capacity => inner.overflowed = false,
+            _ => {}
+        }
+        inner.events.push_back(event);
+        self.ready.notify_one();
+    }
+}
+
+/// Create a queue, and a thread that moves events into it.
+///
+/// Returns the sender to pass to the backend, and the receiving end of the
+/// queue.
+pub(crate) fn spawn(options: Options) -> std::io::Result<(EventSender, Receiver, Dropped)> {
+    let (tx, backend_rx) = mpsc::channel();
+    let queue = Arc::new(Queue::default());
+    let dropped = Dropped::default();
+    let shared = queue.clone();
+    let counter = dropped.clone();
+    std::thread::Builder::new()
+        .name("dirty-tracker-queue".to_string())
+        .spawn(move || {
+            #[cfg(feature = "debounce")]
+            let mut batch = crate::debounce::Batch::default();
+            #[cfg(feature = "debounce")]
+            let release = |batch: &mut crate::debounce::Batch| {
+                for event in batch.take() {
+                    shared.push(&options, &counter, Ok(event));
+                }
+            };
+            loop {
+                #[cfg(feature = "debounce")]
+                let deadline = options.debounce.and_then(|timeout| batch.deadline(timeout));
+                #[cfg(not(feature = "debounce"))]
+                let deadline: Option<Instant> = None;
+                let received = match deadline {
+                    Some(deadline) => {
+                        backend_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    }
+                    None => backend_rx
+                        .recv()
+                        .map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                if let (Some(views), Ok(result)) = (options.views.as_ref(), received.as_ref()) {
+                    // Drop views that have gone away.
+                    views
+                        .lock()
+                        .unwrap()
+                        .retain(|tx| tx.send(crate::view::copy(result)).is_ok());
+                }
+                // The tracker is gone.
+                if Arc::strong_count(&shared) == 1 {
+                    match options.views.as_ref() {
+                        Some(views) if !views.lock().unwrap().is_empty() => continue,
+                        _ => return,
+                    }
+                }
+                match received {
+                    #[cfg(feature = "debounce")]
+                    Ok(Ok(event)) if options.debounce.is_some() => batch.push(event),
+                    Ok(result) => {
+                        // Keep errors in order with the events around them.
+                        #[cfg(feature = "debounce")]
+                        release(&mut batch);
+                        shared.push(&options, &counter, result);
+                    }
+                    #[cfg(feature = "debounce")]
+                    Err(RecvTimeoutError::Timeout) => release(&mut batch),
+                    // Without debouncing there is no deadline to time out.
+                    #[cfg(not(feature = "debounce"))]
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // The backend is gone.
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if let Some(hook) = shared.hook.lock().unwrap().as_mut() {
+                    hook();
+                }
+            }
+            #[cfg(feature = "debounce")]
+            release(&mut batch);
+            shared.inner.lock().unwrap().disconnected = true;
+            shared.ready.notify_one();
+        })?;
+    Ok((tx, Receiver::Queue(queue), dropped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::EventKind;
+
+    /// Returns the queued events, once the thread has seen all of them.
+    fn drain(rx: &Receiver) -> Vec<Event> {
+        if let Receiver::Queue(queue) = rx {
+            while !queue.inner.lock().unwrap().disconnected {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        std::iter::from_fn(|| rx.recv().ok())
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn test_bounded() {
+        let (tx, rx, dropped) = spawn(Options {
+            capacity: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        for _ in 0..5 {
+            tx.send(Ok(Event::default())).unwrap();
+        }
+        drop(tx);
+        let events = drain(&rx);
+        assert_eq!(events.len(), 3);
+        assert!(events[2].need_rescan());
+        assert_eq!(dropped.take(), 3);
+        assert_eq!(dropped.take(), 0);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(1)).unwrap_err(),
+            RecvTimeoutError::Disconnected
+        );
+    }
+
+    #[test]
+    fn test_exempt() {
+        let (tx, rx, dropped) = spawn(Options {
+            capacity: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        rx.exempt(Path::new("/sentinel"));
+        let event = |path: &str| Event::new(EventKind::Any).add_path(path.into());
+        tx.send(Ok(event("/tree/a"))).unwrap();
+        tx.send(Ok(event("/tree/b"))).unwrap();
+        tx.send(Ok(event("/sentinel/c"))).unwrap();
+        drop(tx);
+        let events = drain(&rx);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], event("/tree/a"));
+        assert!(events[1].need_rescan());
+        assert_eq!(events[2], event("/sentinel/c"));
+        assert_eq!(dropped.take(), 1);
+    }
+
+    #[test]
+    fn test_merge() {
+        let (tx, rx, dropped) = spawn(Options {
+            merge: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let modify = || Event::new(EventKind::Any).add_path("a".into());
+        let other = Event::new(EventKind::Any).add_path("b".into());
+        for _ in 0..3 {
+            tx.send(Ok(modify())).unwrap();
+        }
+        tx.send(Ok(other.clone())).unwrap();
+        tx.send(Ok(modify())).unwrap();
+        drop(tx);
+        assert_eq!(drain(&rx), vec![modify(), other, modify()]);
+        assert_eq!(dropped.take(), 0);
+        assert_eq!(rx.try_recv().unwrap_err(), TryRecvError::Disconnected);
+    }
+
+    #[test]
+    fn test_on_push() {
+        let (tx, rx, _) = spawn(Options {
+            merge: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let (hook_tx, hook_rx) = mpsc::channel();
+        assert!(rx.on_push(Box::new(move || hook_tx.send(()).unwrap())));
+        tx.send(Ok(Event::default())).unwrap();
+        hook_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(rx.try_recv().unwrap().unwrap(), Event::default());
+
+        let (_tx, rx) = mpsc::channel();
+        assert!(!Receiver::from(rx).on_push(Box::new(|| {})));
+    }
+
+    #[cfg(feature = "debounce")]
+    #[test]
+    fn test_debounce() {
+        let (tx, rx, _) = spawn(Options {
+            debounce: Some(Duration::from_millis(50)),
+            ..Default::default()
+        })
+        .unwrap();
+        let modify = || Event::new(EventKind::Any).add_path("a".into());
+        for _ in 0..3 {
+            tx.send(Ok(modify())).unwrap();
+        }
+        assert_eq!(
+            rx.try_recv().unwrap_err(),
+            TryRecvError::Empty,
+            "events are held back"
+        );
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap(),
+            modify()
+        );
+        drop(tx);
+        assert!(drain(&rx).is_empty());
+    }
+}
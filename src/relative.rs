@@ -0,0 +1,137 @@
+//! Sets of paths that are stored relative to the root of the tree.
+//!
+//! Most dirty paths share the path of the root as a prefix, so storing them
+//! without it cuts the memory used by deep trees. This is only used for the
+//! sets that are private to the tracker, such as the paths created since it
+//! was clean: the dirty paths themselves are handed out by reference from
+//! `DirtyTracker::paths()`, so they have to be stored as full paths.
+
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+/// A set of paths, stored relative to a root.
+///
+/// Paths outside the root are stored as they are. The paths are ordered,
+/// so that those below a directory can be found without visiting the rest.
+#[derive(Debug)]
+pub(crate) struct RelativeSet {
+    root: PathBuf,
+    relpaths: BTreeSet<PathBuf>,
+}
+
+impl RelativeSet {
+    pub(crate) fn new(root: &Path) -> Self {
+        RelativeSet {
+            root: root.to_path_buf(),
+            relpaths: BTreeSet::new(),
+        }
+    }
+
+    fn key<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(&self.root).unwrap_or(path)
+    }
+
+    pub(crate) fn insert(&mut self, path: PathBuf) -> bool {
+        let key = match path.strip_prefix(&self.root) {
+            Ok(relpath) => relpath.to_path_buf(),
+            Err(_) => path,
+        };
+        self.relpaths.insert(key)
+    }
+
+    pub(crate) fn contains(&self, path: &Path) -> bool {
+        self.relpaths.contains(self.key(path))
+    }
+
+    pub(crate) fn remove(&mut self, path: &Path) -> bool {
+        let key = self.key(path).to_path_buf();
+        self.relpaths.remove(&key)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.relpaths.is_empty()
+    }
+
+    /// Remove all paths, and release the memory they used.
+    pub(crate) fn clear(&mut self) {
+        self.relpaths = BTreeSet::new();
+    }
+
+    fn full_path(&self, relpath: &Path) -> PathBuf {
+        if relpath.as_os_str().is_empty() {
+            self.root.clone()
+        } else {
+            self.root.join(relpath)
+        }
+    }
+
+    /// Returns the full paths in the set.
+    #[cfg(test)]
+    pub(crate) fn iter(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.relpaths
+            .iter()
+            .map(move |relpath| self.full_path(relpath))
+    }
+
+    /// Returns the full paths in the set that are below `dir`, not
+    /// including `dir` itself.
+    pub(crate) fn below<'a>(&'a self, dir: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+        let key = self.key(dir);
+        // Paths outside the root are stored absolute, and aren't below it.
+        let outside = key.as_os_str().is_empty();
+        self.relpaths
+            .range::<Path, _>((Bound::Excluded(key), Bound::Unbounded))
+            .take_while(move |relpath| relpath.starts_with(key))
+            .filter(move |relpath| !(outside && relpath.has_root()))
+            .map(move |relpath| self.full_path(relpath))
+    }
+
+    /// Move the paths below the root to below `to`.
+    pub(crate) fn relocate(&mut self, to: &Path) {
+        self.root = to.to_path_buf();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_set() {
+        let root = Path::new("/tree");
+        let mut set = RelativeSet::new(root);
+        assert!(set.insert(root.join("a/b")));
+        assert!(!set.insert(root.join("a/b")));
+        assert!(set.insert(root.to_path_buf()));
+        assert!(set.insert(PathBuf::from("/elsewhere")));
+        assert!(set.contains(&root.join("a/b")));
+        assert!(!set.contains(Path::new("a/b/c")));
+
+        let mut paths = set.iter().collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/elsewhere"),
+                root.to_path_buf(),
+                root.join("a/b")
+            ]
+        );
+
+        assert_eq!(set.below(root).collect::<Vec<_>>(), vec![root.join("a/b")]);
+        assert_eq!(
+            set.below(&root.join("a")).collect::<Vec<_>>(),
+            vec![root.join("a/b")]
+        );
+        assert_eq!(set.below(&root.join("a/b")).count(), 0);
+        assert_eq!(set.below(Path::new("/")).count(), 1);
+
+        set.relocate(Path::new("/moved"));
+        assert!(set.contains(Path::new("/moved/a/b")));
+        assert!(set.contains(Path::new("/elsewhere")));
+        assert!(set.remove(Path::new("/moved/a/b")));
+        assert!(!set.contains(Path::new("/moved/a/b")));
+    }
+}
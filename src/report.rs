@@ -0,0 +1,215 @@
+//! Human-readable summaries of the changes to a tree.
+
+use crate::ChangeKind;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The number of paths listed by default before the list is truncated.
+const DEFAULT_MAX_PATHS: usize = 20;
+
+/// A summary of the changes to a tree, as returned by
+/// `DirtyTracker::report()`.
+///
+/// The `Display` implementation formats the summary for logs or
+/// descriptions of a change: the number of changes by kind, the top-level
+/// directories affected, and the changed paths, truncated after
+/// `max_paths()` entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Report {
+    /// The number of paths that were created.
+    pub created: usize,
+    /// The number of paths that were modified.
+    pub modified: usize,
+    /// The number of paths that were removed.
+    pub removed: usize,
+    /// The number of paths that were renamed.
+    pub renamed: usize,
+    /// The top-level directories with changes below them, and how many.
+    pub dirs: BTreeMap<PathBuf, usize>,
+    /// The changed paths, relative to the tree and sorted. Renamed paths
+    /// are listed by their new path, as `ChangeKind::RenamedTo`.
+    pub paths: Vec<(PathBuf, ChangeKind)>,
+    /// Maps the new paths of renamed entries onto their original paths.
+    pub renames: BTreeMap<PathBuf, PathBuf>,
+    max_paths: usize,
+}
+
+impl Report {
+    /// Build a report from the changed paths, and the renames as pairs of
+    /// original and new paths.
+    ///
+    /// Paths are made relative to `base`; those outside it are kept as
+    /// they are, and don't count towards any directory.
+    pub(crate) fn new(
+        base: &Path,
+        changes: impl IntoIterator<Item = (PathBuf, ChangeKind)>,
+        renames: impl IntoIterator<Item = (PathBuf, PathBuf)>,
+    ) -> Self {
+        let relpath = |path: PathBuf| match path.strip_prefix(base) {
+            Ok(relpath) => relpath.to_path_buf(),
+            Err(_) => path,
+        };
+        let mut report = Report {
+            created: 0,
+            modified: 0,
+            removed: 0,
+            renamed: 0,
+            dirs: BTreeMap::new(),
+            paths: Vec::new(),
+            renames: BTreeMap::new(),
+            max_paths: DEFAULT_MAX_PATHS,
+        };
+        for (old, new) in renames {
+            let new = relpath(new);
+            report.paths.push((new.clone(), ChangeKind::RenamedTo));
+            report.renames.insert(new, relpath(old));
+        }
+        for (path, kind) in changes {
+            report.paths.push((relpath(path), kind));
+        }
+        report.paths.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, kind) in &report.paths {
+            match kind {
+                ChangeKind::Created => report.created += 1,
+                ChangeKind::Modified => report.modified += 1,
+                ChangeKind::Removed => report.removed += 1,
+                ChangeKind::RenamedFrom | ChangeKind::RenamedTo => report.renamed += 1,
+            }
+            if path.is_relative() && path.components().count() > 1 {
+                if let Some(dir) = path.components().next() {
+                    *report
+                        .dirs
+                        .entry(PathBuf::from(dir.as_os_str()))
+                        .or_insert(0) += 1;
+                }
+            }
+        }
+        report
+    }
+
+    /// Set the number of paths to list before the list is truncated.
+    pub fn max_paths(mut self, max_paths: usize) -> Self {
+        self.max_paths = max_paths;
+        self
+    }
+
+    /// Returns the total number of changed paths.
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// Returns true if there are no changes.
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        let counts = [
+            (self.created, "created"),
+            (self.modified, "modified"),
+            (self.removed, "removed"),
+            (self.renamed, "renamed"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, kind)| format!("{} {}", count, kind))
+        .collect::<Vec<_>>();
+        writeln!(
+            f,
+            "{} {} changed: {}",
+            self.len(),
+            if self.len() == 1 { "path" } else { "paths" },
+            counts.join(", ")
+        )?;
+        if !self.dirs.is_empty() {
+            let dirs = self
+                .dirs
+                .iter()
+                .map(|(dir, count)| format!("{} ({})", dir.display(), count))
+                .collect::<Vec<_>>();
+            writeln!(f, "Directories: {}", dirs.join(", "))?;
+        }
+        for (path, kind) in self.paths.iter().take(self.max_paths) {
+            match (kind, self.renames.get(path)) {
+                (ChangeKind::RenamedTo, Some(old)) => {
+                    writeln!(f, "  R {} -> {}", old.display(), path.display())?
+                }
+                (kind, _) => {
+                    let letter = match kind {
+                        ChangeKind::Created => 'A',
+                        ChangeKind::Modified => 'M',
+                        ChangeKind::Removed => 'D',
+                        ChangeKind::RenamedFrom | ChangeKind::RenamedTo => 'R',
+                    };
+                    writeln!(f, "  {} {}", letter, path.display())?
+                }
+            }
+        }
+        if self.len() > self.max_paths {
+            writeln!(f, "  ... and {} more", self.len() - self.max_paths)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty() {
+        let report = Report::new(Path::new("/tree"), vec![], vec![]);
+        assert!(report.is_empty());
+        assert_eq!(report.to_string(), "No changes\n");
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report::new(
+            Path::new("/tree"),
+            vec![
+                (PathBuf::from("/tree/src/lib.rs"), ChangeKind::Modified),
+                (PathBuf::from("/tree/README"), ChangeKind::Removed),
+                (PathBuf::from("/tree/src/new.rs"), ChangeKind::Created),
+                (PathBuf::from("/elsewhere/file"), ChangeKind::Modified),
+            ],
+            vec![(PathBuf::from("/tree/a"), PathBuf::from("/tree/docs/a"))],
+        );
+        assert_eq!(report.created, 1);
+        assert_eq!(report.modified, 2);
+        assert_eq!(report.removed, 1);
+        assert_eq!(report.renamed, 1);
+        assert_eq!(
+            report.dirs,
+            maplit::btreemap! {
+                PathBuf::from("docs") => 1,
+                PathBuf::from("src") => 2,
+            }
+        );
+        assert_eq!(
+            report.to_string(),
+            "5 paths changed: 1 created, 2 modified, 1 removed, 1 renamed\n\
+             Directories: docs (1), src (2)\n  \
+             M /elsewhere/file\n  \
+             D README\n  \
+             R a -> docs/a\n  \
+             M src/lib.rs\n  \
+             A src/new.rs\n"
+        );
+        assert_eq!(
+            report.max_paths(2).to_string(),
+            "5 paths changed: 1 created, 2 modified, 1 removed, 1 renamed\n\
+             Directories: docs (1), src (2)\n  \
+             M /elsewhere/file\n  \
+             D README\n  \
+             ... and 3 more\n"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Collapsing of very dirty directories into a single entry.
+//!
+//! Operations such as unpacking an archive or installing dependencies can
+//! touch hundreds of thousands of files. Once more than a given number of
+//! entries in a directory are dirty, the directory is recorded instead of
+//! its contents, and later changes below it are absorbed.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub(crate) struct Rollup {
+    /// The number of dirty entries a directory may have before it is
+    /// rolled up.
+    threshold: usize,
+    /// The number of dirty entries directly in each directory.
+    counts: HashMap<PathBuf, usize>,
+    /// The directories that were rolled up.
+    dirs: HashSet<PathBuf>,
+}
+
+impl Rollup {
+    pub(crate) fn new(threshold: usize) -> Self {
+        Rollup {
+            threshold,
+            counts: HashMap::new(),
+            dirs: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn dirs(&self) -> &HashSet<PathBuf> {
+        &self.dirs
+    }
+
+    /// Returns true if `path` is below a directory that was rolled up.
+    pub(crate) fn covers(&self, path: &Path) -> bool {
+        !self.dirs.is_empty() && path.ancestors().skip(1).any(|dir| self.dirs.contains(dir))
+    }
+
+    /// Record that `path` became dirty.
+    ///
+    /// Returns the directory `path` is in if it should now be rolled up.
+    pub(crate) fn added(&mut self, path: &Path) -> Option<PathBuf> {
+        let dir = path.parent()?;
+        let count = self.counts.entry(dir.to_path_buf()).or_insert(0);
+        *count += 1;
+        (*count > self.threshold).then(|| dir.to_path_buf())
+    }
+
+    /// Record that `path` is no longer dirty.
+    pub(crate) fn removed(&mut self, path: &Path) {
+        let dir = match path.parent() {
+            Some(dir) => dir,
+            None => return,
+        };
+        if let Some(count) = self.counts.get_mut(dir) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(dir);
+            }
+        }
+    }
+
+    /// Returns the directory with the most dirty entries, if any has more
+    /// than one.
+    pub(crate) fn busiest(&self) -> Option<PathBuf> {
+        self.counts
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .max_by_key(|(_, count)| **count)
+            .map(|(dir, _)| dir.clone())
+    }
+
+    /// Record that `dir` was rolled up, and its contents dropped from the
+    /// dirty set.
+    pub(crate) fn rolled_up(&mut self, dir: &Path) {
+        self.counts.retain(|path, _| !path.starts_with(dir));
+        self.dirs.retain(|path| !path.starts_with(dir));
+        self.dirs.insert(dir.to_path_buf());
+    }
+
+    /// Stop absorbing changes below `dir`, e.g. because it is clean again.
+    pub(crate) fn unroll(&mut self, dir: &Path) {
+        self.dirs.remove(dir);
+    }
+
+    /// Start over with the dirty paths in `paths`.
+    ///
+    /// Returns the directories that should be rolled up, deepest first.
+    pub(crate) fn recount<'a>(&mut self, paths: impl Iterator<Item = &'a PathBuf>) -> Vec<PathBuf> {
+        self.clear();
+        let mut exceeded = paths
+            .filter_map(|path| self.added(path))
+            .collect::<Vec<_>>();
+        exceeded.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        exceeded.dedup();
+        exceeded
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.counts.clear();
+        self.dirs.clear();
+    }
+
+    /// Update the paths after the tree was moved from `from` to `to`.
+    pub(crate) fn relocate(&mut self, from: &Path, to: &Path) {
+        let rebase = |path: &Path| crate::root::rebase(path, from, to);
+        self.counts = self.counts.drain().map(|(k, v)| (rebase(&k), v)).collect();
+        self.dirs = self.dirs.drain().map(|path| rebase(&path)).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollup() {
+        let mut rollup = Rollup::new(2);
+        let dir = Path::new("/tree/dir");
+        assert_eq!(rollup.added(&dir.join("a")), None);
+        assert_eq!(rollup.added(&dir.join("b")), None);
+        rollup.removed(&dir.join("b"));
+        assert_eq!(rollup.added(&dir.join("b")), None);
+        assert_eq!(rollup.added(&dir.join("c")), Some(dir.to_path_buf()));
+        rollup.rolled_up(dir);
+        assert!(rollup.covers(&dir.join("d")));
+        assert!(rollup.covers(&dir.join("sub/e")));
+        assert!(!rollup.covers(dir));
+        assert!(!rollup.covers(Path::new("/tree/other")));
+        assert_eq!(rollup.busiest(), None);
+        rollup.added(Path::new("/tree/other"));
+        rollup.added(dir);
+        assert_eq!(rollup.busiest(), Some(PathBuf::from("/tree")));
+        rollup.unroll(dir);
+        assert!(!rollup.covers(&dir.join("d")));
+    }
+}
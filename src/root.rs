@@ -0,0 +1,81 @@
+//! Tracking of the watched directory itself, as opposed to its contents.
+
+use crate::identity::{file_id, FileId};
+use std::path::{Path, PathBuf};
+
+/// Returns the new location of a directory that was moved away from `old`.
+///
+/// Only the parent of `old` is searched, which covers the common case of a
+/// directory being renamed in place.
+pub(crate) fn find_moved(old: &Path, id: FileId) -> Option<PathBuf> {
+    let parent = old.parent()?;
+    std::fs::read_dir(parent)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path != old && file_id(path) == Some(id))
+}
+
+/// Returns `path` as an absolute path, relative to the current directory
+/// if it isn't one already.
+///
+/// Unlike `canonicalize()`, this doesn't resolve symbolic links, or require
+/// `path` to exist.
+pub(crate) fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        // Collecting the components drops any `.` in between.
+        Ok(std::env::current_dir()?.join(path).components().collect())
+    }
+}
+
+/// Returns `path` with its `from` prefix replaced by `to`, or `path` itself
+/// if it is not below `from`.
+pub(crate) fn rebase(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(relpath) if relpath.as_os_str().is_empty() => to.to_path_buf(),
+        Ok(relpath) => to.join(relpath),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_moved() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old");
+        let new = dir.path().join("new");
+        std::fs::create_dir(&old).unwrap();
+        std::fs::create_dir(dir.path().join("other")).unwrap();
+        let id = file_id(&old).unwrap();
+        std::fs::rename(&old, &new).unwrap();
+        assert_eq!(find_moved(&old, id), Some(new.clone()));
+        std::fs::remove_dir(&new).unwrap();
+        assert_eq!(find_moved(&old, id), None);
+    }
+
+    #[test]
+    fn test_absolute() {
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(absolute(Path::new("src")).unwrap(), cwd.join("src"));
+        assert_eq!(absolute(Path::new(".")).unwrap(), cwd);
+        assert_eq!(absolute(Path::new("/tree")).unwrap(), Path::new("/tree"));
+    }
+
+    #[test]
+    fn test_rebase() {
+        let from = Path::new("/old");
+        let to = Path::new("/new");
+        assert_eq!(
+            rebase(Path::new("/old/a/b"), from, to),
+            Path::new("/new/a/b")
+        );
+        assert_eq!(rebase(Path::new("/old"), from, to), to);
+        assert_eq!(rebase(Path::new("/older"), from, to), Path::new("/older"));
+    }
+}
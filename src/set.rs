@@ -0,0 +1,182 @@
+//! Tracking several trees at once.
+
+use crate::{DirtyTracker, DirtyTrackerBuilder, DirtyTrackerError, State, WatcherPool};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// A set of trackers for separate trees, which share a watcher.
+///
+/// Each root has a tracker of its own, so it can be marked clean while the
+/// others keep their dirty sets. The state of the set as a whole is the
+/// worst state of its roots.
+///
+/// # Example
+/// ```rust
+/// use dirty_tracker::{DirtyTrackerSet, State};
+///
+/// let td = tempfile::tempdir().unwrap();
+/// let (a, b) = (td.path().join("a"), td.path().join("b"));
+/// std::fs::create_dir(&a).unwrap();
+/// std::fs::create_dir(&b).unwrap();
+/// let mut set = DirtyTrackerSet::new();
+/// set.add_root(&a).unwrap();
+/// set.add_root(&b).unwrap();
+/// std::fs::write(a.join("file"), b"hello").unwrap();
+/// std::fs::write(b.join("file"), b"hello").unwrap();
+/// assert_eq!(set.state(), State::Dirty);
+/// set.mark_root_clean(&a);
+/// assert_eq!(set.root_state(&a), Some(State::Clean));
+/// assert_eq!(set.root_state(&b), Some(State::Dirty));
+/// ```
+#[derive(Default)]
+pub struct DirtyTrackerSet {
+    pool: WatcherPool,
+    trackers: BTreeMap<PathBuf, DirtyTracker>,
+}
+
+impl DirtyTrackerSet {
+    /// Create an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking the tree below `path`, with the default options.
+    ///
+    /// Returns the tracker that was replaced, if `path` was already a root.
+    pub fn add_root(&mut self, path: &Path) -> Result<Option<DirtyTracker>, DirtyTrackerError> {
+        self.add(DirtyTracker::builder(path))
+    }
+
+    /// Start tracking a tree with the options of `builder`.
+    ///
+    /// Returns the tracker that was replaced, if its path was already a
+    /// root.
+    pub fn add(
+        &mut self,
+        builder: DirtyTrackerBuilder,
+    ) -> Result<Option<DirtyTracker>, DirtyTrackerError> {
+        let root = builder.path.clone();
+        let tracker = builder.build_in(&self.pool)?;
+        Ok(self.trackers.insert(root, tracker))
+    }
+
+    /// Stop tracking the tree below `root`, returning its tracker.
+    pub fn remove_root(&mut self, root: &Path) -> Option<DirtyTracker> {
+        self.trackers.remove(root)
+    }
+
+    /// Returns the roots, in order.
+    pub fn roots(&self) -> impl Iterator<Item = &Path> {
+        self.trackers.keys().map(PathBuf::as_path)
+    }
+
+    /// Returns the tracker for `root`.
+    pub fn get(&self, root: &Path) -> Option<&DirtyTracker> {
+        self.trackers.get(root)
+    }
+
+    /// Returns the tracker for `root`, e.g. to query it in more detail.
+    pub fn get_mut(&mut self, root: &Path) -> Option<&mut DirtyTracker> {
+        self.trackers.get_mut(root)
+    }
+
+    /// Returns the state of all roots together: `State::Unknown` if that is
+    /// the state of any root, otherwise `State::Dirty` if any root is dirty.
+    pub fn state(&mut self) -> State {
+        self.trackers.values_mut().map(DirtyTracker::state).fold(
+            State::Clean,
+            |state, root| match (state, root) {
+                (State::Unknown, _) | (_, State::Unknown) => State::Unknown,
+                (State::Dirty, _) | (_, State::Dirty) => State::Dirty,
+                _ => State::Clean,
+            },
+        )
+    }
+
+    /// Returns the state of the tree below `root`.
+    pub fn root_state(&mut self, root: &Path) -> Option<State> {
+        Some(self.trackers.get_mut(root)?.state())
+    }
+
+    /// Returns the state of each root.
+    pub fn states(&mut self) -> BTreeMap<&Path, State> {
+        self.trackers
+            .iter_mut()
+            .map(|(root, tracker)| (root.as_path(), tracker.state()))
+            .collect()
+    }
+
+    /// Returns the dirty paths of all roots, or `None` if the state of any
+    /// of them is unknown.
+    pub fn paths(&mut self) -> Option<HashSet<&Path>> {
+        let mut paths = HashSet::new();
+        for tracker in self.trackers.values_mut() {
+            paths.extend(tracker.paths()?.iter().map(PathBuf::as_path));
+        }
+        Some(paths)
+    }
+
+    /// Mark all roots as clean.
+    pub fn mark_clean(&mut self) {
+        for tracker in self.trackers.values_mut() {
+            tracker.mark_clean();
+        }
+    }
+
+    /// Mark the tree below `root` as clean, leaving the other roots alone.
+    ///
+    /// Returns false if `root` is not a root of the set.
+    pub fn mark_root_clean(&mut self, root: &Path) -> bool {
+        match self.trackers.get_mut(root) {
+            Some(tracker) => {
+                tracker.mark_clean();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        std::fs::create_dir(&a).unwrap();
+        std::fs::create_dir(&b).unwrap();
+        let mut set = DirtyTrackerSet::new();
+        assert!(set.add_root(&a).unwrap().is_none());
+        assert!(set.add_root(&b).unwrap().is_none());
+        assert_eq!(
+            set.roots().collect::<Vec<_>>(),
+            vec![a.as_path(), b.as_path()]
+        );
+        assert_eq!(set.state(), State::Clean);
+
+        let (a_file, b_file) = (a.join("file"), b.join("file"));
+        std::fs::write(&a_file, b"hello").unwrap();
+        std::fs::write(&b_file, b"hello").unwrap();
+        assert_eq!(
+            set.paths().unwrap(),
+            maplit::hashset![a_file.as_path(), b_file.as_path()]
+        );
+        assert!(set.mark_root_clean(&a));
+        assert!(!set.mark_root_clean(&dir.path().join("c")));
+        assert_eq!(
+            set.states(),
+            maplit::btreemap![a.as_path() => State::Clean, b.as_path() => State::Dirty]
+        );
+        assert_eq!(set.state(), State::Dirty);
+        assert_eq!(set.root_state(&dir.path().join("c")), None);
+
+        assert!(set.remove_root(&b).is_some());
+        assert_eq!(set.state(), State::Clean);
+        std::fs::write(a.join("other"), b"hello").unwrap();
+        set.mark_clean();
+        assert_eq!(set.state(), State::Clean);
+    }
+}
@@ -0,0 +1,283 @@
+//! Shadow copies of a tree, used to restore the original contents of files.
+
+use std::path::{Path, PathBuf};
+
+/// Copy `src` to `dst`, preserving its modification time.
+///
+/// Preserving the modification time means that restored files also match
+/// the baseline used by `DirtyTracker::rescan()`. Where the file system
+/// supports it, `std::fs::copy()` clones the file rather than copying its
+/// contents; unlike a hard link, the clone is unaffected by later writes
+/// to the original.
+fn copy_file(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::fs::copy(src, dst)?;
+    let mtime = std::fs::metadata(src)?.modified()?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(dst)?
+        .set_modified(mtime)
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(src)?, dst)
+}
+
+#[cfg(windows)]
+fn copy_symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(src)?;
+    if std::fs::metadata(src).map(|m| m.is_dir()).unwrap_or(false) {
+        std::os::windows::fs::symlink_dir(target, dst)
+    } else {
+        std::os::windows::fs::symlink_file(target, dst)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn copy_symlink(_src: &Path, _dst: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+/// Returns the contents of `path`, or None if it is not a regular file.
+pub(crate) fn read_file(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(Some(std::fs::read(path)?)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove whatever is at `path`, if anything.
+fn remove(path: &Path) -> std::io::Result<()> {
+    let result = match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path),
+        Ok(_) => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Where shadow copies are stored.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ShadowOptions {
+    /// The directory to store shadow copies in; defaults to the system
+    /// temporary directory.
+    pub(crate) dir: Option<PathBuf>,
+}
+
+/// Make `dst` match `src`, replacing whatever is at `dst`.
+///
+/// Paths that don't exist at `src` are removed. Directories are created,
+/// but their contents are left alone.
+fn sync(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(src) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return remove(dst),
+        Err(e) => return Err(e),
+    };
+    if metadata.is_dir() {
+        match std::fs::symlink_metadata(dst) {
+            Ok(current) if current.is_dir() => return Ok(()),
+            Ok(_) => remove(dst)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        return std::fs::create_dir_all(dst);
+    }
+    remove(dst)?;
+    if let Some(parent) = dst.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if metadata.file_type().is_symlink() {
+        copy_symlink(src, dst)
+    } else {
+        copy_file(src, dst)
+    }
+}
+
+/// A copy of a tree, as it was when the copy was made.
+///
+/// The copy is removed when this object is dropped.
+pub(crate) struct Shadow {
+    root: PathBuf,
+    dir: tempfile::TempDir,
+}
+
+impl Shadow {
+    /// Copy the tree below `root` into a new shadow directory.
+    pub(crate) fn capture(root: &Path, options: &ShadowOptions) -> std::io::Result<Self> {
+        let shadow = Shadow::empty(root, options)?;
+        let mut error = None;
+        crate::snapshot::walk(root, &mut |path, metadata| {
+            let dst = shadow.dir.path().join(path.strip_prefix(root).unwrap());
+            let result = if metadata.is_dir() {
+                std::fs::create_dir(&dst)
+            } else if metadata.file_type().is_symlink() {
+                copy_symlink(&path, &dst)
+            } else {
+                copy_file(&path, &dst)
+            };
+            match result {
+                // The entry disappeared after it was listed.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+                Ok(()) => {}
+            }
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(shadow),
+        }
+    }
+
+    /// Create an empty shadow directory, for a tree that doesn't exist yet.
+    pub(crate) fn empty(root: &Path, options: &ShadowOptions) -> std::io::Result<Self> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix("dirty-tracker-");
+        let dir = match options.dir.as_ref() {
+            Some(dir) => builder.tempdir_in(dir)?,
+            None => builder.tempdir()?,
+        };
+        Ok(Shadow {
+            root: root.to_path_buf(),
+            dir,
+        })
+    }
+
+    /// Update the shadow copy after the tree was moved to `root`.
+    pub(crate) fn relocate(&mut self, root: &Path) {
+        self.root = root.to_path_buf();
+    }
+
+    /// Returns the location of the shadow copy of `path`.
+    fn original_path(&self, path: &Path) -> std::io::Result<PathBuf> {
+        let relpath = path.strip_prefix(&self.root).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not below {}", path.display(), self.root.display()),
+            )
+        })?;
+        Ok(self.dir.path().join(relpath))
+    }
+
+    /// Returns the original contents of `path`, or None if it was not a
+    /// regular file when the shadow was captured.
+    pub(crate) fn original_contents(&self, path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+        read_file(&self.original_path(path)?)
+    }
+
+    /// Restore `path` to the state it was in when the shadow was captured.
+    ///
+    /// Paths that did not exist at the time are removed. Directories are
+    /// recreated, but their contents are left alone.
+    pub(crate) fn restore(&self, path: &Path) -> std::io::Result<()> {
+        sync(&self.original_path(path)?, path)
+    }
+
+    /// Update the copy of `path` to its current state, e.g. after it
+    /// changed and the tree was marked clean.
+    ///
+    /// Paths outside the tree are ignored.
+    pub(crate) fn update(&self, path: &Path) -> std::io::Result<()> {
+        match self.original_path(path) {
+            Ok(original) => sync(path, &original),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let subdir = dir.path().join("subdir");
+        let file = subdir.join("file");
+        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(&file, b"hello").unwrap();
+
+        let shadow = Shadow::capture(dir.path(), &ShadowOptions::default()).unwrap();
+
+        let added = dir.path().join("added");
+        std::fs::write(&added, b"new").unwrap();
+        std::fs::remove_dir_all(&subdir).unwrap();
+
+        shadow.restore(&added).unwrap();
+        shadow.restore(&subdir).unwrap();
+        shadow.restore(&file).unwrap();
+
+        assert!(!added.exists());
+        assert_eq!(std::fs::read(&file).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        let removed = dir.path().join("removed");
+        std::fs::write(&file, b"hello").unwrap();
+        std::fs::write(&removed, b"gone").unwrap();
+
+        let shadow = Shadow::capture(dir.path(), &ShadowOptions::default()).unwrap();
+
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(subdir.join("added"), b"new").unwrap();
+        std::fs::write(&file, b"world").unwrap();
+        std::fs::remove_file(&removed).unwrap();
+        for path in [&subdir, &subdir.join("added"), &file, &removed] {
+            shadow.update(path).unwrap();
+        }
+        shadow.update(Path::new("/elsewhere")).unwrap();
+
+        std::fs::write(&file, b"again").unwrap();
+        std::fs::write(&removed, b"back").unwrap();
+        std::fs::remove_dir_all(&subdir).unwrap();
+        for path in [&subdir, &subdir.join("added"), &file, &removed] {
+            shadow.restore(path).unwrap();
+        }
+        assert_eq!(std::fs::read(&file).unwrap(), b"world");
+        assert_eq!(std::fs::read(subdir.join("added")).unwrap(), b"new");
+        assert!(!removed.exists());
+    }
+
+    #[test]
+    fn test_backup_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let backups = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let options = ShadowOptions {
+            dir: Some(backups.path().to_path_buf()),
+        };
+        let shadow = Shadow::capture(dir.path(), &options).unwrap();
+        assert_eq!(std::fs::read_dir(backups.path()).unwrap().count(), 1);
+
+        // The copy is unaffected by changes made in place.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .write_all(b"world")
+            .unwrap();
+
+        shadow.restore(&file).unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"hello");
+
+        std::mem::drop(shadow);
+        assert_eq!(std::fs::read_dir(backups.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,464 @@
+//! Snapshots of a tree, used to recover from missed events and to filter out
+//! spurious ones.
+
+use crate::FileType;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter::FromIterator;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Metadata recorded for a single entry in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    /// Directories are only tracked for existence; their mtime changes
+    /// whenever an entry is added or removed, which is already covered by the
+    /// entries themselves.
+    Dir,
+    /// Regular files, symlinks and special files.
+    File {
+        file_type: FileType,
+        size: u64,
+        mtime: Option<SystemTime>,
+    },
+}
+
+impl Entry {
+    fn from_metadata(metadata: &std::fs::Metadata) -> Self {
+        if metadata.is_dir() {
+            Entry::Dir
+        } else {
+            Entry::File {
+                file_type: FileType::from(metadata.file_type()),
+                size: metadata.len(),
+                mtime: metadata.modified().ok(),
+            }
+        }
+    }
+}
+
+/// The size and modification time of every entry below a directory.
+///
+/// This is what `DirtyTracker::rescan()` compares against by default, but
+/// it can also be used on its own to cheaply find out what changed in a tree
+/// between two points in time.
+///
+/// # Example
+/// ```rust
+/// use dirty_tracker::TreeSnapshot;
+///
+/// let td = tempfile::tempdir().unwrap();
+/// let snapshot = TreeSnapshot::capture(td.path()).unwrap();
+///
+/// std::fs::write(td.path().join("file"), b"hello").unwrap();
+///
+/// assert_eq!(
+///     snapshot.dirty_against(td.path()).unwrap(),
+///     maplit::hashset![td.path().join("file")]
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TreeSnapshot {
+    /// Ordered by path, so that the entries below a directory are adjacent.
+    entries: BTreeMap<PathBuf, Entry>,
+}
+
+/// Walk the tree below `root`, calling `f` for every entry (but not `root`
+/// itself).
+///
+/// Symlinks are not followed. Entries that disappear while the walk is in
+/// progress, and the contents of directories that can't be read, are
+/// skipped; only errors for `root` itself are returned.
+pub(crate) fn walk(
+    root: &Path,
+    f: &mut dyn FnMut(PathBuf, &std::fs::Metadata),
+) -> std::io::Result<()> {
+    let skipped = |e: &std::io::Error| {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+        )
+    };
+    let mut todo = vec![root.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if skipped(&e) && dir != root => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) if skipped(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            let path = entry.path();
+            let metadata = match std::fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) if skipped(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            if metadata.is_dir() {
+                todo.push(path.clone());
+            }
+            f(path, &metadata);
+        }
+    }
+    Ok(())
+}
+
+impl TreeSnapshot {
+    /// Record the size and modification time of every entry below `root`.
+    ///
+    /// Symlinks are recorded as links, rather than followed.
+    pub fn capture(root: &Path) -> std::io::Result<Self> {
+        let mut entries = BTreeMap::new();
+        walk(root, &mut |path, metadata| {
+            entries.insert(path, Entry::from_metadata(metadata));
+        })?;
+        Ok(TreeSnapshot { entries })
+    }
+
+    /// Returns the paths below `root` that differ from this snapshot.
+    ///
+    /// This includes paths that have been added or removed since the
+    /// snapshot was taken. Directories are only compared by existence.
+    pub fn dirty_against(&self, root: &Path) -> std::io::Result<HashSet<PathBuf>> {
+        let mut dirty = HashSet::new();
+        let mut seen = HashSet::new();
+        walk(root, &mut |path, metadata| {
+            if self.entries.get(&path) != Some(&Entry::from_metadata(metadata)) {
+                dirty.insert(path.clone());
+            }
+            seen.insert(path);
+        })?;
+        for path in self.entries.keys() {
+            if !seen.contains(path) {
+                dirty.insert(path.clone());
+            }
+        }
+        Ok(dirty)
+    }
+}
+
+impl crate::Baseline for TreeSnapshot {
+    fn dirty_paths(&self, root: &Path) -> std::io::Result<HashSet<PathBuf>> {
+        self.dirty_against(root)
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn reset(&mut self, root: &Path) -> std::io::Result<()> {
+        *self = TreeSnapshot::capture(root)?;
+        Ok(())
+    }
+
+    fn file_type(&self, path: &Path) -> Option<FileType> {
+        match self.entries.get(path)? {
+            Entry::Dir => Some(FileType::Dir),
+            Entry::File { file_type, .. } => Some(*file_type),
+        }
+    }
+
+    fn entries_below(&self, dir: &Path) -> Vec<PathBuf> {
+        if self.entries.get(dir) != Some(&Entry::Dir) {
+            return Vec::new();
+        }
+        self.entries
+            .range::<Path, _>((Bound::Excluded(dir), Bound::Unbounded))
+            .map(|(path, _)| path)
+            .take_while(|path| path.starts_with(dir))
+            .cloned()
+            .collect()
+    }
+
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        self.entries = relocate_entries(std::mem::take(&mut self.entries), from, to);
+    }
+}
+
+/// The size and modification time of a fixed set of files, for trackers
+/// built with `DirtyTrackerBuilder::for_files()`.
+///
+/// Unlike a `TreeSnapshot`, this never walks the tree.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FileSnapshot {
+    files: Vec<PathBuf>,
+    /// The entries of the files that existed when it was captured.
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl FileSnapshot {
+    pub(crate) fn capture(files: impl IntoIterator<Item = PathBuf>) -> Self {
+        let files = files.into_iter().collect::<Vec<_>>();
+        let entries = files
+            .iter()
+            .filter_map(|path| {
+                let metadata = std::fs::symlink_metadata(path).ok()?;
+                Some((path.clone(), Entry::from_metadata(&metadata)))
+            })
+            .collect();
+        FileSnapshot { files, entries }
+    }
+}
+
+impl crate::Baseline for FileSnapshot {
+    fn dirty_paths(&self, _root: &Path) -> std::io::Result<HashSet<PathBuf>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|path| {
+                let entry = std::fs::symlink_metadata(path)
+                    .ok()
+                    .map(|metadata| Entry::from_metadata(&metadata));
+                entry.as_ref() != self.entries.get(*path)
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    fn reset(&mut self, _root: &Path) -> std::io::Result<()> {
+        *self = FileSnapshot::capture(std::mem::take(&mut self.files));
+        Ok(())
+    }
+
+    fn file_type(&self, path: &Path) -> Option<FileType> {
+        match self.entries.get(path)? {
+            Entry::Dir => Some(FileType::Dir),
+            Entry::File { file_type, .. } => Some(*file_type),
+        }
+    }
+
+    fn relocate(&mut self, from: &Path, to: &Path) {
+        for path in self.files.iter_mut() {
+            *path = crate::root::rebase(path, from, to);
+        }
+        self.entries = relocate_entries(std::mem::take(&mut self.entries), from, to);
+    }
+}
+
+/// Content recorded for a single entry in the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Content {
+    Dir,
+    File(u64),
+    Symlink(PathBuf),
+}
+
+impl Content {
+    fn read(path: &Path, metadata: &std::fs::Metadata) -> std::io::Result<Self> {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            Ok(Content::Dir)
+        } else if file_type.is_symlink() {
+            Ok(Content::Symlink(std::fs::read_link(path)?))
+        } else {
+            Ok(Content::File(hash_file(path)?))
+        }
+    }
+}
+
+/// Hash the contents of a file.
+///
+/// This is only used to detect changes within a single process, so the
+/// standard library hasher is sufficient.
+fn hash_file(path: &Path) -> std::io::Result<u64> {
+    use std::hash::Hasher;
+    use std::io::Read;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut f = std::fs::File::open(path)?;
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Hashes of the contents of every entry below a directory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContentSnapshot {
+    entries: HashMap<PathBuf, Content>,
+}
+
+impl ContentSnapshot {
+    /// Record the contents of every entry below `root`.
+    pub(crate) fn capture(root: &Path) -> std::io::Result<Self> {
+        let mut entries = HashMap::new();
+        let mut error = None;
+        walk(root, &mut |path, metadata| {
+            match Content::read(&path, metadata) {
+                Ok(content) => {
+                    entries.insert(path, content);
+                }
+                // The entry disappeared after it was listed.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        })?;
+        match error {
+            Some(e) => Err(e),
+            None => Ok(ContentSnapshot { entries }),
+        }
+    }
+
+    /// Returns true if `path` has the same contents as when the snapshot was
+    /// taken, or if it neither existed then nor exists now.
+    pub(crate) fn unchanged(&self, path: &Path) -> std::io::Result<bool> {
+        let current = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => Some(Content::read(path, &metadata)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(self.entries.get(path) == current.as_ref())
+    }
+
+    /// Update the snapshot after the tree was moved from `from` to `to`.
+    pub(crate) fn relocate(&mut self, from: &Path, to: &Path) {
+        self.entries = relocate_entries(std::mem::take(&mut self.entries), from, to);
+    }
+}
+
+fn relocate_entries<T, C>(entries: C, from: &Path, to: &Path) -> C
+where
+    C: IntoIterator<Item = (PathBuf, T)> + FromIterator<(PathBuf, T)>,
+{
+    entries
+        .into_iter()
+        .map(|(path, entry)| (crate::root::rebase(&path, from, to), entry))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("file"), b"hello").unwrap();
+        std::fs::create_dir(dir.path().join("subdir")).unwrap();
+
+        let snapshot = TreeSnapshot::capture(dir.path()).unwrap();
+        assert!(snapshot.dirty_against(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_entries_below() {
+        use crate::Baseline;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::write(dir.path().join("a/b/file"), b"hello").unwrap();
+        std::fs::write(dir.path().join("a-file"), b"hello").unwrap();
+        std::fs::write(dir.path().join("ab"), b"hello").unwrap();
+
+        let snapshot = TreeSnapshot::capture(dir.path()).unwrap();
+        assert_eq!(
+            snapshot.entries_below(&dir.path().join("a")),
+            vec![dir.path().join("a/b"), dir.path().join("a/b/file")]
+        );
+        assert!(snapshot.entries_below(&dir.path().join("ab")).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable() {
+        use crate::Baseline;
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let subdir = dir.path().join("subdir");
+        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(subdir.join("file"), b"hello").unwrap();
+        std::fs::write(dir.path().join("file"), b"hello").unwrap();
+        std::fs::set_permissions(&subdir, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        let snapshot = TreeSnapshot::capture(dir.path());
+        std::fs::set_permissions(&subdir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let snapshot = snapshot.unwrap();
+        assert!(snapshot.contains(&dir.path().join("file")));
+        assert!(snapshot.contains(&subdir));
+    }
+
+    #[test]
+    fn test_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let modified = dir.path().join("modified");
+        let removed = dir.path().join("removed");
+        std::fs::write(&modified, b"hello").unwrap();
+        std::fs::write(&removed, b"hello").unwrap();
+
+        let snapshot = TreeSnapshot::capture(dir.path()).unwrap();
+
+        let subdir = dir.path().join("subdir");
+        let added = subdir.join("added");
+        std::fs::write(&modified, b"hello world").unwrap();
+        std::fs::remove_file(&removed).unwrap();
+        std::fs::create_dir(&subdir).unwrap();
+        std::fs::write(&added, b"hello").unwrap();
+
+        assert_eq!(
+            snapshot.dirty_against(dir.path()).unwrap(),
+            maplit::hashset![modified.clone(), removed, subdir.clone(), added.clone()]
+        );
+        assert!(snapshot.entries.contains_key(&modified));
+        assert!(!snapshot.entries.contains_key(&added));
+    }
+
+    #[test]
+    fn test_content_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let snapshot = ContentSnapshot::capture(dir.path()).unwrap();
+        assert!(snapshot.unchanged(&file).unwrap());
+
+        std::fs::write(&file, b"world").unwrap();
+        assert!(!snapshot.unchanged(&file).unwrap());
+
+        std::fs::write(&file, b"hello").unwrap();
+        assert!(snapshot.unchanged(&file).unwrap());
+
+        std::fs::remove_file(&file).unwrap();
+        assert!(!snapshot.unchanged(&file).unwrap());
+
+        assert!(snapshot.unchanged(&dir.path().join("missing")).unwrap());
+    }
+
+    #[test]
+    fn test_file_snapshot() {
+        use crate::Baseline;
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        std::fs::write(&a, b"a").unwrap();
+        let mut snapshot = FileSnapshot::capture(vec![a.clone(), b.clone()]);
+        assert!(snapshot.contains(&a) && !snapshot.contains(&b));
+        std::fs::write(dir.path().join("other"), b"other").unwrap();
+        assert!(snapshot.dirty_paths(dir.path()).unwrap().is_empty());
+
+        std::fs::write(&a, b"longer").unwrap();
+        std::fs::write(&b, b"b").unwrap();
+        assert_eq!(
+            snapshot.dirty_paths(dir.path()).unwrap(),
+            maplit::hashset![a.clone(), b.clone()]
+        );
+        snapshot.reset(dir.path()).unwrap();
+        assert!(snapshot.dirty_paths(dir.path()).unwrap().is_empty());
+        std::fs::remove_file(&b).unwrap();
+        assert_eq!(
+            snapshot.dirty_paths(dir.path()).unwrap(),
+            maplit::hashset![b]
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Metrics for trackers, reported through the `metrics` crate if the
+//! `metrics` feature is enabled.
+//!
+//! The following are registered for every tracker, labelled with the root
+//! of the tree:
+//!
+//! * `dirty_tracker_dirty_paths`: gauge of the number of dirty paths
+//! * `dirty_tracker_events_total`: counter of events received
+//! * `dirty_tracker_rescans_total`: counter of rescans
+//! * `dirty_tracker_unknown_transitions_total`: counter of transitions to
+//!   the `State::Unknown` state
+
+use crate::State;
+use std::path::Path;
+
+#[cfg(feature = "metrics")]
+pub(crate) struct Metrics {
+    dirty_paths: metrics::Gauge,
+    events: metrics::Counter,
+    rescans: metrics::Counter,
+    unknown_transitions: metrics::Counter,
+    last_state: Option<State>,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub(crate) fn new(root: &Path) -> Self {
+        let root = root.display().to_string();
+        Metrics {
+            dirty_paths: metrics::gauge!("dirty_tracker_dirty_paths", "root" => root.clone()),
+            events: metrics::counter!("dirty_tracker_events_total", "root" => root.clone()),
+            rescans: metrics::counter!("dirty_tracker_rescans_total", "root" => root.clone()),
+            unknown_transitions: metrics::counter!(
+                "dirty_tracker_unknown_transitions_total",
+                "root" => root
+            ),
+            last_state: None,
+        }
+    }
+
+    pub(crate) fn event(&mut self) {
+        self.events.increment(1);
+    }
+
+    pub(crate) fn rescan(&mut self) {
+        self.rescans.increment(1);
+    }
+
+    pub(crate) fn state(&mut self, state: State, dirty_paths: usize) {
+        self.dirty_paths.set(dirty_paths as f64);
+        if state == State::Unknown && self.last_state != Some(State::Unknown) {
+            self.unknown_transitions.increment(1);
+        }
+        self.last_state = Some(state);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct Metrics;
+
+#[cfg(not(feature = "metrics"))]
+impl Metrics {
+    pub(crate) fn new(_root: &Path) -> Self {
+        Metrics
+    }
+
+    pub(crate) fn event(&mut self) {}
+
+    pub(crate) fn rescan(&mut self) {}
+
+    pub(crate) fn state(&mut self, _state: State, _dirty_paths: usize) {}
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Value(AtomicU64);
+
+    impl metrics::CounterFn for Value {
+        fn increment(&self, value: u64) {
+            self.0.fetch_add(value, Ordering::SeqCst);
+        }
+
+        fn absolute(&self, value: u64) {
+            self.0.store(value, Ordering::SeqCst);
+        }
+    }
+
+    impl metrics::GaugeFn for Value {
+        fn increment(&self, _value: f64) {}
+
+        fn decrement(&self, _value: f64) {}
+
+        fn set(&self, value: f64) {
+            self.0.store(value as u64, Ordering::SeqCst);
+        }
+    }
+
+    #[derive(Default)]
+    struct TestRecorder(Mutex<Vec<(String, Arc<Value>)>>);
+
+    impl TestRecorder {
+        fn value(&self, name: &str) -> u64 {
+            let values = self.0.lock().unwrap();
+            let (_, value) = values.iter().find(|(n, _)| n == name).unwrap();
+            value.0.load(Ordering::SeqCst)
+        }
+
+        fn register(&self, key: &Key) -> Arc<Value> {
+            let value = Arc::new(Value::default());
+            self.0
+                .lock()
+                .unwrap()
+                .push((key.name().to_string(), value.clone()));
+            value
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.register(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.register(key))
+        }
+
+        fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let recorder = TestRecorder::default();
+        let mut metrics =
+            metrics::with_local_recorder(&recorder, || Metrics::new(Path::new("/root")));
+
+        metrics.event();
+        metrics.event();
+        metrics.rescan();
+        metrics.state(State::Dirty, 3);
+        metrics.state(State::Unknown, 3);
+        metrics.state(State::Unknown, 3);
+        metrics.state(State::Clean, 0);
+        metrics.state(State::Unknown, 0);
+
+        assert_eq!(recorder.value("dirty_tracker_events_total"), 2);
+        assert_eq!(recorder.value("dirty_tracker_rescans_total"), 1);
+        assert_eq!(recorder.value("dirty_tracker_dirty_paths"), 0);
+        assert_eq!(recorder.value("dirty_tracker_unknown_transitions_total"), 2);
+    }
+}
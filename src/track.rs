@@ -0,0 +1,168 @@
+//! Helpers for finding out what a single operation changed.
+
+use crate::{DirtyTracker, DirtyTrackerError};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// Run `f` and return the paths below `path` that it changed.
+///
+/// This sets up a tracker before running `f`, and makes sure all events
+/// have been processed before returning. Changes made by other processes
+/// while `f` runs are included as well.
+///
+/// Returns `DirtyTrackerError::Unknown` if the tracker could not determine
+/// which paths were changed, e.g. because the watcher dropped events.
+///
+/// # Example
+/// ```rust
+/// let td = tempfile::tempdir().unwrap();
+///
+/// let paths = dirty_tracker::track_changes(td.path(), || {
+///     std::fs::write(td.path().join("file"), b"hello").unwrap();
+/// })
+/// .unwrap();
+///
+/// assert_eq!(paths, maplit::hashset![td.path().join("file")]);
+/// ```
+pub fn track_changes<F: FnOnce()>(
+    path: &Path,
+    f: F,
+) -> Result<HashSet<PathBuf>, DirtyTrackerError> {
+    let mut tracker = DirtyTracker::builder(path).without_baseline().build()?;
+    f();
+    Ok(tracker.try_paths()?.clone())
+}
+
+/// Run `command` to completion and return the paths below `path` that were
+/// changed while it ran, along with its exit status.
+///
+/// The command's standard streams are inherited, as with
+/// `std::process::Command::status()`. Failing to start the command is
+/// reported as `DirtyTrackerError::Io`; a command that runs but fails is not
+/// an error, so check the returned exit status.
+pub fn track_command(
+    path: &Path,
+    command: &mut Command,
+) -> Result<(ExitStatus, HashSet<PathBuf>), DirtyTrackerError> {
+    let mut tracker = DirtyTracker::builder(path).without_baseline().build()?;
+    let status = command.status().map_err(DirtyTrackerError::Io)?;
+    Ok((status, tracker.try_paths()?.clone()))
+}
+
+/// Await `fut` and return the paths below `path` that changed while it ran.
+///
+/// This is the async equivalent of `track_changes()`. Setting up the tracker
+/// and waiting for pending events block, so they are run on tokio's blocking
+/// thread pool.
+///
+/// # Example
+/// ```rust
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let td = tempfile::tempdir().unwrap();
+/// let file = td.path().join("file");
+///
+/// let paths = dirty_tracker::track_changes_async(td.path(), async {
+///     tokio::task::spawn_blocking({
+///         let file = file.clone();
+///         move || std::fs::write(file, b"hello").unwrap()
+///     })
+///     .await
+///     .unwrap();
+/// })
+/// .await
+/// .unwrap();
+///
+/// assert_eq!(paths, maplit::hashset![file]);
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+pub async fn track_changes_async<F: std::future::Future>(
+    path: &Path,
+    fut: F,
+) -> Result<HashSet<PathBuf>, DirtyTrackerError> {
+    let path = path.to_path_buf();
+    let mut tracker =
+        spawn_blocking(move || DirtyTracker::builder(&path).without_baseline().build()).await?;
+    fut.await;
+    spawn_blocking(move || Ok(tracker.try_paths()?.clone())).await
+}
+
+#[cfg(feature = "tokio")]
+async fn spawn_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(ret) => ret,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"hello").unwrap();
+
+        let paths = track_changes(dir.path(), || {
+            std::fs::write(&file, b"world").unwrap();
+            std::fs::write(dir.path().join("tmp"), b"tmp").unwrap();
+            std::fs::remove_file(dir.path().join("tmp")).unwrap();
+        })
+        .unwrap();
+        assert_eq!(paths, maplit::hashset![file]);
+    }
+
+    #[test]
+    fn test_track_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(track_changes(dir.path(), || {}).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_track_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, paths) = track_command(
+            dir.path(),
+            Command::new("sh")
+                .arg("-c")
+                .arg("echo hello > file; exit 3")
+                .current_dir(dir.path()),
+        )
+        .unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(paths, maplit::hashset![dir.path().join("file")]);
+    }
+
+    #[test]
+    fn test_track_command_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            track_command(dir.path(), &mut Command::new("/nonexistent/command")),
+            Err(DirtyTrackerError::Io(_))
+        ));
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_track_changes_async() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = track_changes_async(dir.path(), async {
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg("echo hello > file")
+                .current_dir(dir.path())
+                .status()
+                .await
+                .unwrap();
+            assert!(status.success());
+        })
+        .await
+        .unwrap();
+        assert_eq!(paths, maplit::hashset![dir.path().join("file")]);
+    }
+}
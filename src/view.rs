@@ -0,0 +1,342 @@
+//! Several trackers for one watcher.
+//!
+//! A tracker built with `DirtyTrackerBuilder::allow_views()` passes a copy
+//! of every event from its watcher on to its views, which are trackers in
+//! their own right, with their own dirty sets. Trackers in a `WatcherPool`
+//! share a watcher in the same way. This saves watches, which are a limited
+//! resource with some backends.
+
+use crate::backend::{EventSender, WatcherBackend};
+use crate::FlushStrategy;
+use notify::{RecursiveMode, WatcherKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The senders for the events of the views of a tracker.
+pub(crate) type Views = Arc<Mutex<Vec<EventSender>>>;
+
+/// A path watched on behalf of one or more trackers.
+#[derive(Debug)]
+struct Root {
+    path: PathBuf,
+    recursive_mode: RecursiveMode,
+    /// The number of trackers that asked for it.
+    count: usize,
+    /// Whether the watcher watches it, rather than it being covered by an
+    /// active root.
+    active: bool,
+}
+
+/// The watcher, and the paths it watches on behalf of trackers.
+struct Watcher {
+    backend: Box<dyn WatcherBackend>,
+    roots: Vec<Root>,
+}
+
+impl Watcher {
+    fn covers(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| {
+            root.active
+                && root.recursive_mode == RecursiveMode::Recursive
+                && path.starts_with(&root.path)
+        })
+    }
+
+    fn add(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        if let Some(root) = self
+            .roots
+            .iter_mut()
+            .find(|root| root.path == path && root.recursive_mode == recursive_mode)
+        {
+            root.count += 1;
+            return Ok(());
+        }
+        let active = !self.covers(path);
+        if active {
+            self.activate(path, recursive_mode)?;
+        }
+        self.roots.push(Root {
+            path: path.to_path_buf(),
+            recursive_mode,
+            count: 1,
+            active,
+        });
+        Ok(())
+    }
+
+    /// Watch `path`, taking over from the roots below it if it is watched
+    /// recursively.
+    fn activate(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        let covered = self
+            .roots
+            .iter_mut()
+            .filter(|root| {
+                root.active
+                    && recursive_mode == RecursiveMode::Recursive
+                    && root.path.starts_with(path)
+            })
+            .collect::<Vec<_>>();
+        // Some backends map a directory onto a single watch, so the watches
+        // of the roots have to go before the one that covers them is added;
+        // removing them afterwards would remove part of the new watch.
+        for root in covered.iter() {
+            let _ = self.backend.remove_watch(&root.path);
+        }
+        let result = self.backend.add_watch(path, recursive_mode);
+        for root in covered {
+            root.active = result.is_err()
+                && self
+                    .backend
+                    .add_watch(&root.path, root.recursive_mode)
+                    .is_ok();
+        }
+        result
+    }
+
+    fn release(&mut self, path: &Path) -> notify::Result<()> {
+        let i = match self.roots.iter().position(|root| root.path == path) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        self.roots[i].count -= 1;
+        if self.roots[i].count > 0 {
+            return Ok(());
+        }
+        let root = self.roots.remove(i);
+        if !root.active {
+            return Ok(());
+        }
+        let result = self.backend.remove_watch(path);
+        // Watch the roots that were covered by it in their own right.
+        for i in 0..self.roots.len() {
+            if !self.roots[i].active && !self.covers(&self.roots[i].path) {
+                let root = &mut self.roots[i];
+                root.active = self
+                    .backend
+                    .add_watch(&root.path, root.recursive_mode)
+                    .is_ok();
+            }
+        }
+        result
+    }
+}
+
+/// A watcher shared between trackers.
+pub(crate) struct Shared {
+    watcher: Mutex<Watcher>,
+    kind: WatcherKind,
+    pub(crate) views: Views,
+}
+
+impl Shared {
+    pub(crate) fn new(backend: Box<dyn WatcherBackend>, views: Views) -> Arc<Self> {
+        Arc::new(Shared {
+            kind: backend.backend_kind(),
+            watcher: Mutex::new(Watcher {
+                backend,
+                roots: Vec::new(),
+            }),
+            views,
+        })
+    }
+}
+
+/// The backend of a tracker that shares its watcher.
+///
+/// The watches it adds are released when it is dropped, but only removed
+/// from the watcher once no other tracker needs them.
+pub(crate) struct SharedWatcher {
+    shared: Arc<Shared>,
+    paths: Vec<PathBuf>,
+}
+
+impl SharedWatcher {
+    pub(crate) fn new(shared: Arc<Shared>) -> Self {
+        SharedWatcher {
+            shared,
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl WatcherBackend for SharedWatcher {
+    fn add_watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+        self.shared
+            .watcher
+            .lock()
+            .unwrap()
+            .add(path, recursive_mode)?;
+        self.paths.push(path.to_path_buf());
+        Ok(())
+    }
+
+    fn remove_watch(&mut self, path: &Path) -> notify::Result<()> {
+        let i = match self.paths.iter().position(|p| p == path) {
+            Some(i) => i,
+            None => return Err(notify::Error::watch_not_found()),
+        };
+        self.paths.remove(i);
+        self.shared.watcher.lock().unwrap().release(path)
+    }
+
+    fn backend_kind(&self) -> WatcherKind {
+        self.shared.kind
+    }
+
+    fn default_flush(&self) -> Box<dyn FlushStrategy> {
+        self.shared.watcher.lock().unwrap().backend.default_flush()
+    }
+}
+
+impl Drop for SharedWatcher {
+    fn drop(&mut self) {
+        // The watcher may well be going away too.
+        if let Ok(mut watcher) = self.shared.watcher.lock() {
+            for path in self.paths.drain(..) {
+                let _ = watcher.release(&path);
+            }
+        }
+    }
+}
+
+/// Start a thread that passes copies of the events sent on the returned
+/// sender on to `views`.
+///
+/// The thread ends once the sender is dropped.
+pub(crate) fn fan_out(views: Views) -> std::io::Result<EventSender> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::Builder::new()
+        .name("dirty-tracker-views".to_string())
+        .spawn(move || {
+            for result in rx {
+                // Drop views that have gone away.
+                views
+                    .lock()
+                    .unwrap()
+                    .retain(|tx| tx.send(copy(&result)).is_ok());
+            }
+        })?;
+    Ok(tx)
+}
+
+/// Returns a copy of `result`, for passing it on to views.
+pub(crate) fn copy(result: &notify::Result<notify::Event>) -> notify::Result<notify::Event> {
+    use notify::ErrorKind;
+    let e = match result {
+        Ok(event) => return Ok(event.clone()),
+        Err(e) => e,
+    };
+    let kind = match &e.kind {
+        ErrorKind::Generic(message) => ErrorKind::Generic(message.clone()),
+        ErrorKind::Io(e) => ErrorKind::Io(std::io::Error::new(e.kind(), e.to_string())),
+        ErrorKind::PathNotFound => ErrorKind::PathNotFound,
+        ErrorKind::WatchNotFound => ErrorKind::WatchNotFound,
+        ErrorKind::InvalidConfig(config) => ErrorKind::InvalidConfig(*config),
+        ErrorKind::MaxFilesWatch => ErrorKind::MaxFilesWatch,
+    };
+    Err(notify::Error::new(kind).set_paths(e.paths.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the watches added and removed.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl WatcherBackend for Recorder {
+        fn add_watch(&mut self, path: &Path, _: RecursiveMode) -> notify::Result<()> {
+            self.0.lock().unwrap().push(format!("+{}", path.display()));
+            Ok(())
+        }
+
+        fn remove_watch(&mut self, path: &Path) -> notify::Result<()> {
+            self.0.lock().unwrap().push(format!("-{}", path.display()));
+            Ok(())
+        }
+
+        fn backend_kind(&self) -> WatcherKind {
+            WatcherKind::NullWatcher
+        }
+    }
+
+    #[test]
+    fn test_shared_watcher() {
+        let recorder = Recorder::default();
+        let shared = Shared::new(Box::new(recorder.clone()), Views::default());
+        let mut outer = SharedWatcher::new(shared.clone());
+        let mut inner = SharedWatcher::new(shared.clone());
+        let mut again = SharedWatcher::new(shared.clone());
+        let log = || std::mem::take(&mut *recorder.0.lock().unwrap());
+        outer
+            .add_watch(Path::new("/a"), RecursiveMode::Recursive)
+            .unwrap();
+        inner
+            .add_watch(Path::new("/a/b"), RecursiveMode::Recursive)
+            .unwrap();
+        again
+            .add_watch(Path::new("/a"), RecursiveMode::Recursive)
+            .unwrap();
+        assert_eq!(log(), vec!["+/a"]);
+        assert_eq!(again.backend_kind(), WatcherKind::NullWatcher);
+
+        drop(outer);
+        assert!(log().is_empty());
+        again.remove_watch(Path::new("/a")).unwrap();
+        assert!(again.remove_watch(Path::new("/a")).is_err());
+        assert_eq!(log(), vec!["-/a", "+/a/b"]);
+        drop(inner);
+        assert_eq!(log(), vec!["-/a/b"]);
+    }
+
+    #[test]
+    fn test_outer_added_later() {
+        let recorder = Recorder::default();
+        let shared = Shared::new(Box::new(recorder.clone()), Views::default());
+        let mut inner = SharedWatcher::new(shared.clone());
+        let mut outer = SharedWatcher::new(shared.clone());
+        let log = || std::mem::take(&mut *recorder.0.lock().unwrap());
+        inner
+            .add_watch(Path::new("/a/b"), RecursiveMode::Recursive)
+            .unwrap();
+        outer
+            .add_watch(Path::new("/a/c"), RecursiveMode::NonRecursive)
+            .unwrap();
+        outer
+            .add_watch(Path::new("/a"), RecursiveMode::Recursive)
+            .unwrap();
+        assert_eq!(log(), vec!["+/a/b", "+/a/c", "-/a/b", "-/a/c", "+/a"]);
+        drop(outer);
+        assert_eq!(log(), vec!["-/a", "+/a/b"]);
+    }
+
+    #[test]
+    fn test_fan_out() {
+        let views = Views::default();
+        let (first, first_rx) = std::sync::mpsc::channel();
+        let (second, second_rx) = std::sync::mpsc::channel();
+        views.lock().unwrap().extend([first, second]);
+        let tx = fan_out(views.clone()).unwrap();
+        let event = notify::Event::new(notify::EventKind::Any);
+        drop(second_rx);
+        tx.send(Ok(event.clone())).unwrap();
+        assert_eq!(first_rx.recv().unwrap().unwrap(), event);
+        drop(tx);
+        // Wait for the thread to end.
+        while Arc::strong_count(&views) > 1 {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(views.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_copy() {
+        let event = notify::Event::new(notify::EventKind::Any).add_path("a".into());
+        assert_eq!(copy(&Ok(event.clone())).unwrap(), event);
+        let e = notify::Error::path_not_found().add_path("a".into());
+        let copied = copy(&Err(e)).unwrap_err();
+        assert!(matches!(copied.kind, notify::ErrorKind::PathNotFound));
+        assert_eq!(copied.paths, vec![PathBuf::from("a")]);
+    }
+}
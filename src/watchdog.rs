@@ -0,0 +1,144 @@
+//! Periodic checks that the watcher is still delivering events.
+
+use crate::{DirtyTracker, DirtyTrackerError, UnknownReason};
+use notify::{EventKind, RecursiveMode, WatcherKind};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+/// Checks, at most once per interval, that the watcher still reports
+/// changes to a private probe directory.
+pub(crate) struct Watchdog {
+    interval: Duration,
+    timeout: Duration,
+    last: Instant,
+    /// The directory probe files are created in, once it is being watched.
+    dir: Option<tempfile::TempDir>,
+}
+
+impl Watchdog {
+    pub(crate) fn new(interval: Duration, timeout: Duration) -> Self {
+        Watchdog {
+            interval,
+            timeout,
+            last: Instant::now(),
+            dir: None,
+        }
+    }
+
+    /// Check the watcher of `tracker`, if a check is due.
+    ///
+    /// Events that arrive in the meantime are processed as usual. If the
+    /// probe isn't reported within the timeout, the tracker is flagged as
+    /// unknown.
+    pub(crate) fn check(&mut self, tracker: &mut DirtyTracker) -> Result<(), DirtyTrackerError> {
+        if self.last.elapsed() < self.interval {
+            return Ok(());
+        }
+        // Polling watchers won't notice a short-lived probe, and the null
+        // watcher never reports anything by itself.
+        if matches!(
+            tracker.watcher.backend_kind(),
+            WatcherKind::PollWatcher | WatcherKind::NullWatcher
+        ) {
+            return Ok(());
+        }
+        let dir = match self.dir.as_ref() {
+            Some(dir) => dir,
+            None => {
+                let dir = tempfile::Builder::new()
+                    .prefix("dirty-tracker-")
+                    .tempdir()
+                    .map_err(DirtyTrackerError::Io)?;
+                tracker
+                    .watcher
+                    .add_watch(dir.path(), RecursiveMode::NonRecursive)
+                    .map_err(DirtyTrackerError::Watcher)?;
+                let _ = tracker.rx.normalizer.add_root(dir.path());
+                tracker.ignored_dirs.push(dir.path().to_path_buf());
+                self.dir.insert(dir)
+            }
+        };
+        let probe = tempfile::NamedTempFile::new_in(dir.path()).map_err(DirtyTrackerError::Io)?;
+        let probe_path = probe.path().to_path_buf();
+        probe.close().map_err(DirtyTrackerError::Io)?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match tracker.rx.recv_timeout(remaining) {
+                Ok(Ok(event)) => {
+                    let done = matches!(event.kind, EventKind::Remove(_))
+                        && event.paths.contains(&probe_path);
+                    tracker.process_pending_event(event);
+                    if done {
+                        break;
+                    }
+                }
+                Ok(Err(e)) => tracker.process_watcher_error(e),
+                Err(RecvTimeoutError::Timeout) => {
+                    log_warn!(
+                        "Watcher for {} did not respond within {:?}",
+                        tracker.path.display(),
+                        self.timeout
+                    );
+                    tracker
+                        .unknown
+                        .get_or_insert(UnknownReason::Unresponsive(self.timeout));
+                    tracker.publish_state();
+                    break;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(tracker.disconnected()),
+            }
+        }
+        self.last = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DirtyTracker, EventSender, NoFlush, State, UnknownReason, WatcherBackend};
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// A backend that claims to be inotify, but never reports anything.
+    struct Deaf(#[allow(dead_code)] EventSender);
+
+    impl WatcherBackend for Deaf {
+        fn add_watch(&mut self, _path: &Path, _mode: notify::RecursiveMode) -> notify::Result<()> {
+            Ok(())
+        }
+
+        fn remove_watch(&mut self, _path: &Path) -> notify::Result<()> {
+            Ok(())
+        }
+
+        fn backend_kind(&self) -> notify::WatcherKind {
+            notify::WatcherKind::Inotify
+        }
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .watchdog(Duration::ZERO, Duration::from_secs(10))
+            .build()
+            .unwrap();
+        assert_eq!(tracker.state(), State::Clean);
+        assert_eq!(tracker.state(), State::Clean);
+
+        let mut tracker = DirtyTracker::builder(dir.path())
+            .flush_strategy(NoFlush)
+            .watchdog(Duration::ZERO, Duration::from_millis(10))
+            .build_with_backend(|tx| Ok(Deaf(tx)))
+            .unwrap();
+        assert_eq!(
+            tracker.state_detail(),
+            (
+                State::Unknown,
+                Some(UnknownReason::Unresponsive(Duration::from_millis(10)))
+            )
+        );
+    }
+}